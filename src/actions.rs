@@ -0,0 +1,557 @@
+//! Mapping of keys and mouse buttons to the actions they perform.
+//!
+//! Bindings are loaded from a plain text config file, with one binding per line. Lines starting with `#` are
+//! comments. An action can be bound more than once, and any action that appears in the file loses its
+//! default bindings.
+//!
+//! ```text
+//! # <action> <binding>
+//! MoveForward W
+//! MoveForward Up
+//! # Modifiers are put in front of the key, joined with '+'
+//! Pause Shift+P
+//! # Mouse buttons are 'Mouse Left', 'Mouse Middle', 'Mouse Right', 'Mouse X1' or 'Mouse X2'
+//! FireBluePortal Mouse Left
+//! # Game controller buttons use SDL's names, e.g. 'Pad a', 'Pad start' or 'Pad lefttrigger'
+//! Jump Pad a
+//! # 'none' removes all bindings of the action
+//! ToggleNoclip none
+//! ```
+//!
+//! Keys use SDL's scancode names, e.g. `Left Shift`, `Space` or `F7`. A binding with modifiers takes over from
+//! bindings of the same key with fewer modifiers, so with `Screenshot Ctrl+S` pressing Ctrl+S doesn't also move back.
+use prelude::*;
+
+use input::KeyInput;
+
+use sdl2::controller::{self, Axis};
+use sdl2::keyboard::{self, Mod, Scancode};
+use sdl2::mouse::Mouse;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Something that the player can do.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+	MoveForward,
+	MoveBack,
+	MoveLeft,
+	MoveRight,
+	/// Flies up while in noclip.
+	MoveUp,
+	/// Flies down while in noclip.
+	MoveDown,
+	Jump,
+	Sprint,
+	/// Moves slowly.
+	Crouch,
+	ToggleNoclip,
+	FireBluePortal,
+	FireOrangePortal,
+	RotatePortalUp,
+	RotatePortalDown,
+	RotatePortalLeft,
+	RotatePortalRight,
+	ToggleWireframe,
+	TogglePortalRendering,
+	ToggleProjection,
+	CyclePortalFallback,
+	DecreasePortalDepth,
+	IncreasePortalDepth,
+	/// Saves the next frame to the screenshots directory.
+	Screenshot,
+	Pause,
+}
+
+/// Every action, in the order they are listed in.
+pub const ACTIONS: &'static [Action] = &[
+	Action::MoveForward, Action::MoveBack, Action::MoveLeft, Action::MoveRight, Action::MoveUp, Action::MoveDown,
+	Action::Jump, Action::Sprint, Action::Crouch, Action::ToggleNoclip, Action::FireBluePortal, Action::FireOrangePortal,
+	Action::RotatePortalUp, Action::RotatePortalDown, Action::RotatePortalLeft, Action::RotatePortalRight,
+	Action::ToggleWireframe, Action::TogglePortalRendering, Action::ToggleProjection, Action::CyclePortalFallback,
+	Action::DecreasePortalDepth, Action::IncreasePortalDepth, Action::Screenshot, Action::Pause,
+];
+
+impl Action {
+	pub fn from_name(name: &str) -> Option<Action> {
+		ACTIONS.iter().cloned().find(|a| a.name() == name)
+	}
+	
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Action::MoveForward => "MoveForward",
+			Action::MoveBack => "MoveBack",
+			Action::MoveLeft => "MoveLeft",
+			Action::MoveRight => "MoveRight",
+			Action::MoveUp => "MoveUp",
+			Action::MoveDown => "MoveDown",
+			Action::Jump => "Jump",
+			Action::Sprint => "Sprint",
+			Action::Crouch => "Crouch",
+			Action::ToggleNoclip => "ToggleNoclip",
+			Action::FireBluePortal => "FireBluePortal",
+			Action::FireOrangePortal => "FireOrangePortal",
+			Action::RotatePortalUp => "RotatePortalUp",
+			Action::RotatePortalDown => "RotatePortalDown",
+			Action::RotatePortalLeft => "RotatePortalLeft",
+			Action::RotatePortalRight => "RotatePortalRight",
+			Action::ToggleWireframe => "ToggleWireframe",
+			Action::TogglePortalRendering => "TogglePortalRendering",
+			Action::ToggleProjection => "ToggleProjection",
+			Action::CyclePortalFallback => "CyclePortalFallback",
+			Action::DecreasePortalDepth => "DecreasePortalDepth",
+			Action::IncreasePortalDepth => "IncreasePortalDepth",
+			Action::Screenshot => "Screenshot",
+			Action::Pause => "Pause",
+		}
+	}
+}
+
+/// Something that can be asked which actions are currently being held.
+pub trait ActionInput {
+	fn is_action_held(&self, action: Action) -> bool;
+	/// Gets the analog movement, e.g. from a controller stick. x is right and y is forward, from -1 to 1.
+	fn get_move_axis(&self) -> Vec2;
+}
+
+/// A set of actions that are held down.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeldActions {
+	actions: Vec<Action>,
+	move_axis: Vec2,
+}
+impl HeldActions {
+	pub fn new() -> HeldActions {
+		HeldActions {
+			actions: Vec::new(),
+			move_axis: Vec2::new(0.0, 0.0),
+		}
+	}
+	
+	pub fn set_move_axis(&mut self, axis: Vec2) {
+		self.move_axis = axis;
+	}
+	
+	pub fn press(&mut self, action: Action) {
+		if !self.actions.contains(&action) {
+			self.actions.push(action);
+		}
+	}
+	
+	pub fn release(&mut self, action: Action) {
+		self.actions.retain(|&a| a != action);
+	}
+	
+	pub fn get_actions(&self) -> &[Action] {
+		&self.actions
+	}
+}
+impl ActionInput for HeldActions {
+	fn is_action_held(&self, action: Action) -> bool {
+		self.actions.contains(&action)
+	}
+	fn get_move_axis(&self) -> Vec2 {
+		self.move_axis
+	}
+}
+
+/// Modifier keys that have to be held down for a binding to count.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Modifiers {
+	pub ctrl: bool,
+	pub shift: bool,
+	pub alt: bool,
+}
+impl Modifiers {
+	pub fn none() -> Modifiers {
+		Modifiers { ctrl: false, shift: false, alt: false }
+	}
+	
+	/// Gets the modifiers that are held down in a key event.
+	pub fn from_mod(keymod: Mod) -> Modifiers {
+		Modifiers {
+			ctrl: keymod.intersects(keyboard::LCTRLMOD | keyboard::RCTRLMOD),
+			shift: keymod.intersects(keyboard::LSHIFTMOD | keyboard::RSHIFTMOD),
+			alt: keymod.intersects(keyboard::LALTMOD | keyboard::RALTMOD),
+		}
+	}
+	
+	/// Gets the modifiers that are held down in `keys`.
+	pub fn from_keys<K: KeyInput>(keys: &K) -> Modifiers {
+		Modifiers {
+			ctrl: keys.is_scancode_pressed(Scancode::LCtrl) || keys.is_scancode_pressed(Scancode::RCtrl),
+			shift: keys.is_scancode_pressed(Scancode::LShift) || keys.is_scancode_pressed(Scancode::RShift),
+			alt: keys.is_scancode_pressed(Scancode::LAlt) || keys.is_scancode_pressed(Scancode::RAlt),
+		}
+	}
+	
+	/// Returns true if every modifier in `self` is also held in `held`. Extra modifiers in `held` are allowed.
+	pub fn is_satisfied_by(&self, held: Modifiers) -> bool {
+		(!self.ctrl || held.ctrl) && (!self.shift || held.shift) && (!self.alt || held.alt)
+	}
+}
+
+/// A key, mouse button or game controller button.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Button {
+	Key(Scancode),
+	Mouse(Mouse),
+	Pad(controller::Button),
+	/// A game controller trigger, which counts as pressed once it is pulled far enough.
+	Trigger(Axis),
+}
+
+/// A button, and the modifiers that must be held with it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Binding {
+	pub button: Button,
+	pub mods: Modifiers,
+}
+impl Binding {
+	pub fn key(key: Scancode) -> Binding {
+		Binding { button: Button::Key(key), mods: Modifiers::none() }
+	}
+	
+	pub fn mouse(btn: Mouse) -> Binding {
+		Binding { button: Button::Mouse(btn), mods: Modifiers::none() }
+	}
+	
+	pub fn pad(btn: controller::Button) -> Binding {
+		Binding { button: Button::Pad(btn), mods: Modifiers::none() }
+	}
+	
+	pub fn trigger(axis: Axis) -> Binding {
+		Binding { button: Button::Trigger(axis), mods: Modifiers::none() }
+	}
+	
+	/// Parses a binding such as `W`, `Ctrl+Shift+S` or `Mouse Left`.
+	pub fn parse(s: &str) -> Result<Binding, String> {
+		let mut rest = s.trim();
+		let mut mods = Modifiers::none();
+		loop {
+			if rest.starts_with("Ctrl+") {
+				mods.ctrl = true;
+			} else if rest.starts_with("Shift+") {
+				mods.shift = true;
+			} else if rest.starts_with("Alt+") {
+				mods.alt = true;
+			} else {
+				break;
+			}
+			rest = &rest[rest.find('+').unwrap() + 1..];
+		}
+		
+		let button = if rest.starts_with("Mouse ") {
+			Button::Mouse(match rest["Mouse ".len()..].trim() {
+				"Left" => Mouse::Left,
+				"Middle" => Mouse::Middle,
+				"Right" => Mouse::Right,
+				"X1" => Mouse::X1,
+				"X2" => Mouse::X2,
+				name => return Err(format!("unknown mouse button '{}'", name)),
+			})
+		} else if rest.starts_with("Pad ") {
+			let name = rest["Pad ".len()..].trim();
+			match (controller::Button::from_string(name), Axis::from_string(name)) {
+				(Some(btn), _) => Button::Pad(btn),
+				(None, Some(axis @ Axis::TriggerLeft)) | (None, Some(axis @ Axis::TriggerRight)) => Button::Trigger(axis),
+				_ => return Err(format!("unknown controller button '{}'", name)),
+			}
+		} else {
+			match Scancode::from_name(rest) {
+				Some(key) => Button::Key(key),
+				None => return Err(format!("unknown key '{}'", rest)),
+			}
+		};
+		Ok(Binding { button: button, mods: mods })
+	}
+}
+
+/// The bindings of every action.
+#[derive(Clone, Debug)]
+pub struct ActionMap {
+	bindings: Vec<(Action, Binding)>,
+}
+impl ActionMap {
+	/// Creates a map with no bindings at all.
+	pub fn empty() -> ActionMap {
+		ActionMap {
+			bindings: Vec::new(),
+		}
+	}
+	
+	/// Creates a map with the default controls.
+	pub fn new() -> ActionMap {
+		let mut map = ActionMap::empty();
+		let keys = [
+			(Action::MoveForward, Scancode::W),
+			(Action::MoveBack, Scancode::S),
+			(Action::MoveLeft, Scancode::A),
+			(Action::MoveRight, Scancode::D),
+			(Action::MoveUp, Scancode::Q),
+			(Action::MoveDown, Scancode::E),
+			(Action::Jump, Scancode::Space),
+			(Action::Sprint, Scancode::LShift),
+			(Action::Sprint, Scancode::RShift),
+			(Action::Crouch, Scancode::LCtrl),
+			(Action::Crouch, Scancode::RCtrl),
+			(Action::ToggleNoclip, Scancode::V),
+			(Action::RotatePortalUp, Scancode::I),
+			(Action::RotatePortalDown, Scancode::K),
+			(Action::RotatePortalLeft, Scancode::J),
+			(Action::RotatePortalRight, Scancode::L),
+			(Action::ToggleProjection, Scancode::F5),
+			(Action::CyclePortalFallback, Scancode::F6),
+			(Action::ToggleWireframe, Scancode::F7),
+			(Action::TogglePortalRendering, Scancode::F8),
+			(Action::DecreasePortalDepth, Scancode::F9),
+			(Action::IncreasePortalDepth, Scancode::F10),
+			(Action::Screenshot, Scancode::F12),
+			(Action::Pause, Scancode::Escape),
+		];
+		for &(action, key) in keys.iter() {
+			map.bind(action, Binding::key(key));
+		}
+		map.bind(Action::FireBluePortal, Binding::mouse(Mouse::Left));
+		map.bind(Action::FireOrangePortal, Binding::mouse(Mouse::Right));
+		
+		let pad = [
+			(Action::Jump, controller::Button::A),
+			(Action::Crouch, controller::Button::B),
+			(Action::Sprint, controller::Button::LeftStick),
+			(Action::ToggleNoclip, controller::Button::Y),
+			(Action::Pause, controller::Button::Start),
+		];
+		for &(action, btn) in pad.iter() {
+			map.bind(action, Binding::pad(btn));
+		}
+		map.bind(Action::FireBluePortal, Binding::trigger(Axis::TriggerLeft));
+		map.bind(Action::FireOrangePortal, Binding::trigger(Axis::TriggerRight));
+		map
+	}
+	
+	/// Adds a binding to `action`, on top of any it already has.
+	pub fn bind(&mut self, action: Action, binding: Binding) {
+		if !self.bindings.contains(&(action, binding)) {
+			self.bindings.push((action, binding));
+		}
+	}
+	
+	/// Removes all bindings of `action`.
+	pub fn unbind(&mut self, action: Action) {
+		self.bindings.retain(|&(a, _)| a != action);
+	}
+	
+	pub fn get_bindings(&self, action: Action) -> Vec<Binding> {
+		self.bindings.iter().filter(|&&(a, _)| a == action).map(|&(_, b)| b).collect()
+	}
+	
+	/// Gets the actions that are held down, given the keys that are held down.
+	pub fn get_held_actions<K: KeyInput>(&self, keys: &K) -> HeldActions {
+		let mods = Modifiers::from_keys(keys);
+		let mut held = HeldActions::new();
+		for &(action, binding) in self.bindings.iter() {
+			if let Button::Key(key) = binding.button {
+				if keys.is_scancode_pressed(key) && self.is_active(binding, mods) {
+					held.press(action);
+				}
+			}
+		}
+		held
+	}
+	
+	/// Adds the actions bound to the held game controller buttons in `buttons` to `held`.
+	pub fn add_held_buttons(&self, buttons: &[Button], held: &mut HeldActions) {
+		for &(action, binding) in self.bindings.iter() {
+			if binding.mods == Modifiers::none() && buttons.contains(&binding.button) {
+				held.press(action);
+			}
+		}
+	}
+	
+	/// Gets the actions triggered by pressing `button` with the modifiers `mods` held.
+	pub fn get_triggered_actions(&self, button: Button, mods: Modifiers) -> Vec<Action> {
+		self.bindings.iter()
+			.filter(|&&(_, b)| b.button == button && self.is_active(b, mods))
+			.map(|&(a, _)| a)
+			.collect()
+	}
+	
+	/// Returns true if `binding` counts with the modifiers `mods` held. It doesn't if another binding of the same
+	/// button needs more of the held modifiers.
+	fn is_active(&self, binding: Binding, mods: Modifiers) -> bool {
+		binding.mods.is_satisfied_by(mods) && !self.bindings.iter().any(|&(_, b)| {
+			b.button == binding.button && b.mods != binding.mods && b.mods.is_satisfied_by(mods) && binding.mods.is_satisfied_by(b.mods)
+		})
+	}
+	
+	/// Loads bindings from a config file on top of the default controls.
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<ActionMap, String> {
+		let path = path.as_ref();
+		let mut file = match File::open(path) {
+			Ok(f) => f,
+			Err(e) => return Err(format!("error opening controls '{}': {}", path.display(), e)),
+		};
+		let mut src = String::new();
+		match file.read_to_string(&mut src) {
+			Ok(_) => {},
+			Err(e) => return Err(format!("error reading controls '{}': {}", path.display(), e)),
+		}
+		let mut map = ActionMap::new();
+		match map.parse(&src) {
+			Ok(()) => Ok(map),
+			Err(e) => Err(format!("error loading controls '{}': {}", path.display(), e)),
+		}
+	}
+	
+	/// Applies the bindings in a config file to the map. Actions in the file lose their existing bindings.
+	pub fn parse(&mut self, src: &str) -> Result<(), String> {
+		let mut rebound = Vec::new();
+		for (i, line) in src.lines().enumerate() {
+			let line = line.trim();
+			// Key names can contain '#', so only whole lines can be comments
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let (name, rest) = match line.find(char::is_whitespace) {
+				Some(end) => (&line[..end], line[end..].trim()),
+				None => return Err(format!("line {}: expected a binding after '{}'", i + 1, line)),
+			};
+			let action = match Action::from_name(name) {
+				Some(action) => action,
+				None => return Err(format!("line {}: unknown action '{}'", i + 1, name)),
+			};
+			if !rebound.contains(&action) {
+				self.unbind(action);
+				rebound.push(action);
+			}
+			if rest == "none" {
+				continue;
+			}
+			match Binding::parse(rest) {
+				Ok(binding) => self.bind(action, binding),
+				Err(e) => return Err(format!("line {}: {}", i + 1, e)),
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use input::HeldKeys;
+	use sdl2::controller::{self, Axis};
+	use sdl2::keyboard::Scancode;
+	use sdl2::mouse::Mouse;
+	
+	const CONFIG: &'static str = "\
+# Arrows as well as WASD
+MoveForward W
+MoveForward Up
+  # indented comment
+
+MoveBack Down
+Screenshot Ctrl+S
+Pause Ctrl+Shift+Q
+FireBluePortal Mouse Left
+Jump Pad a
+FireOrangePortal Pad righttrigger
+ToggleNoclip none
+";
+	
+	#[test]
+	fn parse_bindings() {
+		assert_eq!(Binding::parse("W"), Ok(Binding::key(Scancode::W)));
+		assert_eq!(Binding::parse(" Left Shift "), Ok(Binding::key(Scancode::LShift)));
+		assert_eq!(Binding::parse("Mouse X1"), Ok(Binding::mouse(Mouse::X1)));
+		assert_eq!(Binding::parse("Pad start"), Ok(Binding::pad(controller::Button::Start)));
+		assert_eq!(Binding::parse("Pad lefttrigger"), Ok(Binding::trigger(Axis::TriggerLeft)));
+		assert_eq!(Binding::parse("Ctrl+Shift+F7"), Ok(Binding {
+			button: Button::Key(Scancode::F7),
+			mods: Modifiers { ctrl: true, shift: true, alt: false },
+		}));
+		assert_eq!(Binding::parse("Alt+Mouse Right"), Ok(Binding {
+			button: Button::Mouse(Mouse::Right),
+			mods: Modifiers { ctrl: false, shift: false, alt: true },
+		}));
+		
+		assert_eq!(Binding::parse("Blah"), Err(String::from("unknown key 'Blah'")));
+		assert_eq!(Binding::parse("Ctrl+"), Err(String::from("unknown key ''")));
+		assert_eq!(Binding::parse("Mouse Wheel"), Err(String::from("unknown mouse button 'Wheel'")));
+		// Sticks are axes but not triggers, so they can't be pressed
+		assert_eq!(Binding::parse("Pad leftx"), Err(String::from("unknown controller button 'leftx'")));
+	}
+	
+	#[test]
+	fn parse_config() {
+		let mut map = ActionMap::new();
+		map.parse(CONFIG).unwrap();
+		assert_eq!(map.get_bindings(Action::MoveForward), vec![Binding::key(Scancode::W), Binding::key(Scancode::Up)]);
+		assert_eq!(map.get_bindings(Action::MoveBack), vec![Binding::key(Scancode::Down)]);
+		assert_eq!(map.get_bindings(Action::Screenshot), vec![Binding {
+			button: Button::Key(Scancode::S),
+			mods: Modifiers { ctrl: true, shift: false, alt: false },
+		}]);
+		assert_eq!(map.get_bindings(Action::Pause), vec![Binding {
+			button: Button::Key(Scancode::Q),
+			mods: Modifiers { ctrl: true, shift: true, alt: false },
+		}]);
+		assert_eq!(map.get_bindings(Action::FireBluePortal), vec![Binding::mouse(Mouse::Left)]);
+		assert_eq!(map.get_bindings(Action::Jump), vec![Binding::pad(controller::Button::A)]);
+		assert_eq!(map.get_bindings(Action::FireOrangePortal), vec![Binding::trigger(Axis::TriggerRight)]);
+		assert!(map.get_bindings(Action::ToggleNoclip).is_empty());
+		// Actions that aren't in the file keep their defaults
+		assert_eq!(map.get_bindings(Action::MoveLeft), ActionMap::new().get_bindings(Action::MoveLeft));
+	}
+	
+	#[test]
+	fn parse_errors() {
+		for &(src, message) in [
+			("MoveForward W\nFly Space\n", "line 2: unknown action 'Fly'"),
+			("\n# comment\nJump Blah\n", "line 3: unknown key 'Blah'"),
+			("Jump Ctrl+Mouse Wheel\n", "line 1: unknown mouse button 'Wheel'"),
+			("Jump Pad nothing\n", "line 1: unknown controller button 'nothing'"),
+			("MoveForward W\nJump\n", "line 2: expected a binding after 'Jump'"),
+		].iter() {
+			assert!(ActionMap::new().parse(src) == Err(String::from(message)), "{:?}", src);
+		}
+	}
+	
+	#[test]
+	fn modifiers_take_over() {
+		let mut map = ActionMap::new();
+		map.parse(CONFIG).unwrap();
+		let none = Modifiers::none();
+		let ctrl = Modifiers { ctrl: true, shift: false, alt: false };
+		let ctrl_shift = Modifiers { ctrl: true, shift: true, alt: false };
+		
+		let s = Button::Key(Scancode::S);
+		assert_eq!(map.get_triggered_actions(s, none), vec![Action::MoveBack]);
+		assert_eq!(map.get_triggered_actions(s, ctrl), vec![Action::Screenshot]);
+		// Extra modifiers are allowed as long as nothing needs them
+		assert_eq!(map.get_triggered_actions(s, ctrl_shift), vec![Action::Screenshot]);
+		assert!(map.get_triggered_actions(Button::Key(Scancode::Q), ctrl).is_empty());
+		assert_eq!(map.get_triggered_actions(Button::Key(Scancode::Q), ctrl_shift), vec![Action::Pause]);
+		
+		let mut keys = HeldKeys::new();
+		keys.press(Scancode::S);
+		assert_eq!(map.get_held_actions(&keys).get_actions(), &[Action::MoveBack]);
+		keys.press(Scancode::LCtrl);
+		let held = map.get_held_actions(&keys);
+		assert!(held.is_action_held(Action::Screenshot));
+		assert!(!held.is_action_held(Action::MoveBack));
+		// Ctrl is still crouch on its own
+		assert!(held.is_action_held(Action::Crouch));
+		
+		// Sprinting doesn't stop keys without a Shift binding from working
+		keys.clear();
+		keys.press(Scancode::W);
+		keys.press(Scancode::LShift);
+		let held = map.get_held_actions(&keys);
+		assert!(held.is_action_held(Action::MoveForward));
+		assert!(held.is_action_held(Action::Sprint));
+	}
+}
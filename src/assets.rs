@@ -0,0 +1,30 @@
+//! Finding the directory that the game's files, such as shaders and levels, are in.
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// A file that is always in the asset root, used to recognise it.
+pub const ROOT_MARKER: &'static str = "shaders/main.vs";
+
+/// Returns true if `dir` looks like the asset root.
+pub fn is_root(dir: &Path) -> bool {
+	dir.join(ROOT_MARKER).is_file()
+}
+
+/// Finds the asset root. The directory of the executable and each of its parents are searched, along with the
+/// `src` directory inside each of them, so that both installed builds and builds in `target` work. If nothing
+/// is found, the current directory is used.
+pub fn find_root() -> PathBuf {
+	if let Ok(exe) = env::current_exe() {
+		let mut dir = exe.parent();
+		while let Some(d) = dir {
+			if is_root(d) {
+				return d.to_path_buf();
+			}
+			if is_root(&d.join("src")) {
+				return d.join("src");
+			}
+			dir = d.parent();
+		}
+	}
+	PathBuf::from(".")
+}
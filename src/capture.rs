@@ -0,0 +1,82 @@
+//! Saving frames as PNGs: screenshots of the window, and sequences of frames drawn offscreen.
+//!
+//! Screenshots are numbered so that they never overwrite each other. A sequence draws the world with the software
+//! renderer after every tick, into `frame_00000.png`, `frame_00001.png` and so on, so that the frames of two runs
+//! can be compared one by one.
+use world::World;
+use soft_render::SoftRender;
+use texture::{self, Image};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The directory that screenshots taken with the screenshot key are saved to.
+pub const SCREENSHOT_DIR: &'static str = "screenshots";
+
+/// Gets the first path of the form `<dir>/<prefix>_0000.png` that isn't already taken.
+pub fn next_free_path(dir: &Path, prefix: &str) -> PathBuf {
+	let mut n = 0;
+	loop {
+		let path = dir.join(format!("{}_{:04}.png", prefix, n));
+		if !path.exists() {
+			return path;
+		}
+		n += 1;
+	}
+}
+
+/// Gets the path of frame `n` of a sequence saved to `dir`.
+pub fn frame_path(dir: &Path, n: u32) -> PathBuf {
+	dir.join(format!("frame_{:05}.png", n))
+}
+
+/// Turns rows of 8-bit RGBA pixels upside down. OpenGL reads pixels from the bottom row up, but images are top down.
+pub fn flip_rows(width: u32, height: u32, data: &[u8]) -> Vec<u8> {
+	let row_len = width as usize * 4;
+	let mut flipped = Vec::with_capacity(data.len());
+	for row in (0..height as usize).rev() {
+		flipped.extend(data[row * row_len..(row + 1) * row_len].iter().cloned());
+	}
+	flipped
+}
+
+/// Saves an image as a PNG, creating the directory it goes in if needed.
+pub fn save_image(path: &Path, img: &Image) -> Result<(), String> {
+	if let Some(dir) = path.parent() {
+		if let Err(e) = fs::create_dir_all(dir) {
+			return Err(format!("error creating '{}': {}", dir.display(), e));
+		}
+	}
+	texture::save_png(path, img)
+}
+
+/// Draws frames with the software renderer and saves them as numbered PNGs.
+pub struct FrameSequence {
+	dir: PathBuf,
+	ren: SoftRender,
+	next_frame: u32,
+}
+impl FrameSequence {
+	/// Starts a sequence of frames of `width` by `height` pixels, saved to `dir`.
+	pub fn new(dir: &Path, width: u32, height: u32) -> FrameSequence {
+		FrameSequence {
+			dir: dir.to_path_buf(),
+			ren: SoftRender::new(width, height),
+			next_frame: 0,
+		}
+	}
+	
+	/// Draws `world` and saves it as the next frame.
+	pub fn capture(&mut self, world: &World) -> Result<(), String> {
+		world.render(&mut self.ren);
+		try!(save_image(&frame_path(&self.dir, self.next_frame), self.ren.get_image()));
+		self.ren.swap();
+		self.next_frame += 1;
+		Ok(())
+	}
+	
+	/// Gets the number of frames saved so far.
+	pub fn get_frame_count(&self) -> u32 {
+		self.next_frame
+	}
+}
@@ -0,0 +1,305 @@
+//! Game controller support.
+//!
+//! The state of every controller is kept here, updated from SDL's controller events. `Controllers` doesn't
+//! need a device, so it can be driven with made up events just as well as real ones.
+use prelude::*;
+
+use actions::Button as ActionButton;
+
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::{Axis, Button, GameController};
+
+/// How far a trigger has to be pulled before it counts as pressed, from 0 to 1.
+pub const TRIGGER_PRESS: f32 = 0.5;
+/// How far a pressed trigger has to be let go before it counts as released, from 0 to 1.
+pub const TRIGGER_RELEASE: f32 = 0.3;
+/// How fast the look stick turns the camera at full tilt, in mouse motion units per second.
+pub const LOOK_SPEED: f32 = 300.0;
+
+/// How the position of a stick is turned into movement.
+#[derive(Copy, Clone, Debug)]
+pub struct StickSettings {
+	/// How far the stick has to be pushed before it does anything, from 0 to 1.
+	pub deadzone: f32,
+	/// The power the position of the stick is raised to. Above 1 gives finer control near the centre.
+	pub exponent: f32,
+	pub sensitivity: f32,
+}
+impl StickSettings {
+	/// Applies the deadzone and curve to a stick position, where each axis is from -1 to 1.
+	/// The result is 0 at the edge of the deadzone, rising to `sensitivity` when the stick is fully pushed.
+	pub fn apply(&self, stick: Vec2) -> Vec2 {
+		let mag = stick.norm();
+		if mag <= self.deadzone || mag == 0.0 {
+			return Vec2::new(0.0, 0.0);
+		}
+		let scaled = ((mag - self.deadzone) / (1.0 - self.deadzone)).min(1.0);
+		stick * (scaled.powf(self.exponent) * self.sensitivity / mag)
+	}
+}
+
+/// Converts a raw SDL axis value to the range -1 to 1.
+pub fn normalize_axis(value: i16) -> f32 {
+	if value < 0 {
+		value as f32 / 32768.0
+	} else {
+		value as f32 / 32767.0
+	}
+}
+
+/// The state of one controller.
+#[derive(Clone, Debug)]
+struct Pad {
+	/// The SDL instance id of the controller.
+	which: i32,
+	axes: [f32; 6],
+	buttons: Vec<Button>,
+	/// Whether the left and right triggers count as pressed.
+	triggers: [bool; 2],
+}
+impl Pad {
+	fn new(which: i32) -> Pad {
+		Pad {
+			which: which,
+			axes: [0.0; 6],
+			buttons: Vec::new(),
+			triggers: [false; 2],
+		}
+	}
+	
+	fn get_axis(&self, axis: Axis) -> f32 {
+		self.axes[axis as usize]
+	}
+}
+
+/// The state of every connected controller.
+#[derive(Clone, Debug)]
+pub struct Controllers {
+	pads: Vec<Pad>,
+	/// The left stick, which moves the player.
+	pub move_stick: StickSettings,
+	/// The right stick, which turns the camera.
+	pub look_stick: StickSettings,
+}
+impl Controllers {
+	pub fn new() -> Controllers {
+		Controllers {
+			pads: Vec::new(),
+			move_stick: StickSettings { deadzone: 0.2, exponent: 1.0, sensitivity: 1.0 },
+			look_stick: StickSettings { deadzone: 0.15, exponent: 2.0, sensitivity: 1.0 },
+		}
+	}
+	
+	fn get_pad(&mut self, which: i32) -> &mut Pad {
+		if let Some(i) = self.pads.iter().position(|p| p.which == which) {
+			return &mut self.pads[i];
+		}
+		self.pads.push(Pad::new(which));
+		self.pads.last_mut().unwrap()
+	}
+	
+	/// Forgets the controller with the instance id `which`, after it has been unplugged.
+	pub fn remove(&mut self, which: i32) {
+		self.pads.retain(|p| p.which != which);
+	}
+	
+	/// Updates an axis of a controller. Returns the trigger if it has just been pulled far enough to count as pressed.
+	pub fn handle_axis(&mut self, which: i32, axis: Axis, value: i16) -> Option<ActionButton> {
+		let pad = self.get_pad(which);
+		let value = normalize_axis(value);
+		pad.axes[axis as usize] = value;
+		let trigger = match axis {
+			Axis::TriggerLeft => 0,
+			Axis::TriggerRight => 1,
+			_ => return None,
+		};
+		if !pad.triggers[trigger] && value >= TRIGGER_PRESS {
+			pad.triggers[trigger] = true;
+			Some(ActionButton::Trigger(axis))
+		} else {
+			if pad.triggers[trigger] && value <= TRIGGER_RELEASE {
+				pad.triggers[trigger] = false;
+			}
+			None
+		}
+	}
+	
+	/// Updates a button of a controller. Returns the button if it has just been pressed.
+	pub fn handle_button(&mut self, which: i32, button: Button, pressed: bool) -> Option<ActionButton> {
+		let pad = self.get_pad(which);
+		if !pressed {
+			pad.buttons.retain(|&b| b != button);
+			None
+		} else if pad.buttons.contains(&button) {
+			None
+		} else {
+			pad.buttons.push(button);
+			Some(ActionButton::Pad(button))
+		}
+	}
+	
+	/// Gets every button and trigger that is held down on any controller.
+	pub fn get_held_buttons(&self) -> Vec<ActionButton> {
+		let mut held = Vec::new();
+		for pad in self.pads.iter() {
+			held.extend(pad.buttons.iter().map(|&b| ActionButton::Pad(b)));
+			if pad.triggers[0] {
+				held.push(ActionButton::Trigger(Axis::TriggerLeft));
+			}
+			if pad.triggers[1] {
+				held.push(ActionButton::Trigger(Axis::TriggerRight));
+			}
+		}
+		held
+	}
+	
+	/// Adds together the position of a stick on every controller, after the deadzone and curve have been applied.
+	fn get_stick(&self, x: Axis, y: Axis, settings: &StickSettings) -> Vec2 {
+		let mut total = Vec2::new(0.0, 0.0);
+		for pad in self.pads.iter() {
+			total = total + settings.apply(Vec2::new(pad.get_axis(x), pad.get_axis(y)));
+		}
+		total
+	}
+	
+	/// Gets how the player should move. x is right and y is forward, from -1 to 1.
+	pub fn get_move_axis(&self) -> Vec2 {
+		let stick = self.get_stick(Axis::LeftX, Axis::LeftY, &self.move_stick);
+		// Up on the stick is negative in SDL
+		Vec2::new(stick.x.max(-1.0).min(1.0), (-stick.y).max(-1.0).min(1.0))
+	}
+	
+	/// Gets how far the camera should turn over `dt` seconds, in the same units as mouse motion.
+	pub fn get_look(&self, dt: DT) -> Vec2 {
+		self.get_stick(Axis::RightX, Axis::RightY, &self.look_stick) * (LOOK_SPEED * dt)
+	}
+}
+
+/// The controllers that have been opened with SDL.
+pub struct Devices {
+	subsystem: GameControllerSubsystem,
+	open: Vec<GameController>,
+}
+impl Devices {
+	pub fn new(subsystem: GameControllerSubsystem) -> Devices {
+		Devices {
+			subsystem: subsystem,
+			open: Vec::new(),
+		}
+	}
+	
+	/// Opens the controller at device index `index`, after it has been plugged in.
+	pub fn open(&mut self, index: u32) {
+		if !self.subsystem.is_game_controller(index) {
+			return;
+		}
+		match self.subsystem.open(index) {
+			Ok(c) => {
+				println!("controller connected: {}", c.name());
+				self.open.push(c);
+			},
+			Err(e) => println!("error opening controller {}: {}", index, e),
+		}
+	}
+	
+	/// Closes any controllers that have been unplugged.
+	pub fn close_detached(&mut self) {
+		for c in self.open.iter().filter(|c| !c.attached()) {
+			println!("controller disconnected: {}", c.name());
+		}
+		self.open.retain(|c| c.attached());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prelude::*;
+	use actions::Button as ActionButton;
+	use sdl2::controller::{Axis, Button};
+	
+	/// Converts a position from -1 to 1 to a raw SDL axis value.
+	fn raw(value: f32) -> i16 {
+		(value * 32767.0).round() as i16
+	}
+	
+	fn approx_eq(a: f32, b: f32) -> bool {
+		(a - b).abs() < 1e-4
+	}
+	
+	#[test]
+	fn deadzone_is_radial() {
+		let settings = StickSettings { deadzone: 0.2, exponent: 1.0, sensitivity: 1.0 };
+		assert_eq!(settings.apply(Vec2::new(0.0, 0.0)), Vec2::new(0.0, 0.0));
+		assert_eq!(settings.apply(Vec2::new(0.2, 0.0)), Vec2::new(0.0, 0.0));
+		assert_eq!(settings.apply(Vec2::new(0.0, -0.2)), Vec2::new(0.0, 0.0));
+		// Each axis is inside the deadzone, but together they are outside it
+		let diagonal = settings.apply(Vec2::new(0.15, 0.15));
+		assert!(diagonal.x > 0.0 && diagonal.y > 0.0);
+		assert!(approx_eq(diagonal.x, diagonal.y));
+		// Just past the edge is close to 0, rather than jumping to the deadzone
+		assert!(settings.apply(Vec2::new(0.21, 0.0)).x < 0.02);
+	}
+	
+	#[test]
+	fn response_curve() {
+		let settings = StickSettings { deadzone: 0.15, exponent: 2.0, sensitivity: 3.0 };
+		let full = settings.apply(Vec2::new(-1.0, 0.0));
+		assert!(approx_eq(full.x, -3.0) && full.y == 0.0);
+		// Halfway between the deadzone and the edge gives a quarter of the speed
+		let half = settings.apply(Vec2::new(0.0, 0.15 + 0.85 * 0.5));
+		assert!(approx_eq(half.x, 0.0) && approx_eq(half.y, 0.75));
+		// Pushing into a corner doesn't go faster than pushing straight
+		let corner = settings.apply(Vec2::new(1.0, 1.0));
+		assert!(approx_eq(corner.norm(), 3.0));
+		assert!(approx_eq(corner.x, corner.y));
+	}
+	
+	#[test]
+	fn trigger_hysteresis() {
+		let mut pads = Controllers::new();
+		let trigger = Some(ActionButton::Trigger(Axis::TriggerRight));
+		assert_eq!(pads.handle_axis(0, Axis::TriggerRight, raw(TRIGGER_PRESS - 0.05)), None);
+		assert_eq!(pads.handle_axis(0, Axis::TriggerRight, raw(TRIGGER_PRESS + 0.05)), trigger);
+		assert_eq!(pads.get_held_buttons(), vec![ActionButton::Trigger(Axis::TriggerRight)]);
+		// Still held until it is let go past the release point
+		assert_eq!(pads.handle_axis(0, Axis::TriggerRight, raw(1.0)), None);
+		assert_eq!(pads.handle_axis(0, Axis::TriggerRight, raw(TRIGGER_RELEASE + 0.05)), None);
+		assert_eq!(pads.get_held_buttons().len(), 1);
+		assert_eq!(pads.handle_axis(0, Axis::TriggerRight, raw(TRIGGER_PRESS + 0.05)), None);
+		assert_eq!(pads.handle_axis(0, Axis::TriggerRight, raw(TRIGGER_RELEASE - 0.05)), None);
+		assert!(pads.get_held_buttons().is_empty());
+		assert_eq!(pads.handle_axis(0, Axis::TriggerRight, raw(TRIGGER_PRESS + 0.05)), trigger);
+		// Sticks are never pressed
+		assert_eq!(pads.handle_axis(0, Axis::LeftX, raw(1.0)), None);
+	}
+	
+	#[test]
+	fn buttons_are_pressed_once() {
+		let mut pads = Controllers::new();
+		assert_eq!(pads.handle_button(0, Button::A, true), Some(ActionButton::Pad(Button::A)));
+		assert_eq!(pads.handle_button(0, Button::A, true), None);
+		// The same button on another controller is a separate press
+		assert_eq!(pads.handle_button(1, Button::A, true), Some(ActionButton::Pad(Button::A)));
+		assert_eq!(pads.handle_button(0, Button::A, false), None);
+		assert_eq!(pads.get_held_buttons(), vec![ActionButton::Pad(Button::A)]);
+		assert_eq!(pads.handle_button(0, Button::A, true), Some(ActionButton::Pad(Button::A)));
+	}
+	
+	#[test]
+	fn unplugging_drops_state() {
+		let mut pads = Controllers::new();
+		pads.handle_button(3, Button::B, true);
+		pads.handle_axis(3, Axis::TriggerLeft, raw(1.0));
+		pads.handle_axis(3, Axis::LeftY, raw(-1.0));
+		assert_eq!(pads.get_held_buttons().len(), 2);
+		assert!(approx_eq(pads.get_move_axis().y, 1.0));
+		
+		pads.remove(3);
+		assert!(pads.get_held_buttons().is_empty());
+		assert_eq!(pads.get_move_axis(), Vec2::new(0.0, 0.0));
+		// Plugging it back in starts from nothing held
+		assert_eq!(pads.handle_button(3, Button::B, true), Some(ActionButton::Pad(Button::B)));
+		assert_eq!(pads.handle_axis(3, Axis::TriggerLeft, raw(1.0)), Some(ActionButton::Trigger(Axis::TriggerLeft)));
+	}
+}
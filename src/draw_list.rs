@@ -0,0 +1,115 @@
+//! Lists of what to draw, separate from how it is drawn.
+//!
+//! The world turns itself into a `DrawList` without touching OpenGL: the cameras to draw from, the meshes to
+//! draw and the passes that the portal stencil technique needs. A backend, such as `Render`, then carries out
+//! the list. This means the portal rendering can be checked without a GPU by looking at the passes in the list.
+use prelude::*;
+
+use entity::Camera;
+use light::Lighting;
+use render::{Mesh, PortalFallback};
+
+/// Settings of the backend that change what is in the draw list.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DrawSettings {
+	/// Whether the views through portals are drawn.
+	pub render_portals: bool,
+	pub wireframe: bool,
+	/// The number of portals that can be seen through each other.
+	pub portal_depth: u32,
+	/// What the portals at the deepest level are filled with.
+	pub portal_fallback: PortalFallback,
+}
+
+/// How a mesh is coloured.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Shading {
+	/// The vertex colours and texture, lit by the lights.
+	Lit,
+	/// A solid colour.
+	Color(Vec4),
+	/// What was on the screen last frame, at the same place on the screen.
+	LastFrame,
+}
+
+/// What the following draws do to the colour, depth and stencil buffers.
+///
+/// The stencil buffer holds the number of portals that each pixel is seen through. `level` is the number of
+/// portals that the current camera is seen through.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pass {
+	/// Draws nothing visible, but increments the stencil from `level` to `level + 1`, marking the area seen
+	/// through a portal.
+	StencilPortal { level: u32 },
+	/// Undoes `StencilPortal`, decrementing the stencil from `level + 1` back to `level`.
+	UnstencilPortal { level: u32 },
+	/// Clears the depth in the area of `level`, then draws only depth, so that the views through the portals
+	/// are only drawn over by things in front of them.
+	ProtectPortals { level: u32 },
+	/// Draws normally, but only in the area of `level`. If `clear_depth` is true, the depth there is cleared first.
+	Level { level: u32, clear_depth: bool },
+	/// Draws normally everywhere, without using the stencil buffer.
+	Normal,
+}
+
+#[derive(Clone, Debug)]
+pub enum DrawCommand {
+	SetLighting(Lighting),
+	/// Draws from this camera from now on.
+	SetCamera(Camera),
+	BeginPass(Pass),
+	Draw { mesh: Mesh, model: Mat4, shading: Shading },
+}
+
+/// Commands to draw a frame, in order.
+#[derive(Clone, Debug)]
+pub struct DrawList {
+	pub commands: Vec<DrawCommand>,
+}
+impl DrawList {
+	pub fn new() -> DrawList {
+		DrawList {
+			commands: Vec::new(),
+		}
+	}
+	
+	pub fn push(&mut self, cmd: DrawCommand) {
+		self.commands.push(cmd);
+	}
+	
+	pub fn set_camera(&mut self, cam: &Camera) {
+		self.push(DrawCommand::SetCamera(*cam));
+	}
+	
+	pub fn begin_pass(&mut self, pass: Pass) {
+		self.push(DrawCommand::BeginPass(pass));
+	}
+	
+	pub fn draw(&mut self, mesh: &Mesh, model: Mat4, shading: Shading) {
+		self.push(DrawCommand::Draw { mesh: mesh.clone(), model: model, shading: shading });
+	}
+	
+	/// Gets every pass in the list, in order.
+	pub fn get_passes(&self) -> Vec<Pass> {
+		self.commands.iter().filter_map(|c| match *c {
+			DrawCommand::BeginPass(p) => Some(p),
+			_ => None,
+		}).collect()
+	}
+	
+	/// Gets the number of meshes drawn.
+	pub fn get_draw_count(&self) -> usize {
+		self.commands.iter().filter(|c| match **c {
+			DrawCommand::Draw { .. } => true,
+			_ => false,
+		}).count()
+	}
+}
+
+/// Something that can carry out a draw list.
+pub trait Backend {
+	/// Gets the settings that the draw list should be made with.
+	fn get_draw_settings(&self) -> DrawSettings;
+	/// Draws everything in the list.
+	fn execute(&mut self, list: &DrawList);
+}
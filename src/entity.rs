@@ -1,25 +1,41 @@
 use prelude::*;
 
-use render::{Render, Mesh};
+use render::{Mesh, MeshBuilder};
+use draw_list::{DrawList, Shading};
+use level::MeshDesc;
+use texture::Texture;
+use physics::RigidBody;
 use nc::ray::{Ray, RayIntersection};
 use na;
 
+use std::rc::Rc;
+
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum EntityType {
 	Dynamic,
 	Static,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Entity {
 	pub pos: Vec3,
 	pub vel: Vec3,
 	rot: Rot3,
 	mesh: Mesh,
+	/// How the mesh was made, if known. Needed to save the entity in a level.
+	mesh_desc: Option<MeshDesc>,
+	/// The path of the texture of the mesh, if it has one. Needed to save the entity in a level.
+	texture_path: Option<String>,
+	/// The shape that other things collide against, if any.
+	collision_mesh: Option<Rc<TriMesh>>,
+	/// The mass and collision shape of a dynamic entity. Entities without one just move at `vel`.
+	body: Option<RigidBody>,
 	etype: EntityType,
+	/// The number of times the entity has been teleported through a portal.
+	transits: u32,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Portal {
 	pub pos: Vec3,
 	pub rot: Rot3,
@@ -35,7 +51,7 @@ impl Portal {
 		let h2 = h/2.0;
 		let d2 = d/2.0;
 		
-		let mesh = Mesh::indexed(
+		let mesh = MeshBuilder::from_indexed(
 			// 01  45
 			// 23  67
 			&[
@@ -64,11 +80,11 @@ impl Portal {
 				Vec3::new(1.0, 1.0, 1.0),
 				Vec3::new(1.0, 1.0, 1.0),
 				Vec3::new(1.0, 1.0, 1.0),
-			]);
+			]).finish();
 		
 		//let mesh = Mesh::new_rectangle_double(w, h, Vec3::new(1.0, 1.0, 1.0));
 		let outline_mesh = Mesh::new_rect_torus(w, h, 0.04);
-				
+		
 		Portal {
 			pos: pos,
 			rot: rot,
@@ -79,23 +95,96 @@ impl Portal {
 		}
 	}
 	
+	pub fn get_w(&self) -> f32 {
+		self.w
+	}
+	pub fn get_h(&self) -> f32 {
+		self.h
+	}
+	
 	pub fn get_normal(&self) -> Vec3 {
 		self.rot * Vec3::new(0.0, 0.0, 1.0)
 	}
 	
+	/// Gets the rotation from this portal to `p_out`. Going into the front of one portal comes out of the front of the
+	/// other, so there is a half turn about the portal's up axis on top of the turn between the portals.
+	pub fn get_rotation_to(&self, p_out: &Portal) -> Rot3 {
+		let half_turn = Rot3::new(Vec3::new(0.0, ::std::f32::consts::PI, 0.0));
+		p_out.rot * half_turn * self.rot.inv().unwrap()
+	}
+	
+	/// If moving from `pos` by `mov` goes through this portal, returns where that ends up on the other
+	/// side of `p_out`, and the rotation from this portal to `p_out`.
+	pub fn get_transit(&self, p_out: &Portal, pos: Vec3, mov: Vec3) -> Option<(Vec3, Rot3)> {
+		let mov_len = mov.norm();
+		if mov_len == 0.0 {
+			return None;
+		}
+		match self.get_intersection(&Ray::new(pos.to_pnt(), mov / mov_len)) {
+			Some(ri) if ri.toi <= mov_len => {
+				// Intersection - translate intersection point to other portal + rotate
+				let rot = self.get_rotation_to(p_out);
+				Some((rot * (pos + mov - self.pos) + p_out.pos, rot))
+			},
+			_ => None,
+		}
+	}
+	
+	/// Gets how far `pos` is in front of the plane of the portal. Negative if it is behind.
+	pub fn get_distance(&self, pos: Vec3) -> f32 {
+		self.get_normal().dot(&(pos - self.pos))
+	}
+	
+	/// Returns true if `pos` is in front of or behind the portal's rectangle, rather than off to one side.
+	pub fn is_within(&self, pos: Vec3) -> bool {
+		let local = self.rot.inv_rotate(&(pos - self.pos));
+		local.x.abs() <= self.w / 2.0 && local.y.abs() <= self.h / 2.0
+	}
+	
+	/// Gets the distance from `pos` to the closest point of the portal's rectangle.
+	pub fn get_distance_to_rect(&self, pos: Vec3) -> f32 {
+		let local = self.rot.inv_rotate(&(pos - self.pos));
+		let (w2, h2) = (self.w / 2.0, self.h / 2.0);
+		let closest = Vec3::new(local.x.max(-w2).min(w2), local.y.max(-h2).min(h2), 0.0);
+		(local - closest).norm()
+	}
+	
+	/// Moves the transform `iso` from this portal to `p_out`, as going through this portal would.
+	pub fn transform_iso(&self, p_out: &Portal, iso: &Iso3) -> Iso3 {
+		let rot = self.get_rotation_to(p_out);
+		Iso3::new_with_rotmat(rot * (iso.translation - self.pos) + p_out.pos, rot * iso.rotation)
+	}
+	
+	/// Returns the portal `alpha` of the way between `self` and `next`.
+	pub fn interpolate(&self, next: &Portal, alpha: f32) -> Portal {
+		let delta = self.rot.rotation_to(&next.rot);
+		Portal {
+			pos: self.pos + (next.pos - self.pos) * alpha,
+			rot: Rot3::new(delta.rotation() * alpha) * self.rot,
+			..next.clone()
+		}
+	}
+	
+	/// Gets the plane of the portal as (normal, offset), facing away from `eye`.
+	/// Points `p` in front of the plane satisfy `dot(plane, (p, 1)) > 0`.
+	pub fn get_clip_plane(&self, eye: Vec3) -> Vec4 {
+		let mut n = self.get_normal();
+		if n.dot(&(eye - self.pos)) > 0.0 {
+			n = -n;
+		}
+		Vec4::new(n.x, n.y, n.z, -n.dot(&self.pos))
+	}
+	
 	pub fn get_model_mat(&self) -> Mat4 {
 		Iso3::new_with_rotmat(self.pos, self.rot).to_homogeneous()
 	}
 	
-	pub fn render(&self, r: &mut Render) {
-		self.mesh.render(r, self.get_model_mat());
-	}
-	pub fn render_color(&self, r: &mut Render, color: &[f32; 4]) {
-		self.mesh.render_color(r, self.get_model_mat(), color);
+	pub fn draw(&self, list: &mut DrawList, shading: Shading) {
+		list.draw(&self.mesh, self.get_model_mat(), shading);
 	}
 	
-	pub fn render_outline(&self, r: &mut Render) {
-		self.outline_mesh.render(r, self.get_model_mat());
+	pub fn draw_outline(&self, list: &mut DrawList) {
+		list.draw(&self.outline_mesh, self.get_model_mat(), Shading::Lit);
 	}
 	
 	pub fn get_intersection(&self, ray: &Ray<Pnt3>) -> Option<RayIntersection<Vec3>> {
@@ -120,41 +209,153 @@ impl Portal {
 }
 
 impl Entity {
+	/// Creates a dynamic entity, with a rigid body that collides as a shape around the vertices of `mesh`.
 	pub fn new(pos: Vec3, vel: Vec3, mesh: Mesh) -> Entity {
-		Entity{ pos:pos, rot: Rot3::new_identity(3), vel:vel, mesh:mesh, etype: EntityType::Dynamic }
+		let body = RigidBody::from_verts(mesh.get_verts());
+		Entity{ pos:pos, rot: Rot3::new_identity(3), vel:vel, mesh:mesh, mesh_desc: None, texture_path: None, collision_mesh: None, body: body, etype: EntityType::Dynamic, transits: 0 }
 	}
 	pub fn new_static(pos: Vec3, mesh: Mesh) -> Entity {
-		Entity{ pos:pos, rot: Rot3::new_identity(3), vel: Vec3::new(0.0, 0.0, 0.0), mesh:mesh, etype: EntityType::Static }
+		Entity{ pos:pos, rot: Rot3::new_identity(3), vel: Vec3::new(0.0, 0.0, 0.0), mesh:mesh, mesh_desc: None, texture_path: None, collision_mesh: None, body: None, etype: EntityType::Static, transits: 0 }
 	}
-	pub fn tick(&mut self, dt: DT) {
+	pub fn set_mesh_desc(&mut self, desc: MeshDesc) {
+		self.mesh_desc = Some(desc);
+	}
+	pub fn get_mesh_desc(&self) -> Option<&MeshDesc> {
+		self.mesh_desc.as_ref()
+	}
+	/// Sets the texture of the mesh. `path` is where it was loaded from, relative to the level.
+	pub fn set_texture(&mut self, path: String, texture: Texture) {
+		self.mesh.set_texture(Some(texture));
+		self.texture_path = Some(path);
+	}
+	pub fn get_texture_path(&self) -> Option<&str> {
+		self.texture_path.as_ref().map(|p| &p[..])
+	}
+	pub fn get_type(&self) -> EntityType {
+		self.etype
+	}
+	pub fn set_collision_mesh(&mut self, mesh: TriMesh) {
+		self.collision_mesh = Some(Rc::new(mesh));
+	}
+	pub fn get_collision_mesh(&self) -> Option<&TriMesh> {
+		self.collision_mesh.as_ref().map(|m| &**m)
+	}
+	pub fn get_body(&self) -> Option<&RigidBody> {
+		self.body.as_ref()
+	}
+	pub fn get_body_mut(&mut self) -> Option<&mut RigidBody> {
+		self.body.as_mut()
+	}
+	/// Gets the transform from the entity's local space to world space.
+	pub fn get_iso(&self) -> Iso3 {
+		Iso3::new_with_rotmat(self.pos, self.rot)
+	}
+	/// Moves a dynamic entity on by `dt` at its current velocity, turning it around its centre of mass if it has a
+	/// rigid body. Collisions are handled by `physics::step`.
+	pub fn tick(&mut self, dt: DT, ps: &Option<(Portal, Portal)>) {
 		match self.etype {
 			EntityType::Dynamic => {
-				self.pos = self.pos + self.vel * dt;
+				let spin = match self.body {
+					Some(ref body) if body.is_sleeping() => return,
+					Some(ref body) => Some((body.ang_vel * dt, body.get_centre())),
+					None => None,
+				};
+				if let Some((spin, centre)) = spin {
+					if spin != Vec3::new(0.0, 0.0, 0.0) {
+						let pos = self.pos + self.rot * centre;
+						self.rot = Rot3::new(spin) * self.rot;
+						self.pos = pos - self.rot * centre;
+					}
+				}
+				
+				let mov = self.vel * dt;
+				if mov == Vec3::new(0.0, 0.0, 0.0) {
+					return;
+				}
+				if let &Some((ref p1, ref p2)) = ps {
+					if self.translate_through_portal(mov, p1, p2) || self.translate_through_portal(mov, p2, p1) {
+						return;
+					}
+				}
+				self.pos = self.pos + mov;
 			},
 			EntityType::Static => {},
 		}
 	}
-	pub fn render(&self, ren: &mut Render) {
-		let model_mat: Mat4 = translation_mat(&self.pos);
-		self.mesh.render(ren, model_mat);
+	// Returns true if the entity has been translated through p_in.
+	// Speedy thing goes in, speedy thing comes out - the velocity and orientation are rotated along with the position.
+	fn translate_through_portal(&mut self, mov: Vec3, p_in: &Portal, p_out: &Portal) -> bool {
+		match p_in.get_transit(p_out, self.pos, mov) {
+			Some((pos, rot)) => {
+				self.pos = pos;
+				self.vel = rot * self.vel;
+				self.rot = rot * self.rot;
+				if let Some(ref mut body) = self.body {
+					body.ang_vel = rot * body.ang_vel;
+				}
+				self.transits += 1;
+				true
+			},
+			None => false,
+		}
+	}
+	/// Gets the number of times the entity has been teleported through a portal.
+	pub fn get_transits(&self) -> u32 {
+		self.transits
+	}
+	pub fn get_rot(&self) -> Rot3 {
+		self.rot
+	}
+	pub fn set_rot(&mut self, rot: Rot3) {
+		self.rot = rot;
+	}
+	/// Moves the entity by `dist` along the unit vector `dir`, and makes sure it is moving along `dir` at least as
+	/// fast as `speed`, waking it if it is asleep. Used by the player to push things.
+	pub fn push(&mut self, dir: Vec3, dist: f32, speed: f32) {
+		self.pos = self.pos + dir * dist;
+		let along = self.vel.dot(&dir);
+		if along < speed {
+			self.vel = self.vel + dir * (speed - along);
+		}
+		if let Some(ref mut body) = self.body {
+			body.wake();
+		}
+	}
+	pub fn draw(&self, list: &mut DrawList) {
+		let model_mat: Mat4 = self.get_iso().to_homogeneous();
+		list.draw(&self.mesh, model_mat, Shading::Lit);
 	}
 }
 
-#[derive(Copy, Clone)]
+/// How far up or down the camera can look, in radians.
+const PITCH_LIMIT: f32 = 1.2;
+/// How quickly the camera levels out after being tilted by a portal. The tilt shrinks by a factor of e every 1/RELEVEL_RATE seconds.
+const RELEVEL_RATE: f32 = 4.0;
+/// Once the tilt is smaller than this (in radians), the camera is snapped upright.
+const RELEVEL_SNAP: f32 = 0.001;
+
+#[derive(Copy, Clone, Debug)]
 pub struct Camera {
 	pos: Vec3,
 	xrot: f32,
 	yrot: f32,
 	fov: f32,
 	view: Mat4,
+	/// The plane (in world space) that everything on the camera's side of should be clipped by.
+	/// This is set to the plane of the exit portal when the camera is transformed through a portal.
+	clip_plane: Option<Vec4>,
+	/// The number of times the camera has been teleported through a portal.
+	transits: u32,
+	/// Extra rotation (in world space) applied on top of xrot and yrot, left over from going through portals
+	/// that aren't upright. This is eased back to the identity so that the camera levels out again.
+	tilt: Rot3,
 }
 
-// /// Gets the matrix that transforms the world from p_in to p_out
-// pub fn get_portal_transform(p_in: &Portal, p_out: &Portal) -> Mat4 {
-// 	let rot = get_rotation_between(p_in.normal, p_out.normal);
-	
-	
-// }
+/// Gets the matrix that transforms the world from p_in to p_out
+pub fn get_portal_transform(p_in: &Portal, p_out: &Portal) -> Mat4 {
+	let rot = p_in.get_rotation_to(p_out);
+	translation_mat(&p_out.pos) * rot.to_homogeneous() * translation_mat(&-p_in.pos)
+}
 
 impl Camera {
 	pub fn new(pos: Vec3, fov: f32) -> Camera {
@@ -164,115 +365,203 @@ impl Camera {
 			yrot: 0.0,
 			fov: fov * (::std::f32::consts::PI / 180.0),
 			view: Mat4::new_identity(4),
+			clip_plane: None,
+			transits: 0,
+			tilt: Rot3::new_identity(3),
 		};
 		cam.update_view();
 		cam
 	}
 	
+	// Transforms the camera so that it views the world from p_out as if it were looking through p_in.
+	// The current view is composed with the portal transform, so this can be applied repeatedly.
 	pub fn transform_through_portal(&mut self, p_in: &Portal, p_out: &Portal) {
-		let mut rot = p_in.rot.rotation_to(&p_out.rot);
+		let rot = p_in.get_rotation_to(p_out);
 		
-		//let rot = ::get_rotation_between(p_in_norm, p_out_norm);
 		self.pos = self.pos - p_in.pos;
 		self.pos = rot * self.pos;
 		self.pos = self.pos + p_out.pos;
 		
-		self.view = (Rot3::new_with_euler_angles(-self.yrot, 0.0, 0.0) * Rot3::new_with_euler_angles(0.0, self.xrot, 0.0)).to_homogeneous();
-		self.view = self.view * rot.inv().unwrap_or(Rot3::new_identity(3)).to_homogeneous();
-		self.view = self.view * translation_mat(&-self.pos);
+		// The world is moved from p_out back to p_in before the existing view is applied
+		self.view = self.view * get_portal_transform(p_out, p_in);
 		
-		/*self.view = self.view * get_rotation_between(p_in_norm, -p_out_norm);
-		match rot.inv() {
-			Some(m) => self.view = self.view * m,
-			None => {},
-		}*/
-	}
-	// // Transforms the current camera through a portal n number of times.
-	// pub fn transform_through_portals(&mut self, p_in: Portal, p_out: Portal, n: u32) {
-	// 	for _ in 0..n {
-			
-	// 	}
-	// }
+		// Anything between the camera and p_out should not be seen
+		self.clip_plane = Some(p_out.get_clip_plane(self.pos));
+	}
+	// Transforms the current camera through a portal n number of times.
+	pub fn transform_through_portals(&mut self, p_in: &Portal, p_out: &Portal, n: u32) {
+		for _ in 0..n {
+			self.transform_through_portal(p_in, p_out);
+		}
+	}
+	
+	pub fn set_rotation(&mut self, xrot: f32, yrot: f32) {
+		self.xrot = 0.0;
+		self.yrot = 0.0;
+		self.tilt = Rot3::new_identity(3);
+		self.rotate(xrot * 10.0, yrot * 10.0);
+	}
+	
+	/// Turns the camera to face `target`, without any tilt.
+	pub fn look_at(&mut self, target: Vec3) {
+		let forward = (target - self.pos).normalize();
+		self.set_rotation((-forward.x).atan2(forward.z), (-forward.y).max(-1.0).min(1.0).asin());
+	}
 	
 	pub fn rotate(&mut self, x: f32, y: f32) {
 		self.xrot += x / 10.0;
 		self.yrot += y / 10.0;
 		
-		let limit = 1.2;
-		if self.yrot < -limit {
-			self.yrot = -limit;
-		} else if self.yrot > limit {
-			self.yrot =  limit;
+		self.clamp_rotation();
+		self.update_view();
+	}
+	
+	fn clamp_rotation(&mut self) {
+		if self.yrot < -PITCH_LIMIT {
+			self.yrot = -PITCH_LIMIT;
+		} else if self.yrot > PITCH_LIMIT {
+			self.yrot =  PITCH_LIMIT;
 		}
 		let pi2 = 2.0 * ::std::f32::consts::PI;
 		self.xrot = self.xrot % pi2;
 		if self.xrot < 0.0 {
 			self.xrot = self.xrot + pi2;
 		}
-		
-		
+	}
+	
+	/// Gets the rotation from world space to view space given by xrot and yrot alone.
+	fn get_upright_view_rot(xrot: f32, yrot: f32) -> Rot3 {
+		Rot3::new_with_euler_angles(-yrot, 0.0, 0.0) * Rot3::new_with_euler_angles(0.0, xrot, 0.0)
+	}
+	
+	/// Gets the full orientation of the camera, as a rotation from view space to world space.
+	pub fn get_orientation(&self) -> Rot3 {
+		self.tilt * Camera::get_upright_view_rot(self.xrot, self.yrot).inv().unwrap()
+	}
+	
+	/// Sets the full orientation of the camera. xrot and yrot are taken from the direction the camera is facing,
+	/// and whatever roll is left over is kept as tilt, to be levelled out over the following ticks.
+	pub fn set_orientation(&mut self, orientation: Rot3) {
+		let forward = orientation * Vec3::new(0.0, 0.0, 1.0);
+		self.xrot = (-forward.x).atan2(forward.z);
+		self.yrot = (-forward.y).max(-1.0).min(1.0).asin();
+		self.clamp_rotation();
+		self.tilt = orientation * Camera::get_upright_view_rot(self.xrot, self.yrot);
+		self.update_view();
+	}
+	
+	/// Gets the angle that the camera is tilted away from upright by.
+	pub fn get_tilt_angle(&self) -> f32 {
+		self.tilt.rotation().norm()
+	}
+	
+	/// Levels the camera out a bit more after it has been tilted by going through a portal.
+	pub fn tick(&mut self, dt: DT) {
+		let angle = self.get_tilt_angle();
+		if angle == 0.0 {
+			return;
+		}
+		let remaining = angle * (-RELEVEL_RATE * dt).exp();
+		if remaining < RELEVEL_SNAP {
+			self.tilt = Rot3::new_identity(3);
+		} else {
+			self.tilt = Rot3::new(self.tilt.rotation() * (remaining / angle));
+		}
 		self.update_view();
 	}
 	
 	fn update_view(&mut self) {
-		self.view = (Rot3::new_with_euler_angles(-self.yrot, 0.0, 0.0) * Rot3::new_with_euler_angles(0.0, self.xrot, 0.0)).to_homogeneous();
+		self.view = (Camera::get_upright_view_rot(self.xrot, self.yrot) * self.tilt.inv().unwrap()).to_homogeneous();
 		self.view = self.view * translation_mat(&-self.pos);
 	}
 	
 	// Returns true if the camera has been translated through a portal.
 	fn translate_through_portal(&mut self, mov: Vec3, p_in: &Portal, p_out: &Portal) -> bool {
-		let mov_norm = mov.normalize();
-		let mov_len = mov.norm();
-		if let Some(ri) = p_in.get_intersection(&Ray::new(self.pos.to_pnt(), mov_norm)) {
-			if ri.toi <= mov_len {
-				// Intersection - translate intersection point to other portal + rotate
-				let rot = p_in.rot.rotation_to(&p_out.rot);
-				let init_pos = self.pos;
-				self.pos = self.pos + mov;
-				self.pos = self.pos - p_in.pos;
-				self.pos = rot * self.pos;
-				self.pos = self.pos + p_out.pos;
-				
-				println!("###### Portal Teleportation ######");
-				println!("from: {:?}", init_pos);
-				println!("  to: {:?}", self.pos);
-				
-				// Get x-rotation between p_in.normal and p_out.normal
-				let p_in_norm  = p_in .get_normal();
-				let p_out_norm = p_out.get_normal();
-				let (in_norm2d, out_norm2d) = (Vec2::new(p_in_norm.x, p_in_norm.z), Vec2::new(p_out_norm.x, p_out_norm.z));
-				let angle_between_x = out_norm2d.y.atan2(out_norm2d.x) - in_norm2d.y.atan2(in_norm2d.x);
-				let angle_between_y = 0.0; // out_norm2d.x.atan2(out_norm2d.y) - in_norm2d.x.atan2(in_norm2d.y);
-				println!("angle_between_x: {}", angle_between_x);
-				println!("angle_between_y: {}", angle_between_y);
-				self.xrot += angle_between_x;
-				self.yrot += angle_between_y;
-				
-				return true;
-			}
+		if let Some((pos, rot)) = p_in.get_transit(p_out, self.pos, mov) {
+			let init_pos = self.pos;
+			self.pos = pos;
+			
+			println!("###### Portal Teleportation ######");
+			println!("from: {:?}", init_pos);
+			println!("  to: {:?}", self.pos);
+			
+			// Turn the whole view by the rotation between the portals, so nothing jumps when going through
+			let orientation = rot * self.get_orientation();
+			self.set_orientation(orientation);
+			self.transits += 1;
+			
+			return true;
 		}
 		false
 	}
 	
-	pub fn translate(&mut self, mov: Vec3, ps: &Option<(Portal, Portal)>) {
+	// Returns the rotation between the portals if the camera has been translated through one.
+	pub fn translate(&mut self, mov: Vec3, ps: &Option<(Portal, Portal)>) -> Option<Rot3> {
 		println!("translate POS x:{: >7.4}, y:{: >7.4}, z:{: >7.4} --- MOV x:{: >7.4}, y:{: >7.4}, z:{: >7.4}"
 					, self.pos.x, self.pos.y, self.pos.z, mov.x, mov.y, mov.z);
 		
+		let mut portal_rot = None;
 		if let &Some((ref p1, ref p2)) = ps {
-			if !(self.translate_through_portal(mov, p1, p2) || self.translate_through_portal(mov, p2, p1)) {
+			if self.translate_through_portal(mov, p1, p2) {
+				portal_rot = Some(p1.get_rotation_to(p2));
+			} else if self.translate_through_portal(mov, p2, p1) {
+				portal_rot = Some(p2.get_rotation_to(p1));
+			} else {
 				self.pos = self.pos + mov;
 			}
 		} else {
 			self.pos = self.pos + mov;
 		}
 		
-		self.update_view()
+		self.update_view();
+		portal_rot
+	}
+	
+	/// Moves the camera to `pos` directly, without going through any portals.
+	pub fn set_pos(&mut self, pos: Vec3) {
+		self.pos = pos;
+		self.update_view();
+	}
+	
+	/// Returns the camera `alpha` of the way between `self` and `next`.
+	/// If the camera went through a portal in between, no interpolation is done.
+	pub fn interpolate(&self, next: &Camera, alpha: f32) -> Camera {
+		if self.transits != next.transits {
+			return *next;
+		}
+		
+		// Take the shortest way around, as xrot wraps at 2pi
+		let pi = ::std::f32::consts::PI;
+		let mut dxrot = next.xrot - self.xrot;
+		if dxrot > pi {
+			dxrot -= 2.0 * pi;
+		} else if dxrot < -pi {
+			dxrot += 2.0 * pi;
+		}
+		
+		let mut cam = *next;
+		cam.pos = self.pos + (next.pos - self.pos) * alpha;
+		cam.xrot = self.xrot + dxrot * alpha;
+		cam.yrot = self.yrot + (next.yrot - self.yrot) * alpha;
+		let dtilt = self.tilt.rotation_to(&next.tilt);
+		cam.tilt = Rot3::new(dtilt.rotation() * alpha) * self.tilt;
+		cam.update_view();
+		cam
+	}
+	
+	/// Gets the direction the camera is looking in.
+	pub fn get_forward(&self) -> Vec3 {
+		self.get_orientation() * Vec3::new(0.0, 0.0, 1.0)
 	}
 	
 	pub fn get_view(&self) -> Mat4 {
 		self.view
 	}
 	
+	pub fn get_clip_plane(&self) -> Option<Vec4> {
+		self.clip_plane
+	}
+	
 	pub fn get_fov(&self) -> f32 {
 		self.fov
 	}
@@ -6,10 +6,22 @@ use render::Render;
 use sdl2;
 use sdl2::Sdl;
 use sdl2::event::{Event, WindowEventId};
-use sdl2::keyboard::KeyboardState;
 use sdl2::mouse::MouseUtil;
+use input::KeyInput;
+use actions::{Action, ActionMap, Button, HeldActions, Modifiers};
+use controller::{Controllers, Devices};
+use replay::{InputEvent, Recorder, Recording};
+use capture;
+
+use std::path::Path;
+
+/// The default number of ticks simulated per second.
+pub const DEFAULT_TICK_RATE: u32 = 60;
+/// The default maximum number of ticks simulated per frame, so that a long frame doesn't make the game spiral.
+pub const DEFAULT_MAX_STEPS: u32 = 5;
 
 pub struct Game {
+	/// The previous and latest tick of the world. Rendering is interpolated between them.
 	worlds: [World; 2],
 	next_index: usize,
 	current_index: usize,
@@ -17,6 +29,17 @@ pub struct Game {
 	paused: bool,
 	mouse_util: MouseUtil,
 	fps: u32,
+	tick_rate: u32,
+	max_steps: u32,
+	/// Time that has passed that has not been simulated yet.
+	accumulator: DT,
+	/// What the keys and mouse buttons do.
+	actions: ActionMap,
+	controllers: Controllers,
+	/// The controllers opened with SDL, if the controller subsystem could be started.
+	devices: Option<Devices>,
+	/// Records the input of every tick, if recording has been started.
+	recorder: Option<Recorder>,
 }
 impl Game {
 	pub fn new(world: World, mouse_util: MouseUtil) -> Game {
@@ -28,9 +51,58 @@ impl Game {
 			paused: false,
 			mouse_util: mouse_util,
 			fps: 0,
+			tick_rate: DEFAULT_TICK_RATE,
+			max_steps: DEFAULT_MAX_STEPS,
+			accumulator: 0.0,
+			actions: ActionMap::new(),
+			controllers: Controllers::new(),
+			devices: None,
+			recorder: None,
 		}
 	}
 	
+	pub fn set_action_map(&mut self, actions: ActionMap) {
+		self.actions = actions;
+	}
+	pub fn get_action_map(&self) -> &ActionMap {
+		&self.actions
+	}
+	
+	/// Lets controllers be plugged in and out while the game is running.
+	pub fn set_devices(&mut self, devices: Devices) {
+		self.devices = Some(devices);
+	}
+	pub fn get_controllers_mut(&mut self) -> &mut Controllers {
+		&mut self.controllers
+	}
+	
+	/// Starts recording the input sent to the world, so that it can be replayed later.
+	pub fn start_recording(&mut self) {
+		self.recorder = Some(Recorder::new(self.tick_rate));
+	}
+	/// Stops recording, returning everything that was recorded.
+	pub fn stop_recording(&mut self) -> Option<Recording> {
+		self.recorder.take().map(|r| r.get_recording().clone())
+	}
+	
+	pub fn set_tick_rate(&mut self, tick_rate: u32) {
+		self.tick_rate = ::std::cmp::max(tick_rate, 1);
+	}
+	pub fn get_tick_rate(&self) -> u32 {
+		self.tick_rate
+	}
+	/// Gets the length of a tick in seconds.
+	pub fn get_tick_dt(&self) -> DT {
+		1.0 / self.tick_rate as DT
+	}
+	
+	pub fn set_max_steps(&mut self, max_steps: u32) {
+		self.max_steps = ::std::cmp::max(max_steps, 1);
+	}
+	pub fn get_max_steps(&self) -> u32 {
+		self.max_steps
+	}
+	
 	pub fn get_current_world<'a>(&'a self) -> &'a World {
 		&self.worlds[self.current_index]
 	}
@@ -42,45 +114,55 @@ impl Game {
 		self.paused
 	}
 	
-	pub fn handle_events(&mut self, _sdl: &Sdl, pump: &mut sdl2::EventPump, ren: &mut Render) {
+	pub fn handle_events(&mut self, sdl: &Sdl, pump: &mut sdl2::EventPump, ren: &mut Render) {
 		for event in pump.poll_iter() {
 			match event {
 				Event::Quit{..} => {
 					self.to_quit = true;
 					break;
 				},
-				Event::KeyDown{ keycode:key, keymod, repeat, .. } => {
-					match key {
-						Some(Key::Escape) => {
-							self.toggle_paused();
-						},
-						Some(Key::F7) => {
-							ren.toggle_wireframes();
-						},
-						Some(Key::F8) => {
-							ren.toggle_portal_rendering();
-						},
-						_ => {}
-					}
-					if !self.paused {
-						self.worlds[self.next_index].handle_keydown(&key.unwrap(), &keymod, repeat);
-					}
-				},
-				Event::KeyUp{ keycode:key, keymod, .. } => {
-					if key.is_some() && !self.paused {
-						self.worlds[self.next_index].handle_keyup(&key.unwrap(), &keymod);
+				Event::KeyDown{ scancode: Some(key), keymod, repeat, .. } => {
+					if !repeat {
+						self.handle_button(Button::Key(key), Modifiers::from_mod(keymod), ren);
 					}
 				},
 				Event::MouseMotion{xrel:x, yrel:y, ..} => {
 					if !self.paused {
-						self.worlds[self.next_index].handle_mouse_motion(x as f32, y as f32);
+						self.send_event(InputEvent::MouseMotion(x as f32, y as f32));
 					}
 				},
 				Event::MouseButtonDown{ mouse_btn, .. } => {
 					if self.paused && mouse_btn == sdl2::mouse::Mouse::Left {
 						self.toggle_paused();
+					} else {
+						let mods = Modifiers::from_mod(sdl.keyboard().mod_state());
+						self.handle_button(Button::Mouse(mouse_btn), mods, ren);
 					}
 				},
+				Event::ControllerDeviceAdded{ which, .. } => {
+					if let Some(ref mut devices) = self.devices {
+						devices.open(which as u32);
+					}
+				},
+				Event::ControllerDeviceRemoved{ which, .. } => {
+					if let Some(ref mut devices) = self.devices {
+						devices.close_detached();
+					}
+					self.controllers.remove(which);
+				},
+				Event::ControllerAxisMotion{ which, axis, value, .. } => {
+					if let Some(button) = self.controllers.handle_axis(which, axis, value) {
+						self.handle_button(button, Modifiers::none(), ren);
+					}
+				},
+				Event::ControllerButtonDown{ which, button, .. } => {
+					if let Some(button) = self.controllers.handle_button(which, button, true) {
+						self.handle_button(button, Modifiers::none(), ren);
+					}
+				},
+				Event::ControllerButtonUp{ which, button, .. } => {
+					self.controllers.handle_button(which, button, false);
+				},
 				Event::Window{ win_event_id, .. } => {
 					match win_event_id {
 						WindowEventId::SizeChanged => {
@@ -94,13 +176,91 @@ impl Game {
 		}
 	}
 	
+	/// Performs the actions bound to a button that has just been pressed.
+	fn handle_button(&mut self, button: Button, mods: Modifiers, ren: &mut Render) {
+		let triggered = self.actions.get_triggered_actions(button, mods);
+		for action in triggered {
+			self.handle_action(action, ren);
+		}
+	}
+	
+	/// Performs an action that was triggered by a key or mouse button being pressed.
+	fn handle_action(&mut self, action: Action, ren: &mut Render) {
+		match action {
+			Action::Pause => self.toggle_paused(),
+			Action::ToggleWireframe => ren.toggle_wireframes(),
+			Action::TogglePortalRendering => ren.toggle_portal_rendering(),
+			Action::ToggleProjection => ren.toggle_projection_mode(),
+			Action::CyclePortalFallback => ren.cycle_portal_fallback(),
+			Action::DecreasePortalDepth => {
+				let depth = ren.get_portal_depth();
+				if depth > 0 {
+					ren.set_portal_depth(depth - 1);
+				}
+			},
+			Action::IncreasePortalDepth => {
+				let depth = ren.get_portal_depth();
+				ren.set_portal_depth(depth + 1);
+			},
+			Action::Screenshot => ren.request_screenshot(capture::next_free_path(Path::new(capture::SCREENSHOT_DIR), "screenshot")),
+			_ => if !self.paused {
+				self.send_event(InputEvent::Action(action));
+			},
+		}
+	}
+	
+	/// Sends an input event to the latest world, recording it if needed.
+	fn send_event(&mut self, event: InputEvent) {
+		event.apply(&mut self.worlds[self.next_index]);
+		if let Some(ref mut recorder) = self.recorder {
+			recorder.record_event(event);
+		}
+	}
+	
 	fn toggle_paused(&mut self) {
 		self.paused = !self.paused;
 		self.mouse_util.set_relative_mouse_mode(!self.paused);
 	}
 	
-	pub fn tick(&mut self, dt: DT, state: &KeyboardState) {
-		self.worlds[self.next_index].tick(dt, state);
+	/// Advances the game by `dt` seconds of real time, simulating as many fixed ticks as have passed.
+	pub fn update<K: KeyInput>(&mut self, dt: DT, state: &K) {
+		if self.paused {
+			return;
+		}
+		
+		let mut held = self.actions.get_held_actions(state);
+		self.actions.add_held_buttons(&self.controllers.get_held_buttons(), &mut held);
+		held.set_move_axis(self.controllers.get_move_axis());
+		let step = self.get_tick_dt();
+		self.accumulator += dt;
+		let mut steps = 0;
+		while self.accumulator >= step && steps < self.max_steps {
+			self.swap();
+			let look = self.controllers.get_look(step);
+			if look != Vec2::new(0.0, 0.0) {
+				self.send_event(InputEvent::MouseMotion(look.x, look.y));
+			}
+			self.tick(step, &held);
+			self.accumulator -= step;
+			steps += 1;
+		}
+		if self.accumulator >= step {
+			// Too far behind - drop the time that couldn't be simulated
+			self.accumulator = self.accumulator % step;
+		}
+	}
+	
+	/// Gets how far between the previous and latest tick the current time is, from 0 to 1.
+	pub fn get_alpha(&self) -> f32 {
+		let alpha = self.accumulator / self.get_tick_dt();
+		if alpha > 1.0 { 1.0 } else { alpha }
+	}
+	
+	pub fn tick(&mut self, dt: DT, held: &HeldActions) {
+		if let Some(ref mut recorder) = self.recorder {
+			recorder.record_tick(held);
+		}
+		self.worlds[self.next_index].tick(dt, held);
 	}
 	
 	pub fn swap(&mut self) {
@@ -123,7 +283,8 @@ impl Game {
 	}
 	
 	pub fn render(&self, ren: &mut Render) {
-		self.worlds[self.current_index].render(ren);
+		let world = self.worlds[self.current_index].interpolate(&self.worlds[self.next_index], self.get_alpha());
+		world.render(ren);
 		ren.swap();
 	}
 }
@@ -0,0 +1,416 @@
+//! Parsing of glTF 2.0 files, both as JSON (`.gltf`) and binary (`.glb`).
+//!
+//! Every triangle primitive of every mesh in the file is loaded, with its positions, indices and either its
+//! vertex colours (`COLOR_0`) or the base colour of its material. Node transforms, textures, sparse
+//! accessors and everything else are ignored, so meshes are loaded in their own space.
+use prelude::*;
+
+use mesh_file::{MeshData, DEFAULT_COLOR};
+
+use rustc_serialize::json::Json;
+use rustc_serialize::base64::FromBase64;
+
+use std::mem;
+
+const GLB_MAGIC: &'static [u8] = b"glTF";
+const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+const GLB_CHUNK_BIN: u32 = 0x004E4942;
+/// The primitive mode for a list of triangles.
+const MODE_TRIANGLES: u64 = 4;
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+	data[offset] as u32 | (data[offset + 1] as u32) << 8 | (data[offset + 2] as u32) << 16 | (data[offset + 3] as u32) << 24
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+	data[offset] as u16 | (data[offset + 1] as u16) << 8
+}
+
+/// Splits a GLB file into its JSON and binary chunks.
+fn split_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>), String> {
+	if data.len() < 12 {
+		return Err(String::from("the GLB header is cut off"));
+	}
+	let version = read_u32(data, 4);
+	if version != 2 {
+		return Err(format!("GLB version {} is not supported", version));
+	}
+	let len = ::std::cmp::min(read_u32(data, 8) as usize, data.len());
+	
+	let (mut json, mut bin) = (None, None);
+	let mut offset = 12;
+	while offset + 8 <= len {
+		let chunk_len = read_u32(data, offset) as usize;
+		let chunk_type = read_u32(data, offset + 4);
+		let start = offset + 8;
+		if start + chunk_len > len {
+			return Err(String::from("a GLB chunk is cut off"));
+		}
+		let chunk = &data[start..start + chunk_len];
+		if chunk_type == GLB_CHUNK_JSON && json.is_none() {
+			json = Some(chunk);
+		} else if chunk_type == GLB_CHUNK_BIN && bin.is_none() {
+			bin = Some(chunk);
+		}
+		offset = start + chunk_len;
+	}
+	match json {
+		Some(json) => Ok((json, bin)),
+		None => Err(String::from("the GLB file has no JSON chunk")),
+	}
+}
+
+fn get<'a>(obj: &'a Json, key: &str) -> Result<&'a Json, String> {
+	match obj.find(key) {
+		Some(v) => Ok(v),
+		None => Err(format!("missing '{}'", key)),
+	}
+}
+
+fn get_index(obj: &Json, key: &str) -> Result<usize, String> {
+	match try!(get(obj, key)).as_u64() {
+		Some(v) => Ok(v as usize),
+		None => Err(format!("'{}' is not an index", key)),
+	}
+}
+
+fn get_usize_or(obj: &Json, key: &str, default: usize) -> Result<usize, String> {
+	match obj.find(key) {
+		Some(_) => get_index(obj, key),
+		None => Ok(default),
+	}
+}
+
+/// Gets element `i` of the array `key` of the document.
+fn get_item<'a>(doc: &'a Json, key: &str, i: usize) -> Result<&'a Json, String> {
+	match try!(get(doc, key)).as_array().and_then(|a| a.get(i)) {
+		Some(v) => Ok(v),
+		None => Err(format!("{} {} does not exist", key, i)),
+	}
+}
+
+/// Loads the data of every buffer in the document.
+fn load_buffers<F: Fn(&str) -> Result<Vec<u8>, String>>(doc: &Json, bin: Option<&[u8]>, read_file: &F) -> Result<Vec<Vec<u8>>, String> {
+	let mut buffers = Vec::new();
+	let list = match doc.find("buffers").and_then(|b| b.as_array()) {
+		Some(list) => list,
+		None => return Ok(buffers),
+	};
+	for (i, buffer) in list.iter().enumerate() {
+		let data = match buffer.find("uri").and_then(|u| u.as_string()) {
+			Some(uri) if uri.starts_with("data:") => {
+				let start = match uri.find(";base64,") {
+					Some(start) => start + ";base64,".len(),
+					None => return Err(format!("buffer {} has a data URI that is not base64", i)),
+				};
+				match uri[start..].from_base64() {
+					Ok(data) => data,
+					Err(e) => return Err(format!("buffer {} has invalid base64: {}", i, e)),
+				}
+			},
+			Some(uri) => try!(read_file(uri)),
+			None => match bin {
+				// Only the first buffer can refer to the GLB binary chunk
+				Some(bin) if i == 0 => bin.to_vec(),
+				_ => return Err(format!("buffer {} has no data", i)),
+			},
+		};
+		buffers.push(data);
+	}
+	Ok(buffers)
+}
+
+/// Reads every element of an accessor as floats. Returns the values and the number of components per element.
+/// Integer components are converted to 0 to 1 (or -1 to 1) if `normalize` is true.
+fn read_accessor(doc: &Json, buffers: &[Vec<u8>], index: usize, normalize: bool) -> Result<(Vec<f64>, usize), String> {
+	let accessor = try!(get_item(doc, "accessors", index));
+	let count = try!(get_index(accessor, "count"));
+	let component_type = try!(get_index(accessor, "componentType"));
+	let comps = match try!(get(accessor, "type")).as_string() {
+		Some("SCALAR") => 1,
+		Some("VEC2") => 2,
+		Some("VEC3") => 3,
+		Some("VEC4") => 4,
+		Some("MAT2") => 4,
+		Some("MAT3") => 9,
+		Some("MAT4") => 16,
+		_ => return Err(format!("accessor {} has an unknown type", index)),
+	};
+	let size = match component_type {
+		5120 | 5121 => 1,
+		5122 | 5123 => 2,
+		5125 | 5126 => 4,
+		_ => return Err(format!("accessor {} has an unknown component type {}", index, component_type)),
+	};
+	if accessor.find("sparse").is_some() {
+		return Err(format!("accessor {} is sparse, which is not supported", index));
+	}
+	let view_index = match accessor.find("bufferView") {
+		Some(_) => try!(get_index(accessor, "bufferView")),
+		// No buffer view means every value is zero
+		None => return Ok((vec![0.0; count * comps], comps)),
+	};
+	
+	let view = try!(get_item(doc, "bufferViews", view_index));
+	let buffer = match buffers.get(try!(get_index(view, "buffer"))) {
+		Some(buffer) => buffer,
+		None => return Err(format!("buffer view {} refers to a buffer that does not exist", view_index)),
+	};
+	let view_start = try!(get_usize_or(view, "byteOffset", 0));
+	let view_len = try!(get_index(view, "byteLength"));
+	let stride = try!(get_usize_or(view, "byteStride", comps * size));
+	let start = view_start + try!(get_usize_or(accessor, "byteOffset", 0));
+	if count > 0 {
+		let end = start + stride * (count - 1) + comps * size;
+		if end > view_start + view_len || end > buffer.len() {
+			return Err(format!("accessor {} goes past the end of its buffer", index));
+		}
+	}
+	
+	let mut values = Vec::with_capacity(count * comps);
+	for i in 0..count {
+		for c in 0..comps {
+			let offset = start + i * stride + c * size;
+			let v = match component_type {
+				5120 => {
+					let v = buffer[offset] as i8 as f64;
+					if normalize { (v / 127.0).max(-1.0) } else { v }
+				},
+				5121 => {
+					let v = buffer[offset] as f64;
+					if normalize { v / 255.0 } else { v }
+				},
+				5122 => {
+					let v = read_u16(buffer, offset) as i16 as f64;
+					if normalize { (v / 32767.0).max(-1.0) } else { v }
+				},
+				5123 => {
+					let v = read_u16(buffer, offset) as f64;
+					if normalize { v / 65535.0 } else { v }
+				},
+				5125 => read_u32(buffer, offset) as f64,
+				_ => unsafe { mem::transmute::<u32, f32>(read_u32(buffer, offset)) as f64 },
+			};
+			values.push(v);
+		}
+	}
+	Ok((values, comps))
+}
+
+/// Gets the base colour of a material.
+fn get_material_color(doc: &Json, index: usize) -> Result<Vec3, String> {
+	let material = try!(get_item(doc, "materials", index));
+	let factor = material.find_path(&["pbrMetallicRoughness", "baseColorFactor"]).and_then(|f| f.as_array());
+	match factor {
+		Some(f) if f.len() >= 3 => {
+			let c: Vec<f32> = f.iter().map(|v| v.as_f64().unwrap_or(1.0) as f32).collect();
+			Ok(Vec3::new(c[0], c[1], c[2]))
+		},
+		_ => Ok(DEFAULT_COLOR),
+	}
+}
+
+/// Loads a single primitive of a mesh.
+fn load_primitive(doc: &Json, buffers: &[Vec<u8>], prim: &Json) -> Result<MeshData, String> {
+	let attributes = try!(get(prim, "attributes"));
+	let (positions, comps) = try!(read_accessor(doc, buffers, try!(get_index(attributes, "POSITION")), false));
+	if comps != 3 {
+		return Err(String::from("positions must be VEC3"));
+	}
+	let count = positions.len() / 3;
+	
+	let colors = match attributes.find("COLOR_0") {
+		Some(_) => {
+			let (colors, comps) = try!(read_accessor(doc, buffers, try!(get_index(attributes, "COLOR_0")), true));
+			if comps != 3 && comps != 4 {
+				return Err(String::from("colours must be VEC3 or VEC4"));
+			}
+			if colors.len() / comps != count {
+				return Err(String::from("there must be as many colours as positions"));
+			}
+			colors.chunks(comps).map(|c| Vec3::new(c[0] as f32, c[1] as f32, c[2] as f32)).collect()
+		},
+		None => {
+			let color = match prim.find("material") {
+				Some(_) => try!(get_material_color(doc, try!(get_index(prim, "material")))),
+				None => DEFAULT_COLOR,
+			};
+			vec![color; count]
+		},
+	};
+	
+	let mut mesh = MeshData::new();
+	for i in 0..count {
+		mesh.push(Vec3::new(positions[i * 3] as f32, positions[i * 3 + 1] as f32, positions[i * 3 + 2] as f32), colors[i]);
+	}
+	
+	let indices: Vec<u32> = match prim.find("indices") {
+		Some(_) => try!(read_accessor(doc, buffers, try!(get_index(prim, "indices")), false)).0.iter().map(|&i| i as u32).collect(),
+		None => (0..count as u32).collect(),
+	};
+	for t in indices.chunks(3) {
+		if t.len() < 3 {
+			break;
+		}
+		if t.iter().any(|&i| i as usize >= count) {
+			return Err(String::from("an index is out of range"));
+		}
+		mesh.indices.push([t[0], t[1], t[2]]);
+	}
+	Ok(mesh)
+}
+
+/// Parses a glTF file, or a GLB file if `data` starts with the GLB header.
+/// `read_file` is used to read the external buffers that the file refers to.
+pub fn parse<F: Fn(&str) -> Result<Vec<u8>, String>>(data: &[u8], read_file: F) -> Result<MeshData, String> {
+	let (json, bin) = if data.starts_with(GLB_MAGIC) {
+		try!(split_glb(data))
+	} else {
+		(data, None)
+	};
+	let json = match ::std::str::from_utf8(json) {
+		Ok(json) => json,
+		Err(e) => return Err(format!("the JSON is not valid UTF-8: {}", e)),
+	};
+	let doc = match Json::from_str(json) {
+		Ok(doc) => doc,
+		Err(e) => return Err(format!("invalid JSON: {}", e)),
+	};
+	let buffers = try!(load_buffers(&doc, bin, &read_file));
+	
+	let mut mesh = MeshData::new();
+	let meshes = match doc.find("meshes").and_then(|m| m.as_array()) {
+		Some(meshes) => meshes,
+		None => return Err(String::from("the file has no meshes")),
+	};
+	for (i, m) in meshes.iter().enumerate() {
+		let prims = match m.find("primitives").and_then(|p| p.as_array()) {
+			Some(prims) => prims,
+			None => return Err(format!("mesh {} has no primitives", i)),
+		};
+		for (j, prim) in prims.iter().enumerate() {
+			let mode = prim.find("mode").and_then(|m| m.as_u64()).unwrap_or(MODE_TRIANGLES);
+			if mode != MODE_TRIANGLES {
+				println!("warning: skipping primitive {} of mesh {}, as only triangles are supported", j, i);
+				continue;
+			}
+			match load_primitive(&doc, &buffers, prim) {
+				Ok(p) => mesh.append(&p),
+				Err(e) => return Err(format!("primitive {} of mesh {}: {}", j, i, e)),
+			}
+		}
+	}
+	Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prelude::*;
+	use mesh_file::DEFAULT_COLOR;
+	use rustc_serialize::base64::{ToBase64, STANDARD};
+	use std::mem;
+	
+	fn push_u32(data: &mut Vec<u8>, v: u32) {
+		data.extend([v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8].iter().cloned());
+	}
+	
+	/// The positions of a triangle as floats, then its indices backwards as shorts, padded to 44 bytes.
+	fn triangle_buffer() -> Vec<u8> {
+		let mut data = Vec::new();
+		for &v in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0].iter() {
+			push_u32(&mut data, unsafe { mem::transmute::<f32, u32>(v) });
+		}
+		data.extend([2, 0, 1, 0, 0, 0, 0, 0].iter().cloned());
+		data
+	}
+	
+	/// A document with one red triangle. `buffer` is the JSON of its buffer, and the accessors are given by
+	/// `position_count`, `indices` and `index_count`.
+	fn document(buffer: &str, position_count: usize, indices: usize, index_count: usize) -> String {
+		format!(r#"{{
+			"asset": {{ "version": "2.0" }},
+			"buffers": [{}],
+			"bufferViews": [
+				{{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+				{{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }}
+			],
+			"accessors": [
+				{{ "bufferView": 0, "componentType": 5126, "count": {}, "type": "VEC3" }},
+				{{ "bufferView": 1, "componentType": 5123, "count": {}, "type": "SCALAR" }}
+			],
+			"materials": [{{ "pbrMetallicRoughness": {{ "baseColorFactor": [1, 0, 0, 1] }} }}],
+			"meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "indices": {}, "material": 0 }}] }}]
+		}}"#, buffer, position_count, index_count, indices)
+	}
+	
+	fn no_files(name: &str) -> Result<Vec<u8>, String> {
+		Err(format!("no file '{}'", name))
+	}
+	
+	fn assert_red_triangle(mesh: &MeshData) {
+		assert_eq!(mesh.verts, vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)]);
+		assert_eq!(mesh.colors, vec![Vec3::new(1.0, 0.0, 0.0); 3]);
+		assert_eq!(mesh.indices, vec![[2, 1, 0]]);
+	}
+	
+	fn data_uri_buffer() -> String {
+		format!(r#"{{ "byteLength": 44, "uri": "data:application/octet-stream;base64,{}" }}"#, triangle_buffer().to_base64(STANDARD))
+	}
+	
+	#[test]
+	fn data_uri_buffer_is_decoded() {
+		let mesh = parse(document(&data_uri_buffer(), 3, 1, 3).as_bytes(), no_files).unwrap();
+		assert_red_triangle(&mesh);
+	}
+	
+	#[test]
+	fn external_buffer_is_read() {
+		let read_file = |name: &str| if name == "triangle.bin" { Ok(triangle_buffer()) } else { no_files(name) };
+		let doc = document(r#"{ "byteLength": 44, "uri": "triangle.bin" }"#, 3, 1, 3);
+		assert_red_triangle(&parse(doc.as_bytes(), read_file).unwrap());
+		assert!(parse(doc.as_bytes(), no_files).is_err());
+	}
+	
+	#[test]
+	fn glb_chunks_are_split() {
+		let mut json = document(r#"{ "byteLength": 44 }"#, 3, 1, 3).into_bytes();
+		while json.len() % 4 != 0 {
+			json.push(b' ');
+		}
+		let bin = triangle_buffer();
+		let mut glb = Vec::new();
+		glb.extend(b"glTF".iter().cloned());
+		push_u32(&mut glb, 2);
+		push_u32(&mut glb, (12 + 8 + json.len() + 8 + bin.len()) as u32);
+		push_u32(&mut glb, json.len() as u32);
+		push_u32(&mut glb, GLB_CHUNK_JSON);
+		glb.extend(json.iter().cloned());
+		push_u32(&mut glb, bin.len() as u32);
+		push_u32(&mut glb, GLB_CHUNK_BIN);
+		glb.extend(bin.iter().cloned());
+		assert_red_triangle(&parse(&glb, no_files).unwrap());
+		
+		// Cut off in the middle of the binary chunk
+		let len = glb.len();
+		assert!(parse(&glb[..len - 4], no_files).is_err());
+	}
+	
+	#[test]
+	fn bad_accessors_are_errors() {
+		let buffer = data_uri_buffer();
+		// Past the end of the buffer view
+		assert!(parse(document(&buffer, 4, 1, 3).as_bytes(), no_files).is_err());
+		assert!(parse(document(&buffer, 3, 1, 4).as_bytes(), no_files).is_err());
+		// No such accessor
+		assert!(parse(document(&buffer, 3, 2, 3).as_bytes(), no_files).is_err());
+		// Indices pointing past the last vertex
+		assert!(parse(document(&buffer, 2, 1, 3).as_bytes(), no_files).is_err());
+	}
+	
+	#[test]
+	fn missing_material_uses_default_colour() {
+		let doc = document(&data_uri_buffer(), 3, 1, 3).replace(r#", "material": 0"#, "");
+		assert_eq!(parse(doc.as_bytes(), no_files).unwrap().colors, vec![DEFAULT_COLOR; 3]);
+	}
+}
@@ -0,0 +1,283 @@
+//! Golden-image tests of portal rendering.
+//!
+//! A canned scene is drawn with `SoftRender` from fixed camera poses, and each frame is compared with a reference
+//! image in the `golden` directory of the asset root. A frame passes if few enough of its pixels are more than a
+//! little different from the reference, so that small changes in rounding don't fail it. When a frame fails, it
+//! is saved along with an image of the differences, where the pixels that are too different are red.
+//!
+//! Run with `--golden <dir>`, where `dir` is where failures are saved. After an intended change to how things look,
+//! `--update-golden` draws the frames again and replaces the references.
+use prelude::*;
+
+use world::World;
+use draw_list::Backend;
+use soft_render::SoftRender;
+use texture::{self, Image};
+
+use std::cmp;
+use std::fs;
+use std::path::Path;
+
+/// The directory in the asset root that the scene and the reference images are in.
+pub const GOLDEN_DIR: &'static str = "golden";
+/// The level that every case is drawn in, in `GOLDEN_DIR`.
+pub const SCENE: &'static str = "scene.lvl";
+
+/// The size of the frames. Small, so that drawing every case on the CPU is quick.
+pub const WIDTH: u32 = 320;
+pub const HEIGHT: u32 = 240;
+
+/// A camera pose and settings to draw the scene with.
+pub struct Case {
+	/// The reference image is `<name>.png`.
+	pub name: &'static str,
+	pub cam_pos: Vec3,
+	/// The point that the camera looks at.
+	pub target: Vec3,
+	/// How far the second portal is turned left and up, in radians, as by the portal rotation controls.
+	pub exit_rot: (f32, f32),
+	pub wireframe: bool,
+	pub portal_depth: u32,
+}
+
+pub const CASES: &'static [Case] = &[
+	// Straight into the first portal
+	Case {
+		name: "straight",
+		cam_pos: Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+		target: Vec3 { x: 0.0, y: 1.0, z: 4.0 },
+		exit_rot: (0.0, 0.0),
+		wireframe: false,
+		portal_depth: 3,
+	},
+	// Nearly along the surface of the first portal
+	Case {
+		name: "grazing",
+		cam_pos: Vec3 { x: 2.2, y: 1.1, z: 3.7 },
+		target: Vec3 { x: 0.0, y: 1.0, z: 4.0 },
+		exit_rot: (0.0, 0.0),
+		wireframe: false,
+		portal_depth: 3,
+	},
+	// Straight into the first portal, with the second portal turned
+	Case {
+		name: "rotated_exit",
+		cam_pos: Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+		target: Vec3 { x: 0.0, y: 1.0, z: 4.0 },
+		exit_rot: (0.4, 0.25),
+		wireframe: false,
+		portal_depth: 3,
+	},
+	// Into the second portal
+	Case {
+		name: "exit_portal",
+		cam_pos: Vec3 { x: 0.5, y: 1.0, z: 1.5 },
+		target: Vec3 { x: -2.5, y: 1.0, z: 1.5 },
+		exit_rot: (0.0, 0.0),
+		wireframe: false,
+		portal_depth: 3,
+	},
+	// Only one level of portals, so the fallback shows
+	Case {
+		name: "shallow",
+		cam_pos: Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+		target: Vec3 { x: 0.0, y: 1.0, z: 4.0 },
+		exit_rot: (0.0, 0.0),
+		wireframe: false,
+		portal_depth: 1,
+	},
+	Case {
+		name: "wireframe",
+		cam_pos: Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+		target: Vec3 { x: 0.0, y: 1.0, z: 4.0 },
+		exit_rot: (0.0, 0.0),
+		wireframe: true,
+		portal_depth: 3,
+	},
+];
+
+/// How different a frame can be from its reference.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tolerance {
+	/// The most that any channel of a pixel can be off by before the pixel counts as different.
+	pub channel: u8,
+	/// The fraction of the pixels that can be different.
+	pub pixels: f32,
+}
+
+pub const TOLERANCE: Tolerance = Tolerance { channel: 8, pixels: 0.002 };
+
+/// The result of comparing a frame with its reference.
+#[derive(Clone, Debug)]
+pub struct Comparison {
+	pub different_pixels: usize,
+	pub total_pixels: usize,
+	/// The most that any channel of any pixel is off by.
+	pub max_diff: u8,
+	/// The reference, darkened, with the pixels that are too different in red.
+	pub diff: Image,
+}
+impl Comparison {
+	pub fn passes(&self, tolerance: &Tolerance) -> bool {
+		self.different_pixels as f32 <= self.total_pixels as f32 * tolerance.pixels
+	}
+}
+
+/// Compares `actual` with `expected` pixel by pixel. Images of different sizes can't be compared.
+pub fn compare(actual: &Image, expected: &Image, tolerance: &Tolerance) -> Result<Comparison, String> {
+	if (actual.width, actual.height) != (expected.width, expected.height) {
+		return Err(format!("the frame is {}x{}, but the reference is {}x{}", actual.width, actual.height, expected.width, expected.height));
+	}
+	let mut diff = Vec::with_capacity(expected.data.len());
+	let (mut different, mut max_diff) = (0, 0);
+	for (a, e) in actual.data.chunks(4).zip(expected.data.chunks(4)) {
+		let d = a.iter().zip(e.iter()).map(|(&x, &y)| if x > y { x - y } else { y - x }).max().unwrap_or(0);
+		max_diff = cmp::max(max_diff, d);
+		if d > tolerance.channel {
+			different += 1;
+			diff.extend([255, 0, 0, 255].iter().cloned());
+		} else {
+			// Dark grey, so that the red stands out
+			let grey = ((e[0] as u32 + e[1] as u32 + e[2] as u32) / 12 + 32) as u8;
+			diff.extend([grey, grey, grey, 255].iter().cloned());
+		}
+	}
+	Ok(Comparison {
+		different_pixels: different,
+		total_pixels: expected.width as usize * expected.height as usize,
+		max_diff: max_diff,
+		diff: Image {
+			width: expected.width,
+			height: expected.height,
+			data: diff,
+		},
+	})
+}
+
+/// Draws the scene as set up by `case`.
+pub fn draw_case(scene: &World, case: &Case) -> Image {
+	let mut world = scene.clone();
+	world.camera.set_pos(case.cam_pos);
+	world.camera.look_at(case.target);
+	world.rotate_exit_portal(case.exit_rot.0, case.exit_rot.1);
+	
+	let mut ren = SoftRender::new(WIDTH, HEIGHT);
+	let mut settings = ren.get_draw_settings();
+	settings.wireframe = case.wireframe;
+	settings.portal_depth = case.portal_depth;
+	ren.set_draw_settings(settings);
+	world.render(&mut ren);
+	ren.get_image().clone()
+}
+
+/// Draws every case and compares it with its reference, saving the frame and the differences to `out_dir` if it
+/// fails. If `update` is true, the references are replaced instead. Returns true if every case passed.
+pub fn run(asset_root: &Path, out_dir: &Path, update: bool) -> Result<bool, String> {
+	let dir = asset_root.join(GOLDEN_DIR);
+	let scene = try!(World::load(dir.join(SCENE)));
+	let mut passed = true;
+	for case in CASES.iter() {
+		let frame = draw_case(&scene, case);
+		let ref_path = dir.join(format!("{}.png", case.name));
+		if update {
+			try!(texture::save_png(&ref_path, &frame));
+			println!("{}: updated {}", case.name, ref_path.display());
+			continue;
+		}
+		
+		let result = match texture::load_png(&ref_path) {
+			Ok(expected) => compare(&frame, &expected, &TOLERANCE),
+			Err(e) => Err(format!("{} (the references are made with --update-golden)", e)),
+		};
+		match result {
+			Ok(ref c) if c.passes(&TOLERANCE) => {
+				println!("{}: ok, {} pixels different", case.name, c.different_pixels);
+			},
+			Ok(c) => {
+				passed = false;
+				println!("{}: FAILED, {} of {} pixels are different, by up to {}", case.name, c.different_pixels, c.total_pixels, c.max_diff);
+				try!(save_failure(out_dir, case, &frame, Some(&c.diff)));
+			},
+			Err(e) => {
+				passed = false;
+				println!("{}: FAILED, {}", case.name, e);
+				try!(save_failure(out_dir, case, &frame, None));
+			},
+		}
+	}
+	Ok(passed)
+}
+
+fn save_failure(out_dir: &Path, case: &Case, frame: &Image, diff: Option<&Image>) -> Result<(), String> {
+	if let Err(e) = fs::create_dir_all(out_dir) {
+		return Err(format!("error creating '{}': {}", out_dir.display(), e));
+	}
+	let path = out_dir.join(format!("{}.png", case.name));
+	try!(texture::save_png(&path, frame));
+	println!("    saved the frame to {}", path.display());
+	if let Some(diff) = diff {
+		let path = out_dir.join(format!("{}.diff.png", case.name));
+		try!(texture::save_png(&path, diff));
+		println!("    saved the differences to {}", path.display());
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use assets;
+	use texture::Image;
+	use std::env;
+	
+	#[test]
+	fn frames_match_references() {
+		let out_dir = env::temp_dir().join("golden_failures");
+		assert_eq!(run(&assets::find_root(), &out_dir, false), Ok(true), "failed frames are in {}", out_dir.display());
+	}
+	
+	#[test]
+	fn compare_counts_pixels_past_the_channel_tolerance() {
+		let tolerance = Tolerance { channel: 8, pixels: 0.25 };
+		let expected = Image::new(2, 2, [100, 100, 100, 255]);
+		let mut actual = expected.clone();
+		// Exactly at the tolerance is still the same, one past it is different
+		actual.data[0] = 108;
+		actual.data[4 + 1] = 92;
+		let c = compare(&actual, &expected, &tolerance).unwrap();
+		assert_eq!(c.different_pixels, 0);
+		assert_eq!(c.max_diff, 8);
+		assert!(c.passes(&tolerance));
+		
+		actual.data[0] = 109;
+		let c = compare(&actual, &expected, &tolerance).unwrap();
+		assert_eq!((c.different_pixels, c.total_pixels, c.max_diff), (1, 4, 9));
+		assert!(c.passes(&tolerance));
+		
+		// A quarter of the pixels can be different, but not more
+		actual.data[4 + 1] = 91;
+		let c = compare(&actual, &expected, &tolerance).unwrap();
+		assert_eq!(c.different_pixels, 2);
+		assert!(!c.passes(&tolerance));
+	}
+	
+	#[test]
+	fn compare_rejects_different_sizes() {
+		let expected = Image::new(2, 2, [0, 0, 0, 255]);
+		assert!(compare(&Image::new(2, 3, [0, 0, 0, 255]), &expected, &TOLERANCE).is_err());
+		assert!(compare(&Image::new(4, 1, [0, 0, 0, 255]), &expected, &TOLERANCE).is_err());
+	}
+	
+	#[test]
+	fn compare_marks_different_pixels_red() {
+		let expected = Image::new(2, 1, [60, 120, 180, 255]);
+		let mut actual = expected.clone();
+		actual.data[2] = 0;
+		let c = compare(&actual, &expected, &TOLERANCE).unwrap();
+		assert_eq!((c.diff.width, c.diff.height), (2, 1));
+		assert_eq!(c.diff.get_pixel(0, 0), [255, 0, 0, 255]);
+		// The same pixels are the reference darkened to grey
+		let grey = ((60 + 120 + 180) / 12 + 32) as u8;
+		assert_eq!(c.diff.get_pixel(1, 0), [grey, grey, grey, 255]);
+	}
+}
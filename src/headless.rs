@@ -0,0 +1,76 @@
+use prelude::*;
+
+use world::World;
+use actions::HeldActions;
+use replay::Recording;
+use soft_render::SoftRender;
+use capture::FrameSequence;
+
+use std::path::Path;
+
+/// The size of frames drawn without a window, the same as the size the window starts at.
+pub const RENDER_WIDTH: u32 = 800;
+pub const RENDER_HEIGHT: u32 = 600;
+
+/// Steps `world` for `ticks` ticks of `dt` seconds each, with `actions` held the whole time.
+///
+/// No window or OpenGL context is needed, so this can be run on machines without a GPU.
+pub fn run(world: &mut World, ticks: u32, dt: DT, actions: &HeldActions) {
+	for _ in 0..ticks {
+		world.tick(dt, actions);
+	}
+}
+
+/// Steps `world` for `ticks` ticks and then prints out its final state.
+pub fn run_and_dump(mut world: World, ticks: u32, dt: DT, actions: &HeldActions) -> World {
+	println!("running {} ticks headless (dt: {:.4}s)", ticks, dt);
+	run(&mut world, ticks, dt, actions);
+	world.dump();
+	world
+}
+
+/// Replays a recording on `world` and then prints out its final state.
+pub fn replay_and_dump(mut world: World, rec: &Recording) -> World {
+	println!("replaying {} ticks headless (dt: {:.4}s)", rec.ticks.len(), rec.get_tick_dt());
+	rec.replay(&mut world);
+	world.dump();
+	world
+}
+
+/// Steps `world` for `ticks` ticks like `run_and_dump`, saving a frame before the first tick and after every tick
+/// to `dir`. See `capture::FrameSequence`.
+pub fn run_and_capture(mut world: World, ticks: u32, dt: DT, actions: &HeldActions, dir: &Path) -> Result<World, String> {
+	println!("running {} ticks headless (dt: {:.4}s), saving frames to {}", ticks, dt, dir.display());
+	let mut frames = FrameSequence::new(dir, RENDER_WIDTH, RENDER_HEIGHT);
+	try!(frames.capture(&world));
+	for _ in 0..ticks {
+		world.tick(dt, actions);
+		try!(frames.capture(&world));
+	}
+	println!("saved {} frames", frames.get_frame_count());
+	world.dump();
+	Ok(world)
+}
+
+/// Replays a recording on `world` like `replay_and_dump`, saving a frame before the first tick and after every
+/// tick to `dir`. See `capture::FrameSequence`.
+pub fn replay_and_capture(mut world: World, rec: &Recording, dir: &Path) -> Result<World, String> {
+	let dt = rec.get_tick_dt();
+	println!("replaying {} ticks headless (dt: {:.4}s), saving frames to {}", rec.ticks.len(), dt, dir.display());
+	let mut frames = FrameSequence::new(dir, RENDER_WIDTH, RENDER_HEIGHT);
+	try!(frames.capture(&world));
+	for tick in rec.ticks.iter() {
+		tick.apply(&mut world, dt);
+		try!(frames.capture(&world));
+	}
+	println!("saved {} frames", frames.get_frame_count());
+	world.dump();
+	Ok(world)
+}
+
+/// Draws `world` with the software renderer and saves the frame as a PNG.
+pub fn render_to_png(world: &World, width: u32, height: u32, path: &Path) -> Result<(), String> {
+	let mut ren = SoftRender::new(width, height);
+	world.render(&mut ren);
+	ren.save_png(path)
+}
@@ -0,0 +1,48 @@
+use sdl2::keyboard::{KeyboardState, Scancode};
+
+/// Something that can be asked which keys are currently held down.
+pub trait KeyInput {
+	fn is_scancode_pressed(&self, scancode: Scancode) -> bool;
+}
+
+impl<'a> KeyInput for KeyboardState<'a> {
+	fn is_scancode_pressed(&self, scancode: Scancode) -> bool {
+		KeyboardState::is_scancode_pressed(self, scancode)
+	}
+}
+
+/// A set of keys that are held down, used to drive a world without SDL.
+#[derive(Clone, Debug)]
+pub struct HeldKeys {
+	keys: Vec<Scancode>,
+}
+impl HeldKeys {
+	pub fn new() -> HeldKeys {
+		HeldKeys {
+			keys: Vec::new(),
+		}
+	}
+	
+	pub fn press(&mut self, scancode: Scancode) {
+		if !self.keys.contains(&scancode) {
+			self.keys.push(scancode);
+		}
+	}
+	
+	pub fn release(&mut self, scancode: Scancode) {
+		self.keys.retain(|&k| k != scancode);
+	}
+	
+	pub fn clear(&mut self) {
+		self.keys.clear();
+	}
+	
+	pub fn get_keys(&self) -> &[Scancode] {
+		&self.keys
+	}
+}
+impl KeyInput for HeldKeys {
+	fn is_scancode_pressed(&self, scancode: Scancode) -> bool {
+		self.keys.contains(&scancode)
+	}
+}
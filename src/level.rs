@@ -0,0 +1,649 @@
+//! Loading and saving of levels.
+//!
+//! Levels are plain text, with one object per line. Anything after a `#` is a comment.
+//!
+//! ```text
+//! # camera <x> <y> <z> [<xrot> <yrot> [<fov in degrees>]]
+//! camera 0 1 0
+//! # dynamic <x> <y> <z> <vx> <vy> <vz> <mesh> [<option> ...]
+//! dynamic -0.3 0.6 0.6  0 0 0  triangle 0.5
+//! dynamic  1.0 0.25 2.0  0 0 0  cuboid 0.5 0.5 0.5  0.8 0.5 0.2  mass 10 friction 0.8 texture textures/crate.png
+//! # static <x> <y> <z> <mesh> [texture <path>]
+//! static 0 0 0  planes 10 10 10 10  1 1 1  0 0 0  texture textures/floor.png
+//! # portal <x> <y> <z> <rotation as axis * angle in radians> <w> <h>
+//! # Portals face along their z axis. Going into the front of one comes out of the front of the other.
+//! portal  0.0 1 4.0  0 0 0  0.9 1.4
+//! portal -1.5 1 2.5  0 3.14159 0  0.9 1.4
+//! # ambient <r> <g> <b>
+//! ambient 0.3 0.3 0.3
+//! # light directional <direction x> <y> <z> <r> <g> <b>
+//! light directional 0.3 -1 0.5  0.7 0.7 0.7
+//! # light point <x> <y> <z> <r> <g> <b> <range>
+//! light point 0 2 0  1 0.8 0.6  5
+//! ```
+//!
+//! A mesh is one of:
+//!
+//! ```text
+//! triangle <scale>
+//! square <scale>
+//! rectangle <w> <h> <r> <g> <b>
+//! rect_torus <w> <h> <d>
+//! planes <num_w> <num_h> <w> <h> <r1> <g1> <b1> <r2> <g2> <b2>
+//! cuboid <w> <h> <d> <r> <g> <b>
+//! file <path relative to the level, to an .obj, .gltf or .glb file>
+//! ```
+//!
+//! The options of a dynamic entity, in any order, are:
+//!
+//! ```text
+//! texture <path to a .png, relative to the level>
+//! rotation <rotation as axis * angle in radians>
+//! angular_velocity <axis * radians/s>
+//! mass <kg>
+//! friction <coefficient>
+//! restitution <fraction of the speed kept when bouncing>
+//! ```
+//!
+//! Static entities can only have a texture. Dynamic entities are rigid bodies that collide as a box if their mesh is
+//! a box, and otherwise as the convex hull of their mesh. Without a mass, they weigh `physics::DEFAULT_DENSITY` per
+//! unit of the volume of their bounding box.
+//!
+//! Textures are multiplied by the colours of the mesh. Only `planes` meshes have texture coordinates, which repeat
+//! the texture once on every plane.
+//!
+//! Portals must come in pairs, and there can only be one pair.
+//!
+//! Levels without any `ambient` or `light` lines get the default lighting. There can be at most `MAX_LIGHTS` lights.
+use prelude::*;
+
+use entity::{Entity, EntityType, Camera, Portal};
+use render::{Mesh, MeshBuilder};
+use world::World;
+use light::{Light, Lighting, DEFAULT_AMBIENT, MAX_LIGHTS};
+use texture::Texture;
+use mesh_file;
+use physics::{RigidBody, DEFAULT_FRICTION, DEFAULT_RESTITUTION};
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::str::{FromStr, SplitWhitespace};
+
+/// The default field of view of the camera, in degrees.
+pub const DEFAULT_FOV: f32 = 90.0;
+
+/// A description of how a mesh was made, so that it can be saved and made again.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MeshDesc {
+	Triangle(f32),
+	Square(f32),
+	Rectangle(f32, f32, Vec3),
+	RectTorus(f32, f32, f32),
+	Planes(u32, u32, f32, f32, Vec3, Vec3),
+	Cuboid(f32, f32, f32, Vec3),
+	/// A mesh loaded from a file. The path is relative to the level.
+	File(String),
+}
+impl MeshDesc {
+	/// Builds the mesh. `dir` is the directory that file paths are relative to.
+	pub fn build(&self, dir: &Path) -> Result<Mesh, String> {
+		Ok(try!(self.to_builder(dir)).finish())
+	}
+	
+	/// Builds the mesh on the CPU only. `dir` is the directory that file paths are relative to.
+	pub fn to_builder(&self, dir: &Path) -> Result<MeshBuilder, String> {
+		Ok(match *self {
+			MeshDesc::Triangle(scale) => MeshBuilder::new_triangle(scale),
+			MeshDesc::Square(scale) => MeshBuilder::new_square(scale),
+			MeshDesc::Rectangle(w, h, color) => MeshBuilder::new_rectangle(w, h, color),
+			MeshDesc::RectTorus(w, h, d) => MeshBuilder::new_rect_torus(w, h, d),
+			MeshDesc::Planes(num_w, num_h, w, h, color1, color2) => MeshBuilder::new_planes(num_w, num_h, w, h, color1, color2),
+			MeshDesc::Cuboid(w, h, d, color) => MeshBuilder::new_cuboid(w, h, d, color),
+			MeshDesc::File(ref path) => try!(mesh_file::load(&dir.join(path))).to_builder(),
+		})
+	}
+	
+	fn write_to(&self, s: &mut String) {
+		match *self {
+			MeshDesc::Triangle(scale) => s.push_str(&format!("triangle {}", scale)),
+			MeshDesc::Square(scale) => s.push_str(&format!("square {}", scale)),
+			MeshDesc::Rectangle(w, h, c) => s.push_str(&format!("rectangle {} {}  {} {} {}", w, h, c.x, c.y, c.z)),
+			MeshDesc::RectTorus(w, h, d) => s.push_str(&format!("rect_torus {} {} {}", w, h, d)),
+			MeshDesc::Planes(num_w, num_h, w, h, c1, c2) => s.push_str(&format!("planes {} {} {} {}  {} {} {}  {} {} {}", num_w, num_h, w, h, c1.x, c1.y, c1.z, c2.x, c2.y, c2.z)),
+			MeshDesc::Cuboid(w, h, d, c) => s.push_str(&format!("cuboid {} {} {}  {} {} {}", w, h, d, c.x, c.y, c.z)),
+			MeshDesc::File(ref path) => s.push_str(&format!("file {}", path)),
+		}
+	}
+}
+
+/// The tokens on one line of a level file.
+struct Tokens<'a> {
+	iter: SplitWhitespace<'a>,
+}
+impl<'a> Tokens<'a> {
+	fn next_str(&mut self, what: &str) -> Result<&'a str, String> {
+		match self.iter.next() {
+			Some(s) => Ok(s),
+			None => Err(format!("expected {}, but the line ended", what)),
+		}
+	}
+	
+	fn next<T: FromStr>(&mut self, what: &str) -> Result<T, String> where T::Err: Display {
+		let s = try!(self.next_str(what));
+		match s.parse() {
+			Ok(v) => Ok(v),
+			Err(e) => Err(format!("expected {}, but found '{}' ({})", what, s, e)),
+		}
+	}
+	
+	fn next_vec3(&mut self, what: &str) -> Result<Vec3, String> {
+		let x: f32 = try!(self.next(&format!("{} x", what)));
+		let y: f32 = try!(self.next(&format!("{} y", what)));
+		let z: f32 = try!(self.next(&format!("{} z", what)));
+		if !x.is_finite() || !y.is_finite() || !z.is_finite() {
+			return Err(format!("{} must be finite, but is {} {} {}", what, x, y, z));
+		}
+		Ok(Vec3::new(x, y, z))
+	}
+	
+	fn next_color(&mut self, what: &str) -> Result<Vec3, String> {
+		let r = try!(self.next(&format!("{} red", what)));
+		let g = try!(self.next(&format!("{} green", what)));
+		let b = try!(self.next(&format!("{} blue", what)));
+		Ok(Vec3::new(r, g, b))
+	}
+	
+	fn next_mesh(&mut self) -> Result<MeshDesc, String> {
+		let kind = try!(self.next_str("a mesh"));
+		Ok(match kind {
+			"triangle" => MeshDesc::Triangle(try!(self.next("triangle scale"))),
+			"square" => MeshDesc::Square(try!(self.next("square scale"))),
+			"rectangle" => MeshDesc::Rectangle(
+				try!(self.next("rectangle width")),
+				try!(self.next("rectangle height")),
+				try!(self.next_color("rectangle color"))),
+			"rect_torus" => MeshDesc::RectTorus(
+				try!(self.next("torus width")),
+				try!(self.next("torus height")),
+				try!(self.next("torus depth"))),
+			"planes" => MeshDesc::Planes(
+				try!(self.next("number of planes across")),
+				try!(self.next("number of planes down")),
+				try!(self.next("planes width")),
+				try!(self.next("planes height")),
+				try!(self.next_color("first planes color")),
+				try!(self.next_color("second planes color"))),
+			"cuboid" => MeshDesc::Cuboid(
+				try!(self.next("cuboid width")),
+				try!(self.next("cuboid height")),
+				try!(self.next("cuboid depth")),
+				try!(self.next_color("cuboid color"))),
+			"file" => MeshDesc::File(String::from(try!(self.next_str("a mesh file path")))),
+			_ => return Err(format!("unknown mesh type '{}'", kind)),
+		})
+	}
+	
+	fn finish(&mut self) -> Result<(), String> {
+		match self.iter.next() {
+			Some(s) => Err(format!("unexpected '{}' at the end of the line", s)),
+			None => Ok(()),
+		}
+	}
+}
+
+/// The objects read from a level so far.
+struct Parser<'a> {
+	dir: &'a Path,
+	camera: Option<Camera>,
+	entities: Vec<Entity>,
+	portals: Vec<Portal>,
+	last_portal_line: usize,
+	ambient: Option<Vec3>,
+	lights: Vec<Light>,
+	/// Textures that have been loaded, by path, so that entities with the same texture share it.
+	textures: HashMap<String, Texture>,
+}
+impl<'a> Parser<'a> {
+	/// Reads the texture at the end of a static entity line, if there is one, and gives it to the entity.
+	fn parse_texture(&mut self, tokens: &mut Tokens, ent: &mut Entity) -> Result<(), String> {
+		match tokens.iter.next() {
+			Some("texture") => self.parse_texture_path(tokens, ent),
+			Some(s) => Err(format!("expected 'texture', but found '{}'", s)),
+			None => Ok(()),
+		}
+	}
+	
+	fn parse_texture_path(&mut self, tokens: &mut Tokens, ent: &mut Entity) -> Result<(), String> {
+		let path = try!(tokens.next_str("a texture path"));
+		if !self.textures.contains_key(path) {
+			let texture = try!(Texture::load(&self.dir.join(path)));
+			self.textures.insert(String::from(path), texture);
+		}
+		ent.set_texture(String::from(path), self.textures[path].clone());
+		Ok(())
+	}
+	
+	/// Reads the options at the end of a dynamic entity line, and gives them to the entity.
+	fn parse_dynamic_options(&mut self, tokens: &mut Tokens, ent: &mut Entity) -> Result<(), String> {
+		while let Some(option) = tokens.iter.next() {
+			if option == "texture" {
+				try!(self.parse_texture_path(tokens, ent));
+				continue;
+			}
+			if option == "rotation" {
+				ent.set_rot(Rot3::new(try!(tokens.next_vec3("entity rotation"))));
+				continue;
+			}
+			let body = match ent.get_body_mut() {
+				Some(body) => body,
+				None => return Err(String::from("the mesh has no vertices, so it can't be a rigid body")),
+			};
+			match option {
+				"angular_velocity" => body.ang_vel = try!(tokens.next_vec3("entity angular velocity")),
+				"mass" => {
+					let mass: f32 = try!(tokens.next("entity mass"));
+					if !(mass > 0.0) || !mass.is_finite() {
+						return Err(format!("mass must be positive, but is {}", mass));
+					}
+					body.set_mass(mass);
+				},
+				"friction" => {
+					let friction: f32 = try!(tokens.next("entity friction"));
+					if !(friction >= 0.0) || !friction.is_finite() {
+						return Err(format!("friction must be zero or more, but is {}", friction));
+					}
+					body.friction = friction;
+				},
+				"restitution" => {
+					let restitution: f32 = try!(tokens.next("entity restitution"));
+					if !(restitution >= 0.0 && restitution <= 1.0) {
+						return Err(format!("restitution must be between 0 and 1, but is {}", restitution));
+					}
+					body.restitution = restitution;
+				},
+				_ => return Err(format!("unknown dynamic entity option '{}'", option)),
+			}
+		}
+		Ok(())
+	}
+	
+	
+	fn parse_line(&mut self, line: &str, line_num: usize) -> Result<(), String> {
+		let line = match line.find('#') {
+			Some(i) => &line[..i],
+			None => line,
+		};
+		let mut tokens = Tokens { iter: line.split_whitespace() };
+		let kind = match tokens.iter.next() {
+			Some(kind) => kind,
+			None => return Ok(()), // Blank line
+		};
+		match kind {
+			"camera" => {
+				if self.camera.is_some() {
+					return Err(String::from("there can only be one camera"));
+				}
+				let pos = try!(tokens.next_vec3("camera position"));
+				let (mut xrot, mut yrot, mut fov) = (0.0, 0.0, DEFAULT_FOV);
+				if tokens.iter.clone().next().is_some() {
+					xrot = try!(tokens.next("camera x rotation"));
+					yrot = try!(tokens.next("camera y rotation"));
+					if tokens.iter.clone().next().is_some() {
+						fov = try!(tokens.next("camera field of view"));
+					}
+				}
+				let mut cam = Camera::new(pos, fov);
+				cam.set_rotation(xrot, yrot);
+				self.camera = Some(cam);
+			},
+			"static" => {
+				let pos = try!(tokens.next_vec3("entity position"));
+				let desc = try!(tokens.next_mesh());
+				let mb = try!(desc.to_builder(self.dir));
+				let mut ent = Entity::new_static(pos, mb.finish());
+				ent.set_collision_mesh(mb.to_trimesh());
+				ent.set_mesh_desc(desc);
+				try!(self.parse_texture(&mut tokens, &mut ent));
+				self.entities.push(ent);
+			},
+			"dynamic" => {
+				let pos = try!(tokens.next_vec3("entity position"));
+				let vel = try!(tokens.next_vec3("entity velocity"));
+				let desc = try!(tokens.next_mesh());
+				let mut ent = Entity::new(pos, vel, try!(desc.build(self.dir)));
+				ent.set_mesh_desc(desc);
+				try!(self.parse_dynamic_options(&mut tokens, &mut ent));
+				self.entities.push(ent);
+			},
+			"portal" => {
+				if self.portals.len() >= 2 {
+					return Err(String::from("there can only be two portals"));
+				}
+				let pos = try!(tokens.next_vec3("portal position"));
+				let rot = try!(tokens.next_vec3("portal rotation"));
+				let w: f32 = try!(tokens.next("portal width"));
+				let h: f32 = try!(tokens.next("portal height"));
+				if !(w > 0.0 && h > 0.0) || !w.is_finite() || !h.is_finite() {
+					return Err(format!("portal size must be positive, but is {}x{}", w, h));
+				}
+				self.portals.push(Portal::new(pos, Rot3::new(rot), w, h));
+				self.last_portal_line = line_num;
+			},
+			"ambient" => {
+				if self.ambient.is_some() {
+					return Err(String::from("there can only be one ambient light"));
+				}
+				self.ambient = Some(try!(tokens.next_color("ambient light color")));
+			},
+			"light" => {
+				if self.lights.len() >= MAX_LIGHTS {
+					return Err(format!("there can only be {} lights", MAX_LIGHTS));
+				}
+				let light = match try!(tokens.next_str("a light type")) {
+					"directional" => {
+						let dir = try!(tokens.next_vec3("light direction"));
+						if dir.norm() == 0.0 {
+							return Err(String::from("light direction can't be zero"));
+						}
+						Light::Directional { dir: dir.normalize(), color: try!(tokens.next_color("light color")) }
+					},
+					"point" => {
+						let pos = try!(tokens.next_vec3("light position"));
+						let color = try!(tokens.next_color("light color"));
+						let range: f32 = try!(tokens.next("light range"));
+						if !(range > 0.0) || !range.is_finite() {
+							return Err(format!("light range must be positive, but is {}", range));
+						}
+						Light::Point { pos: pos, color: color, range: range }
+					},
+					kind => return Err(format!("unknown light type '{}'", kind)),
+				};
+				self.lights.push(light);
+			},
+			_ => return Err(format!("unknown object '{}'", kind)),
+		}
+		tokens.finish()
+	}
+	
+	fn finish(self) -> Result<World, String> {
+		let mut world = match self.camera {
+			Some(cam) => World::new(cam),
+			None => return Err(String::from("no camera in level")),
+		};
+		world.entities = self.entities;
+		if self.ambient.is_some() || self.lights.len() > 0 {
+			world.lighting = Lighting {
+				ambient: self.ambient.unwrap_or(DEFAULT_AMBIENT),
+				lights: self.lights,
+			};
+		}
+		match self.portals.len() {
+			0 => {},
+			2 => world.set_portals(self.portals[0].clone(), self.portals[1].clone()),
+			_ => return Err(format!("line {}: portals must come in pairs", self.last_portal_line)),
+		}
+		Ok(world)
+	}
+}
+
+/// Parses a level from a string. `dir` is the directory that mesh files are relative to.
+pub fn parse(src: &str, dir: &Path) -> Result<World, String> {
+	let mut parser = Parser {
+		dir: dir,
+		camera: None,
+		entities: Vec::new(),
+		portals: Vec::new(),
+		last_portal_line: 0,
+		ambient: None,
+		lights: Vec::new(),
+		textures: HashMap::new(),
+	};
+	for (i, line) in src.lines().enumerate() {
+		if let Err(e) = parser.parse_line(line, i + 1) {
+			return Err(format!("line {}: {}", i + 1, e));
+		}
+	}
+	parser.finish()
+}
+
+/// Writes the world out in the level format.
+pub fn write(world: &World) -> Result<String, String> {
+	let mut s = String::new();
+	let cam = &world.camera;
+	let p = cam.get_pos();
+	s.push_str(&format!("camera {} {} {}  {} {}  {}\n", p.x, p.y, p.z, cam.get_xrot(), cam.get_yrot(), cam.get_fov().to_degrees()));
+	
+	for (i, ent) in world.entities.iter().enumerate() {
+		let desc = match ent.get_mesh_desc() {
+			Some(desc) => desc,
+			None => return Err(format!("entity {} can't be saved as it was not created from a mesh description", i)),
+		};
+		match ent.get_type() {
+			EntityType::Static => s.push_str(&format!("static {} {} {}  ", ent.pos.x, ent.pos.y, ent.pos.z)),
+			EntityType::Dynamic => s.push_str(&format!("dynamic {} {} {}  {} {} {}  ", ent.pos.x, ent.pos.y, ent.pos.z, ent.vel.x, ent.vel.y, ent.vel.z)),
+		}
+		desc.write_to(&mut s);
+		if let Some(path) = ent.get_texture_path() {
+			s.push_str(&format!("  texture {}", path));
+		}
+		if let Some(body) = ent.get_body() {
+			write_body(&mut s, ent.get_rot(), body);
+		}
+		s.push('\n');
+	}
+	
+	if let Some((p1, p2)) = world.get_portals() {
+		for p in [p1, p2].iter() {
+			let rot = p.rot.rotation();
+			s.push_str(&format!("portal {} {} {}  {} {} {}  {} {}\n", p.pos.x, p.pos.y, p.pos.z, rot.x, rot.y, rot.z, p.get_w(), p.get_h()));
+		}
+	}
+	
+	let a = world.lighting.ambient;
+	s.push_str(&format!("ambient {} {} {}\n", a.x, a.y, a.z));
+	for light in world.lighting.lights.iter() {
+		match *light {
+			Light::Directional { dir, color: c } => s.push_str(&format!("light directional {} {} {}  {} {} {}\n", dir.x, dir.y, dir.z, c.x, c.y, c.z)),
+			Light::Point { pos, color: c, range } => s.push_str(&format!("light point {} {} {}  {} {} {}  {}\n", pos.x, pos.y, pos.z, c.x, c.y, c.z, range)),
+		}
+	}
+	Ok(s)
+}
+
+/// Writes the options of a rigid body. The mass is always written, as its default depends on the mesh.
+fn write_body(s: &mut String, rot: Rot3, body: &RigidBody) {
+	let r = rot.rotation();
+	if r != Vec3::new(0.0, 0.0, 0.0) {
+		s.push_str(&format!("  rotation {} {} {}", r.x, r.y, r.z));
+	}
+	let w = body.ang_vel;
+	if w != Vec3::new(0.0, 0.0, 0.0) {
+		s.push_str(&format!("  angular_velocity {} {} {}", w.x, w.y, w.z));
+	}
+	s.push_str(&format!("  mass {}", body.get_mass()));
+	if body.friction != DEFAULT_FRICTION {
+		s.push_str(&format!("  friction {}", body.friction));
+	}
+	if body.restitution != DEFAULT_RESTITUTION {
+		s.push_str(&format!("  restitution {}", body.restitution));
+	}
+}
+
+/// Loads a level from a file.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<World, String> {
+	let path = path.as_ref();
+	let mut file = match File::open(path) {
+		Ok(f) => f,
+		Err(e) => return Err(format!("error opening level '{}': {}", path.display(), e)),
+	};
+	let mut src = String::new();
+	match file.read_to_string(&mut src) {
+		Ok(_) => {},
+		Err(e) => return Err(format!("error reading level '{}': {}", path.display(), e)),
+	}
+	let dir = match path.parent() {
+		Some(dir) => dir.to_path_buf(),
+		None => PathBuf::new(),
+	};
+	match parse(&src, &dir) {
+		Ok(world) => Ok(world),
+		Err(e) => Err(format!("error loading level '{}': {}", path.display(), e)),
+	}
+}
+
+/// Saves a level to a file.
+pub fn save<P: AsRef<Path>>(world: &World, path: P) -> Result<(), String> {
+	let path = path.as_ref();
+	let src = match write(world) {
+		Ok(src) => src,
+		Err(e) => return Err(format!("error saving level '{}': {}", path.display(), e)),
+	};
+	let mut file = match File::create(path) {
+		Ok(f) => f,
+		Err(e) => return Err(format!("error creating level '{}': {}", path.display(), e)),
+	};
+	match file.write_all(src.as_bytes()) {
+		Ok(_) => Ok(()),
+		Err(e) => Err(format!("error writing level '{}': {}", path.display(), e)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prelude::*;
+	use world::World;
+	use entity::EntityType;
+	use light::Light;
+	use std::env;
+	use std::fs::File;
+	use std::io::prelude::*;
+	use std::path::Path;
+	
+	fn parse_crate(options: &str) -> Result<World, String> {
+		parse(&format!("camera 0 1 0\ndynamic 0 1 0  0 0 0  cuboid 0.5 0.5 0.5  1 1 1  {}", options), Path::new("."))
+	}
+	
+	#[test]
+	fn body_options_must_be_numbers_in_range() {
+		assert!(parse_crate("mass 10 friction 0.8 restitution 0.4").is_ok());
+		for options in ["mass 0", "mass -1", "mass NaN", "mass inf", "friction -0.1", "friction NaN", "friction inf",
+				"restitution -0.1", "restitution 1.1", "restitution NaN"].iter() {
+			assert!(parse_crate(options).is_err(), "'{}' was accepted", options);
+		}
+	}
+	
+	fn parse_src(src: &str) -> Result<World, String> {
+		parse(src, Path::new("."))
+	}
+	
+	#[test]
+	fn errors_give_the_line() {
+		for &(src, message) in [
+			("camera 0 1 0\n\n# a comment\nstatic 0 0 0  blob 1\n", "line 4: unknown mesh type 'blob'"),
+			("camera 0 1 0\ncamera 0 1 0\n", "line 2: there can only be one camera"),
+			("camera 0 1 0\nstatic 0 0 0  square 1  spin\n", "line 2: expected 'texture', but found 'spin'"),
+			("camera 0 1 0\nstatic 0 0 0  square 1 2\n", "line 2: expected 'texture', but found '2'"),
+			("camera 0 1 0\n\nportal 0 1 0  0 0 0  1 1\n", "line 3: portals must come in pairs"),
+			("\nstatic 0 0 0  square 1\n", "no camera in level"),
+		].iter() {
+			match parse_src(src) {
+				Ok(_) => panic!("{:?} was accepted", src),
+				Err(e) => assert!(e == message, "{:?} gave '{}'", src, e),
+			}
+		}
+	}
+	
+	#[test]
+	fn sizes_and_positions_must_be_finite() {
+		for line in ["portal 0 1 0  0 0 0  NaN 1", "portal 0 1 0  0 0 0  1 NaN", "portal 0 1 0  0 0 0  inf 1",
+				"portal 0 1 0  0 0 0  0 1", "portal NaN 1 0  0 0 0  1 1", "portal 0 1 0  0 inf 0  1 1",
+				"static 0 inf 0  square 1", "dynamic 0 1 0  NaN 0 0  square 1",
+				"light point 0 -inf 0  1 1 1  5", "light point 0 1 0  1 1 1  NaN"].iter() {
+			match parse_src(&format!("camera 0 1 0\n{}\n", line)) {
+				Ok(_) => panic!("'{}' was accepted", line),
+				Err(e) => assert!(e.starts_with("line 2: "), "'{}' gave '{}'", line, e),
+			}
+		}
+		match parse_src("camera NaN 1 0\n") {
+			Ok(_) => panic!("a NaN camera position was accepted"),
+			Err(e) => assert_eq!(e, "line 1: camera position must be finite, but is NaN 1 0"),
+		}
+	}
+	
+	const LEVEL: &'static str = "
+camera 0.5 1 -2  0.25 -0.5  75
+static 0 0 0  planes 4 4 10 10  1 1 1  0.2 0.2 0.2
+static 0 1.5 5  rectangle 4 3  0.8 0.6 0.4
+dynamic 1.3 2.5 2.0  0.1 0 -0.2  cuboid 0.3 0.3 0.3  0.3 0.5 0.8  rotation 0.3 0 0.4  angular_velocity 0 1 0  mass 2.5  friction 0.2  restitution 0.4
+portal  0.0 1 4.0  0 0 0  0.9 1.4
+portal -1.5 1 2.5  0 3.14159 0  0.9 1.4
+ambient 0.1 0.1 0.2
+light directional 1 -2 0.5  0.9 0.9 0.8
+light point 0 2 1  1 0.5 0.25  6
+";
+	
+	fn approx_eq(a: Vec3, b: Vec3) -> bool {
+		(a - b).norm() < 1e-5
+	}
+	
+	#[test]
+	fn save_and_load() {
+		let world = parse_src(LEVEL).unwrap();
+		let path = env::temp_dir().join("level_round_trip.lvl");
+		world.save(&path).unwrap();
+		let mut src = String::new();
+		File::open(&path).unwrap().read_to_string(&mut src).unwrap();
+		let loaded = parse_src(&src).unwrap();
+		
+		let (a, b) = (&world.camera, &loaded.camera);
+		assert_eq!(a.get_pos(), b.get_pos());
+		assert!((a.get_xrot() - b.get_xrot()).abs() < 1e-6 && (a.get_yrot() - b.get_yrot()).abs() < 1e-6);
+		assert!((a.get_fov() - b.get_fov()).abs() < 1e-6);
+		
+		assert_eq!(world.entities.len(), loaded.entities.len());
+		for (a, b) in world.entities.iter().zip(loaded.entities.iter()) {
+			assert!(a.get_type() == b.get_type());
+			assert_eq!(a.get_mesh_desc(), b.get_mesh_desc());
+			assert_eq!(a.pos, b.pos);
+			assert_eq!(a.vel, b.vel);
+			assert!(approx_eq(a.get_rot().rotation(), b.get_rot().rotation()));
+			match (a.get_body(), b.get_body()) {
+				(Some(a), Some(b)) => {
+					assert_eq!(a.ang_vel, b.ang_vel);
+					assert_eq!(a.get_mass(), b.get_mass());
+					assert_eq!(a.friction, b.friction);
+					assert_eq!(a.restitution, b.restitution);
+				},
+				(None, None) => {},
+				_ => panic!("only one of the entities has a rigid body"),
+			}
+		}
+		assert!(world.entities[2].get_type() == EntityType::Dynamic);
+		assert_eq!(loaded.entities[2].get_body().unwrap().get_mass(), 2.5);
+		
+		let ((a1, a2), (b1, b2)) = (world.get_portals().unwrap(), loaded.get_portals().unwrap());
+		for &(a, b) in [(&a1, &b1), (&a2, &b2)].iter() {
+			assert_eq!(a.pos, b.pos);
+			assert!(approx_eq(a.rot.rotation(), b.rot.rotation()));
+			assert_eq!((a.get_w(), a.get_h()), (b.get_w(), b.get_h()));
+		}
+		
+		assert_eq!(world.lighting.ambient, loaded.lighting.ambient);
+		assert_eq!(world.lighting.lights.len(), 2);
+		assert_eq!(world.lighting.lights.len(), loaded.lighting.lights.len());
+		for (a, b) in world.lighting.lights.iter().zip(loaded.lighting.lights.iter()) {
+			match (*a, *b) {
+				(Light::Directional { dir: d1, color: c1 }, Light::Directional { dir: d2, color: c2 }) => {
+					assert!(approx_eq(d1, d2));
+					assert_eq!(c1, c2);
+				},
+				(Light::Point { .. }, Light::Point { .. }) => assert_eq!(*a, *b),
+				_ => panic!("lights {:?} and {:?} are different types", a, b),
+			}
+		}
+	}
+}
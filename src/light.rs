@@ -0,0 +1,79 @@
+//! Lights, and the lighting of a world.
+use prelude::*;
+
+/// The most lights that can affect the world at once. This must match `MAX_LIGHTS` in `shaders/lighting.glsl`.
+pub const MAX_LIGHTS: usize = 8;
+
+/// How sharp the highlights on surfaces are. This must match `SHININESS` in `shaders/lighting.glsl`.
+pub const SHININESS: f32 = 32.0;
+/// How bright the highlights on surfaces are. This must match `SPECULAR` in `shaders/lighting.glsl`.
+pub const SPECULAR: f32 = 0.25;
+
+/// The ambient light of worlds that don't set their own lighting.
+pub const DEFAULT_AMBIENT: Vec3 = Vec3 { x: 0.35, y: 0.35, z: 0.35 };
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Light {
+	/// A light infinitely far away, such as the sun, shining in direction `dir`.
+	Directional { dir: Vec3, color: Vec3 },
+	/// A light at `pos` that shines in every direction, fading out to nothing at `range`.
+	Point { pos: Vec3, color: Vec3, range: f32 },
+}
+
+/// All of the lights in a world.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lighting {
+	/// Light that reaches every surface equally, so that nothing is completely black.
+	pub ambient: Vec3,
+	pub lights: Vec<Light>,
+}
+impl Lighting {
+	/// Creates lighting with nothing but ambient light.
+	pub fn new(ambient: Vec3) -> Lighting {
+		Lighting {
+			ambient: ambient,
+			lights: Vec::new(),
+		}
+	}
+	
+	/// The lighting of worlds that don't set their own: ambient light, and a light shining down at an angle
+	/// so that surfaces facing different ways can be told apart.
+	pub fn default() -> Lighting {
+		let mut lighting = Lighting::new(DEFAULT_AMBIENT);
+		lighting.lights.push(Light::Directional {
+			dir: Vec3::new(0.3, -1.0, 0.5).normalize(),
+			color: Vec3::new(0.65, 0.65, 0.65),
+		});
+		lighting
+	}
+	
+	/// Lights a surface of colour `albedo` at `pos`, facing `normal`, seen from `cam_pos`, all in world space.
+	/// This does the same as `light_surface` in `shaders/lighting.glsl`, for drawing on the CPU.
+	pub fn light_surface(&self, cam_pos: Vec3, albedo: Vec3, pos: Vec3, normal: Vec3) -> Vec3 {
+		let to_cam = (cam_pos - pos).normalize();
+		let mut color = self.ambient * albedo;
+		
+		for light in self.lights.iter().take(MAX_LIGHTS) {
+			let (to_light, light_color, strength) = match *light {
+				Light::Directional { dir, color } => (-dir.normalize(), color, 1.0),
+				Light::Point { pos: light_pos, color, range } => {
+					let d = light_pos - pos;
+					let dist = d.norm();
+					let falloff = (1.0 - dist / range).max(0.0).min(1.0);
+					(d / dist.max(0.0001), color, falloff * falloff)
+				},
+			};
+			
+			// Lambert diffuse, and Blinn-Phong specular
+			let diffuse = normal.dot(&to_light).max(0.0);
+			let specular = if diffuse > 0.0 {
+				let halfway = (to_light + to_cam).normalize();
+				normal.dot(&halfway).max(0.0).powf(SHININESS) * SPECULAR
+			} else {
+				0.0
+			};
+			color = color + (albedo * diffuse + Vec3::new(specular, specular, specular)) * light_color * strength;
+		}
+		color
+	}
+}
@@ -4,19 +4,49 @@ extern crate nalgebra as na;
 //extern crate rand;
 extern crate sdl2;
 extern crate gl;
+extern crate rustc_serialize;
+extern crate png;
 
 pub mod world;
 pub mod entity;
 pub mod game;
 pub mod render;
+pub mod input;
+pub mod headless;
+pub mod level;
+pub mod player;
+pub mod portal_gun;
+pub mod replay;
+pub mod actions;
+pub mod controller;
+pub mod mesh_file;
+pub mod obj;
+pub mod gltf;
+pub mod light;
+pub mod texture;
+pub mod shader_source;
+pub mod assets;
+pub mod uniform;
+pub mod draw_list;
+pub mod soft_render;
+pub mod golden;
+pub mod capture;
+pub mod physics;
 
-use render::{Render, Mesh};
+use render::Render;
 use world::World;
 use game::Game;
-use entity::{Entity, Camera, Portal};
+use input::HeldKeys;
+use replay::Recording;
+use actions::ActionMap;
+use controller::Devices;
+
+use std::env;
+use std::process;
+use std::path::{Path, PathBuf};
 
 use sdl2::Sdl;
-use sdl2::keyboard::KeyboardState;
+use sdl2::keyboard::{KeyboardState, Scancode};
 
 pub type DT = f32;
 
@@ -88,7 +118,271 @@ pub fn get_rotation_between(a: Vec3, b: Vec3) -> Rot3 {
 	Rot3::new(a.cross(&b) * a.dot(&b).acos())
 }
 
+/// Options given on the command line.
+pub struct Options {
+	/// The level file to load.
+	pub level: String,
+	/// If set, run this many ticks without a window and print the final state of the world.
+	pub headless_ticks: Option<u32>,
+	/// Keys held down for the whole of a headless run.
+	pub held_keys: HeldKeys,
+	pub tick_rate: u32,
+	/// If set, the input of the session is recorded and saved to this file on quit.
+	pub record: Option<String>,
+	/// If set, this recording is replayed without a window and the final state of the world is printed.
+	pub replay: Option<String>,
+	/// The file the controls are loaded from.
+	pub controls: String,
+	/// The directory that shaders, and the default level and controls, are loaded from. Found automatically if not given.
+	pub assets: Option<String>,
+	/// If set, a frame is drawn without a window by the software renderer and saved to this PNG file.
+	pub render: Option<String>,
+	/// If set, the golden-image tests are run, and the frames that fail are saved to this directory.
+	pub golden: Option<String>,
+	/// If true, the reference images of the golden-image tests are drawn again.
+	pub update_golden: bool,
+	/// If set, the first frame drawn in the window is saved to this PNG file.
+	pub screenshot: Option<String>,
+	/// If set, every tick of a headless run or replay is drawn and saved as a numbered PNG in this directory.
+	pub capture: Option<String>,
+}
+impl Options {
+	pub fn parse<I: Iterator<Item=String>>(mut args: I) -> Result<Options, String> {
+		let mut opts = Options {
+			level: String::from(DEFAULT_LEVEL),
+			headless_ticks: None,
+			held_keys: HeldKeys::new(),
+			tick_rate: game::DEFAULT_TICK_RATE,
+			record: None,
+			replay: None,
+			controls: String::from(DEFAULT_CONTROLS),
+			assets: None,
+			render: None,
+			golden: None,
+			update_golden: false,
+			screenshot: None,
+			capture: None,
+		};
+		while let Some(arg) = args.next() {
+			match &arg[..] {
+				"--level" => {
+					opts.level = try!(Options::next_value(&mut args, &arg));
+				},
+				"--headless" => {
+					let ticks = try!(Options::next_value(&mut args, &arg));
+					opts.headless_ticks = match ticks.parse() {
+						Ok(n) => Some(n),
+						Err(e) => return Err(format!("invalid number of ticks '{}': {}", ticks, e)),
+					};
+				},
+				"--hold" => {
+					let keys = try!(Options::next_value(&mut args, &arg));
+					for name in keys.split(',') {
+						match Scancode::from_name(name) {
+							Some(key) => opts.held_keys.press(key),
+							None => return Err(format!("unknown key '{}'", name)),
+						}
+					}
+				},
+				"--tick-rate" => {
+					let rate = try!(Options::next_value(&mut args, &arg));
+					opts.tick_rate = match rate.parse() {
+						Ok(n) if n > 0 => n,
+						Ok(_) => return Err(String::from("tick rate must be above 0")),
+						Err(e) => return Err(format!("invalid tick rate '{}': {}", rate, e)),
+					};
+				},
+				"--record" => {
+					opts.record = Some(try!(Options::next_value(&mut args, &arg)));
+				},
+				"--replay" => {
+					opts.replay = Some(try!(Options::next_value(&mut args, &arg)));
+				},
+				"--controls" => {
+					opts.controls = try!(Options::next_value(&mut args, &arg));
+				},
+				"--assets" => {
+					opts.assets = Some(try!(Options::next_value(&mut args, &arg)));
+				},
+				"--render" => {
+					opts.render = Some(try!(Options::next_value(&mut args, &arg)));
+				},
+				"--golden" => {
+					opts.golden = Some(try!(Options::next_value(&mut args, &arg)));
+				},
+				"--update-golden" => {
+					opts.update_golden = true;
+				},
+				"--screenshot" => {
+					opts.screenshot = Some(try!(Options::next_value(&mut args, &arg)));
+				},
+				"--capture" => {
+					opts.capture = Some(try!(Options::next_value(&mut args, &arg)));
+				},
+				_ => return Err(format!("unknown argument '{}'", arg)),
+			}
+		}
+		if opts.capture.is_some() && opts.replay.is_none() && opts.headless_ticks.is_none() {
+			return Err(String::from("--capture needs --replay or --headless"));
+		}
+		Ok(opts)
+	}
+	
+	/// Gets the asset root directory.
+	pub fn get_asset_root(&self) -> PathBuf {
+		match self.assets {
+			Some(ref dir) => PathBuf::from(dir),
+			None => assets::find_root(),
+		}
+	}
+	
+	/// Gets the path of the level to load. The default level is in the asset root.
+	pub fn get_level_path(&self) -> PathBuf {
+		if self.level == DEFAULT_LEVEL {
+			self.get_asset_root().join(DEFAULT_LEVEL)
+		} else {
+			PathBuf::from(&self.level)
+		}
+	}
+	
+	/// Gets the path of the controls file. The default controls file is in the asset root.
+	pub fn get_controls_path(&self) -> PathBuf {
+		if self.controls == DEFAULT_CONTROLS {
+			self.get_asset_root().join(DEFAULT_CONTROLS)
+		} else {
+			PathBuf::from(&self.controls)
+		}
+	}
+	
+	fn next_value<I: Iterator<Item=String>>(args: &mut I, arg: &str) -> Result<String, String> {
+		match args.next() {
+			Some(v) => Ok(v),
+			None => Err(format!("expected a value after '{}'", arg)),
+		}
+	}
+}
+
+pub fn print_usage() {
+	println!("usage: Portal [options]");
+	println!("    --level <path>      the level to load (default {})", DEFAULT_LEVEL);
+	println!("    --headless <ticks>  run the given number of ticks without a window, then print the world");
+	println!("    --hold <keys>       comma seperated keys to hold down during a headless run, e.g. W,LShift");
+	println!("    --tick-rate <hz>    number of ticks simulated per second (default {})", game::DEFAULT_TICK_RATE);
+	println!("    --record <path>     record the input of every tick to a file, saved on quit");
+	println!("    --replay <path>     replay a recording without a window, then print the world");
+	println!("    --controls <path>   the file the controls are loaded from (default {} in the asset directory)", DEFAULT_CONTROLS);
+	println!("    --assets <path>     the directory with the shaders, default level and controls (default: found next to the executable)");
+	println!("    --render <path>     draw a frame without a window or GPU and save it as a PNG, after any headless ticks");
+	println!("    --golden <dir>      draw the golden-image tests and compare them with the references, saving failures to the directory");
+	println!("    --update-golden     draw the golden-image tests again and replace the references with them");
+	println!("    --screenshot <path> save the first frame drawn in the window as a PNG");
+	println!("    --capture <dir>     save every tick of a headless run or replay as numbered PNGs in the directory");
+}
+
+/// The level that is loaded if none is given on the command line.
+pub const DEFAULT_LEVEL: &'static str = "levels/default.lvl";
+
+/// The controls file that is loaded if none is given on the command line.
+pub const DEFAULT_CONTROLS: &'static str = "controls.cfg";
+
+/// Loads the controls given in the options, exiting if they can't be loaded.
+/// The default controls are used if the default controls file doesn't exist.
+fn load_controls(opts: &Options) -> ActionMap {
+	let path = opts.get_controls_path();
+	if opts.controls == DEFAULT_CONTROLS && !path.exists() {
+		return ActionMap::new();
+	}
+	match ActionMap::load(&path) {
+		Ok(map) => map,
+		Err(e) => {
+			println!("{}", e);
+			process::exit(1);
+		}
+	}
+}
+
+/// Loads the level given in the options, exiting if it can't be loaded.
+fn load_world(opts: &Options) -> World {
+	match World::load(opts.get_level_path()) {
+		Ok(world) => world,
+		Err(e) => {
+			println!("{}", e);
+			process::exit(1);
+		}
+	}
+}
+
 fn main() {
+	let opts = match Options::parse(env::args().skip(1)) {
+		Ok(opts) => opts,
+		Err(e) => {
+			println!("error: {}", e);
+			print_usage();
+			process::exit(1);
+		}
+	};
+	
+	if let Some(ref path) = opts.replay {
+		let rec = match Recording::load(path) {
+			Ok(rec) => rec,
+			Err(e) => {
+				println!("{}", e);
+				process::exit(1);
+			}
+		};
+		if let Some(ref dir) = opts.capture {
+			if let Err(e) = headless::replay_and_capture(load_world(&opts), &rec, Path::new(dir)) {
+				println!("{}", e);
+				process::exit(1);
+			}
+			return;
+		}
+		headless::replay_and_dump(load_world(&opts), &rec);
+		return;
+	}
+	if opts.golden.is_some() || opts.update_golden {
+		let out_dir = match opts.golden {
+			Some(ref dir) => PathBuf::from(dir),
+			None => PathBuf::from("."),
+		};
+		match golden::run(&opts.get_asset_root(), &out_dir, opts.update_golden) {
+			Ok(true) => return,
+			Ok(false) => process::exit(1),
+			Err(e) => {
+				println!("{}", e);
+				process::exit(1);
+			}
+		}
+	}
+	if let Some(ref path) = opts.render {
+		let mut world = load_world(&opts);
+		if let Some(ticks) = opts.headless_ticks {
+			let actions = load_controls(&opts).get_held_actions(&opts.held_keys);
+			headless::run(&mut world, ticks, 1.0 / opts.tick_rate as DT, &actions);
+		}
+		match headless::render_to_png(&world, headless::RENDER_WIDTH, headless::RENDER_HEIGHT, Path::new(path)) {
+			Ok(()) => println!("saved a frame to '{}'", path),
+			Err(e) => {
+				println!("{}", e);
+				process::exit(1);
+			}
+		}
+		return;
+	}
+	if let Some(ticks) = opts.headless_ticks {
+		// No SDL or OpenGL - meshes stay on the CPU
+		let actions = load_controls(&opts).get_held_actions(&opts.held_keys);
+		if let Some(ref dir) = opts.capture {
+			if let Err(e) = headless::run_and_capture(load_world(&opts), ticks, 1.0 / opts.tick_rate as DT, &actions, Path::new(dir)) {
+				println!("{}", e);
+				process::exit(1);
+			}
+			return;
+		}
+		headless::run_and_dump(load_world(&opts), ticks, 1.0 / opts.tick_rate as DT, &actions);
+		return;
+	}
+	
 	let sdl = match sdl2::init() {
 		Ok(sdl) => sdl,
 		Err(s)  => panic!("sdl init error: {}", &s)
@@ -121,57 +415,63 @@ fn main() {
 	
 	gl::load_with(|name| video.gl_get_proc_address(name) as *const _);
 	
-	let mut ren = Render::new(&mut win, &mut context);
-	
-	let mut init_world = World::new(Camera::new(Vec3::new(0.0, 1.0, 0.0), 90.0));
-	init_world.entities.push(Entity::new(Vec3::new(-0.3, 0.6, 0.6), Vec3::new(0., 0./*5*/, 0./*1*/), Mesh::new_triangle(0.5)));
-	init_world.entities.push(Entity::new(Vec3::new( 0.3, 0.6, 0.6), Vec3::new(0., 0./*5*/, 0./*1*/), Mesh::new_square(0.5)));
-	let planes = Mesh::new_planes(10, 10, 10.0, 10.0, Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.0, 0.0, 0.0));
-	init_world.entities.push(Entity::new_static(Vec3::new(0.0, 0.0, 0.0), planes));
-	init_world.set_portals(
-		Portal::new(Vec3::new(0.0, 1.0, 4.0), na::Eye::new_identity(3), 0.9, 1.4),
-		Portal::new(Vec3::new(-1.5, 1.0, 2.5), na::Eye::new_identity(3), 0.9, 1.4)
-	);
+	let mut ren = Render::new(&mut win, &mut context, &opts.get_asset_root());
+	if let Some(ref path) = opts.screenshot {
+		ren.request_screenshot(PathBuf::from(path));
+	}
 	
-	let mut game = Game::new(init_world, sdl.mouse());
+	let mut game = Game::new(load_world(&opts), sdl.mouse());
+	game.set_tick_rate(opts.tick_rate);
+	game.set_action_map(load_controls(&opts));
+	match sdl.game_controller() {
+		// Controllers that are already plugged in are sent as added events, so they're opened in the main loop
+		Ok(sub) => game.set_devices(Devices::new(sub)),
+		Err(e) => println!("sdl game controller subsystem init error: {}", e),
+	}
+	if opts.record.is_some() {
+		game.start_recording();
+	}
 	main_loop(&sdl, &mut timer, &mut pump, &mut game, &mut ren);
+	
+	if let (Some(path), Some(rec)) = (opts.record.as_ref(), game.stop_recording()) {
+		match rec.save(path) {
+			Ok(()) => println!("saved {} ticks of input to '{}'", rec.ticks.len(), path),
+			Err(e) => println!("{}", e),
+		}
+	}
 }
 
 fn main_loop(sdl: &Sdl, timer: &mut sdl2::TimerSubsystem, pump: &mut sdl2::EventPump, game: &mut Game, ren: &mut Render) {
-	let mut total: u32 = 0;
-	let mut prev = timer.ticks();
-	let mut marker = prev;
+	let freq = timer.performance_frequency() as f64;
+	let mut total: f64 = 0.0;
+	let mut prev = timer.performance_counter();
+	let mut marker = timer.ticks();
 	let mut frames_since_marker = 0;
 	loop {
-		let now = timer.ticks();
-		let dur = now - prev;
-		if now as i64 - marker as i64 >= 1000 {
+		let now_ms = timer.ticks();
+		if now_ms as i64 - marker as i64 >= 1000 {
 			game.set_fps(frames_since_marker);
-			marker = now;
+			marker = now_ms;
 			frames_since_marker = 0;
+			ren.reload_changed_shaders();
 		}
+		let now = timer.performance_counter();
+		let dt: DT = ((now - prev) as f64 / freq) as DT;
 		prev = now;
 		
-		let dt: DT = dur as DT / 1_000.0;
-		/*let secs: u64 = dur.as_secs();
-		let nsecs: u32 = dur.subsec_nanos();
-		let dt: DT = (secs as DT) + ((nsecs as DT) / 1_000_000_000.0);*/
 		if !game.is_paused() {
-			total += dur;
+			total += dt as f64;
 		}
 		
-		print!("fps:{: >3}, total: {: >7.3}s, dt: {: >.3}s", game.get_fps(), (total as f64) / 1_000.0, dt);
+		print!("fps:{: >3}, total: {: >7.3}s, dt: {: >.3}s", game.get_fps(), total, dt);
 		print!(" --- ");
 		game.get_current_world().print();
 		print!(" --- ");
 		ren.print();
 		println!("");
 		
-		if !game.is_paused() {
-			game.tick(dt, &KeyboardState::new(&pump));
-		}
 		game.handle_events(sdl, pump, ren);
-		game.swap();
+		game.update(dt, &KeyboardState::new(&pump));
 		// This render can be done by a seperate thread. Probably.
 		game.render(ren);
 		frames_since_marker += 1;
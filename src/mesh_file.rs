@@ -0,0 +1,99 @@
+//! Loading of meshes from model files.
+//!
+//! Files are parsed into a `MeshData` on the CPU, which is then turned into a `MeshBuilder`. No OpenGL
+//! context is needed until the builder is finished.
+use prelude::*;
+
+use render::{Index, MeshBuilder};
+use na;
+use obj;
+use gltf;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// The colour of vertices that don't have a colour or a material.
+pub const DEFAULT_COLOR: Vec3 = Vec3 { x: 1.0, y: 1.0, z: 1.0 };
+
+/// A mesh loaded from a file, with 32-bit indices.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshData {
+	pub verts: Vec<Vec3>,
+	pub colors: Vec<Vec3>,
+	pub indices: Vec<[u32; 3]>,
+}
+impl MeshData {
+	pub fn new() -> MeshData {
+		MeshData {
+			verts: Vec::new(),
+			colors: Vec::new(),
+			indices: Vec::new(),
+		}
+	}
+	
+	pub fn push(&mut self, vert: Vec3, color: Vec3) -> u32 {
+		self.verts.push(vert);
+		self.colors.push(color);
+		self.verts.len() as u32 - 1
+	}
+	
+	/// Adds all of the vertices and triangles of `other` to this mesh.
+	pub fn append(&mut self, other: &MeshData) {
+		let offset = self.verts.len() as u32;
+		self.verts.extend(other.verts.iter().cloned());
+		self.colors.extend(other.colors.iter().cloned());
+		self.indices.extend(other.indices.iter().map(|t| [t[0] + offset, t[1] + offset, t[2] + offset]));
+	}
+	
+	/// Turns the mesh into a builder. The builder picks 16 or 32-bit indices depending on the size of the mesh.
+	pub fn to_builder(&self) -> MeshBuilder {
+		let mut mb = MeshBuilder::new();
+		for (&v, &c) in self.verts.iter().zip(self.colors.iter()) {
+			mb.push(v, c);
+		}
+		for t in self.indices.iter() {
+			mb.index(na::Vec3::new(t[0] as Index, t[1] as Index, t[2] as Index));
+		}
+		mb
+	}
+}
+
+/// Reads a whole file into a string.
+pub fn read_to_string(path: &Path) -> Result<String, String> {
+	let mut src = String::new();
+	match File::open(path).and_then(|mut f| f.read_to_string(&mut src)) {
+		Ok(_) => Ok(src),
+		Err(e) => Err(format!("error reading '{}': {}", path.display(), e)),
+	}
+}
+
+/// Reads a whole file into bytes.
+pub fn read_to_bytes(path: &Path) -> Result<Vec<u8>, String> {
+	let mut data = Vec::new();
+	match File::open(path).and_then(|mut f| f.read_to_end(&mut data)) {
+		Ok(_) => Ok(data),
+		Err(e) => Err(format!("error reading '{}': {}", path.display(), e)),
+	}
+}
+
+/// Loads a mesh from a Wavefront OBJ (`.obj`) or glTF 2.0 (`.gltf` or `.glb`) file.
+pub fn load(path: &Path) -> Result<MeshData, String> {
+	let dir = path.parent().unwrap_or(Path::new(""));
+	let ext = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+	let ret = match ext.as_ref().map(|e| &e[..]) {
+		Some("obj") => {
+			let src = try!(read_to_string(path));
+			obj::parse(&src, |name| read_to_string(&dir.join(name)))
+		},
+		Some("gltf") | Some("glb") => {
+			let data = try!(read_to_bytes(path));
+			gltf::parse(&data, |uri| read_to_bytes(&dir.join(uri)))
+		},
+		_ => return Err(format!("can't load mesh '{}': unknown file type", path.display())),
+	};
+	match ret {
+		Ok(mesh) => Ok(mesh),
+		Err(e) => Err(format!("error loading mesh '{}': {}", path.display(), e)),
+	}
+}
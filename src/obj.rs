@@ -0,0 +1,244 @@
+//! Parsing of Wavefront OBJ files, and the MTL files they use for materials.
+//!
+//! Only the parts needed for coloured triangles are read: vertex positions (with optional `r g b` vertex
+//! colours after them), faces and the diffuse colour (`Kd`) of materials. Faces with more than three
+//! vertices are split into a fan of triangles. Everything else, such as texture coordinates and normals,
+//! is ignored.
+use prelude::*;
+
+use mesh_file::{MeshData, DEFAULT_COLOR};
+
+use std::collections::HashMap;
+use std::mem;
+use std::str::SplitWhitespace;
+
+/// Parses the diffuse colours of the materials in an MTL file.
+pub fn parse_mtl(src: &str) -> Result<HashMap<String, Vec3>, String> {
+	let mut materials = HashMap::new();
+	let mut current = None;
+	for (i, line) in src.lines().enumerate() {
+		let mut tokens = line.split_whitespace();
+		let ret = match tokens.next() {
+			Some("newmtl") => {
+				let name = tokens.collect::<Vec<_>>().join(" ");
+				materials.insert(name.clone(), DEFAULT_COLOR);
+				current = Some(name);
+				Ok(())
+			},
+			Some("Kd") => match current {
+				Some(ref name) => parse_vec3(&mut tokens, "diffuse colour").map(|c| { materials.insert(name.clone(), c); }),
+				None => Err(String::from("'Kd' before any 'newmtl'")),
+			},
+			_ => Ok(()),
+		};
+		if let Err(e) = ret {
+			return Err(format!("line {}: {}", i + 1, e));
+		}
+	}
+	Ok(materials)
+}
+
+fn parse_f32(s: Option<&str>, what: &str) -> Result<f32, String> {
+	match s {
+		Some(s) => match s.parse() {
+			Ok(v) => Ok(v),
+			Err(e) => Err(format!("expected {}, but found '{}' ({})", what, s, e)),
+		},
+		None => Err(format!("expected {}, but the line ended", what)),
+	}
+}
+
+fn parse_vec3(tokens: &mut SplitWhitespace, what: &str) -> Result<Vec3, String> {
+	let x = try!(parse_f32(tokens.next(), what));
+	let y = try!(parse_f32(tokens.next(), what));
+	let z = try!(parse_f32(tokens.next(), what));
+	Ok(Vec3::new(x, y, z))
+}
+
+/// Gets the bits of a colour, so that it can be used as a key.
+fn color_key(c: Vec3) -> [u32; 3] {
+	unsafe { [mem::transmute(c.x), mem::transmute(c.y), mem::transmute(c.z)] }
+}
+
+/// The state of an OBJ file part way through being parsed.
+struct Parser<F: Fn(&str) -> Result<String, String>> {
+	read_file: F,
+	positions: Vec<Vec3>,
+	/// The colour given after each position, if any.
+	vert_colors: Vec<Option<Vec3>>,
+	materials: HashMap<String, Vec3>,
+	material: Option<Vec3>,
+	/// Vertices of the mesh, by position index and colour, so that they are shared between faces.
+	vert_map: HashMap<(usize, [u32; 3]), u32>,
+	mesh: MeshData,
+}
+impl<F: Fn(&str) -> Result<String, String>> Parser<F> {
+	fn parse_line(&mut self, line: &str) -> Result<(), String> {
+		let mut tokens = line.split_whitespace();
+		match tokens.next() {
+			Some("v") => {
+				let pos = try!(parse_vec3(&mut tokens, "a vertex position"));
+				let color = match tokens.next() {
+					Some(r) => Some(Vec3::new(
+						try!(parse_f32(Some(r), "a vertex colour")),
+						try!(parse_f32(tokens.next(), "a vertex colour")),
+						try!(parse_f32(tokens.next(), "a vertex colour")))),
+					None => None,
+				};
+				self.positions.push(pos);
+				self.vert_colors.push(color);
+			},
+			Some("f") => {
+				let mut face = Vec::new();
+				for t in tokens {
+					face.push(try!(self.get_vert(t)));
+				}
+				if face.len() < 3 {
+					return Err(String::from("a face needs at least 3 vertices"));
+				}
+				for i in 1..face.len() - 1 {
+					self.mesh.indices.push([face[0], face[i], face[i + 1]]);
+				}
+			},
+			Some("mtllib") => {
+				for name in tokens {
+					let src = try!((self.read_file)(name));
+					match parse_mtl(&src) {
+						Ok(materials) => self.materials.extend(materials.into_iter()),
+						Err(e) => return Err(format!("error in material library '{}': {}", name, e)),
+					}
+				}
+			},
+			Some("usemtl") => {
+				let name = tokens.collect::<Vec<_>>().join(" ");
+				self.material = match self.materials.get(&name) {
+					Some(&color) => Some(color),
+					None => return Err(format!("unknown material '{}'", name)),
+				};
+			},
+			_ => {},
+		}
+		Ok(())
+	}
+	
+	/// Gets the index in the mesh of a face vertex such as `3`, `3/1` or `-1//2`.
+	fn get_vert(&mut self, s: &str) -> Result<u32, String> {
+		let pos = s.split('/').next().unwrap();
+		let n: i64 = match pos.parse() {
+			Ok(n) => n,
+			Err(e) => return Err(format!("expected a vertex index, but found '{}' ({})", s, e)),
+		};
+		// Indices start at 1, and negative ones count back from the last vertex
+		let i = if n > 0 { n - 1 } else { self.positions.len() as i64 + n };
+		if i < 0 || i >= self.positions.len() as i64 {
+			return Err(format!("vertex index {} is out of range", n));
+		}
+		let i = i as usize;
+		
+		// Vertex colours take priority over materials
+		let color = self.vert_colors[i].or(self.material).unwrap_or(DEFAULT_COLOR);
+		let key = (i, color_key(color));
+		if let Some(&index) = self.vert_map.get(&key) {
+			return Ok(index);
+		}
+		let index = self.mesh.push(self.positions[i], color);
+		self.vert_map.insert(key, index);
+		Ok(index)
+	}
+}
+
+/// Parses an OBJ file. `read_file` is used to read the material libraries it refers to.
+pub fn parse<F: Fn(&str) -> Result<String, String>>(src: &str, read_file: F) -> Result<MeshData, String> {
+	let mut parser = Parser {
+		read_file: read_file,
+		positions: Vec::new(),
+		vert_colors: Vec::new(),
+		materials: HashMap::new(),
+		material: None,
+		vert_map: HashMap::new(),
+		mesh: MeshData::new(),
+	};
+	for (i, line) in src.lines().enumerate() {
+		let line = match line.find('#') {
+			Some(end) => &line[..end],
+			None => line,
+		};
+		if let Err(e) = parser.parse_line(line) {
+			return Err(format!("line {}: {}", i + 1, e));
+		}
+	}
+	Ok(parser.mesh)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prelude::*;
+	use mesh_file::DEFAULT_COLOR;
+	
+	fn no_files(name: &str) -> Result<String, String> {
+		Err(format!("no file '{}'", name))
+	}
+	
+	const SQUARE: &'static str = "
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+";
+	
+	#[test]
+	fn triangles_and_quads() {
+		let mesh = parse(&format!("{}f 1 2 3\nf 1 2 3 4\n", SQUARE), no_files).unwrap();
+		assert_eq!(mesh.verts, vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)]);
+		assert_eq!(mesh.colors, vec![DEFAULT_COLOR; 4]);
+		assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 1, 2], [0, 2, 3]]);
+	}
+	
+	#[test]
+	fn negative_and_slashed_indices() {
+		let mesh = parse(&format!("{}f -4/1/1 -3//2 -2/3\nf 4/4 1/1/1 3\n", SQUARE), no_files).unwrap();
+		assert_eq!(mesh.verts.len(), 4);
+		assert_eq!(mesh.indices, vec![[0, 1, 2], [3, 0, 2]]);
+	}
+	
+	#[test]
+	fn material_and_vertex_colours() {
+		let red = Vec3::new(1.0, 0.0, 0.0);
+		let blue = Vec3::new(0.0, 0.0, 1.0);
+		let green = Vec3::new(0.0, 1.0, 0.0);
+		let read_file = |name: &str| if name == "colours.mtl" {
+			Ok(String::from("newmtl red paint\nKd 1 0 0\nnewmtl blue\nKd 0 0 1\n"))
+		} else {
+			no_files(name)
+		};
+		let src = "mtllib colours.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0 0 1 0\nusemtl red paint\nf 1 2 3\nusemtl blue\nf 1 2 3\n";
+		let mesh = parse(src, read_file).unwrap();
+		// Each colour of a position is a different vertex, and vertex colours win over materials
+		assert_eq!(mesh.colors, vec![red, red, green, blue, blue]);
+		assert_eq!(mesh.indices, vec![[0, 1, 2], [3, 4, 2]]);
+	}
+	
+	#[test]
+	fn bad_faces_are_errors() {
+		assert!(parse(&format!("{}f 1 2 5\n", SQUARE), no_files).is_err());
+		assert!(parse(&format!("{}f 0 1 2\n", SQUARE), no_files).is_err());
+		assert!(parse(&format!("{}f -5 1 2\n", SQUARE), no_files).is_err());
+		assert!(parse(&format!("{}f 1 2\n", SQUARE), no_files).is_err());
+		assert!(parse(&format!("{}f 1 x 2\n", SQUARE), no_files).is_err());
+		assert!(parse(&format!("{}usemtl missing\n", SQUARE), no_files).is_err());
+		assert!(parse("mtllib missing.mtl\n", no_files).is_err());
+		// The line number is given
+		assert!(parse(&format!("{}f 1 2 5\n", SQUARE), no_files).unwrap_err().starts_with("line 6:"));
+	}
+	
+	#[test]
+	fn mtl_colours() {
+		let materials = parse_mtl("# comment\nnewmtl a\nKd 0.5 0.25 1\nnewmtl b\n").unwrap();
+		assert_eq!(materials.len(), 2);
+		assert_eq!(materials["a"], Vec3::new(0.5, 0.25, 1.0));
+		assert_eq!(materials["b"], DEFAULT_COLOR);
+		assert!(parse_mtl("Kd 1 0 0\n").is_err());
+		assert!(parse_mtl("newmtl a\nKd 1 0\n").is_err());
+	}
+}
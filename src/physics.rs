@@ -0,0 +1,908 @@
+//! Rigid-body dynamics for dynamic entities.
+//!
+//! Every dynamic entity has a `RigidBody`, which collides as a shape made from the vertices of its mesh: a box if
+//! they are the corners of a box centred on the origin, otherwise their convex hull. Static entities collide as
+//! their triangle meshes and never move. The mass of a body is spread evenly over its bounding box.
+//!
+//! Each tick, gravity is applied, the contacts between the shapes are found with ncollide, and impulses are worked
+//! out one contact at a time, over and over, until the bodies stop moving into each other. ncollide only gives the
+//! deepest point of each contact, so the other corners of the shape that lie on the contact plane are added, which
+//! is what lets a box rest flat instead of rocking on one corner. Static meshes are collided with one triangle at a
+//! time, so that a body touching a floor and a wall of the same mesh is held by both. Bodies that have been still
+//! for a while are put to sleep, and only wake up when something moving touches them.
+//!
+//! A body partly through a portal is in two places at once. The part on the far side of the portal doesn't
+//! collide with anything behind the portal, such as the wall that the portal is on, but is moved through to the
+//! exit portal and collides with what is around that instead, apart from the wall that the exit portal is on. So a
+//! body can rest half in and half out of a portal, held up at both ends.
+use prelude::*;
+
+use entity::{Entity, EntityType, Portal};
+use player::GRAVITY;
+
+use nc::shape::{Cuboid, Convex, Triangle};
+use nc::inspection::Repr;
+use nc::bounding_volume::{BoundingVolume, BoundingVolumeInterferencesCollector, HasBoundingVolume, AABB};
+use nc::point::PointQuery;
+use nc::geometry;
+
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+/// The density that the mass of a body is worked out from, in kg/unit^3, unless the level gives its mass.
+pub const DEFAULT_DENSITY: f32 = 200.0;
+pub const DEFAULT_FRICTION: f32 = 0.6;
+pub const DEFAULT_RESTITUTION: f32 = 0.1;
+/// Flat meshes, such as `square`, are made at least this thick, so that they have a volume to collide with.
+pub const MIN_THICKNESS: f32 = 0.05;
+
+/// Shapes closer than this count as touching, so that resting contacts aren't lost from one tick to the next.
+const PREDICTION: f32 = 0.01;
+/// How far shapes can sink into each other before they are pushed apart, so that resting bodies don't jitter.
+const SLOP: f32 = 0.005;
+/// The fraction of the overlap beyond `SLOP` that is pushed apart each tick.
+const BAUMGARTE: f32 = 0.2;
+/// Bodies that hit each other slower than this, in units/s, don't bounce, so that they can settle.
+const BOUNCE_THRESHOLD: f32 = 0.5;
+/// The number of times the impulses of every contact are worked out each tick.
+const SOLVER_ITERATIONS: usize = 10;
+/// The most points kept for each face of the contact between two shapes.
+const MAX_CONTACT_POINTS: usize = 4;
+/// Contact points closer together than this are merged.
+const MERGE_DIST: f32 = 0.02;
+/// The minimum dot product between the normals of two contact points for them to be on the same face.
+const SAME_NORMAL: f32 = 0.99;
+/// Points of a box closer than this to its bounds count as corners.
+const CORNER_EPSILON: f32 = 0.0001;
+/// Bodies slower than this, in units/s and radians/s...
+const SLEEP_SPEED: f32 = 0.05;
+const SLEEP_ANGULAR_SPEED: f32 = 0.1;
+/// ...for this many seconds are put to sleep.
+const SLEEP_TIME: f32 = 0.5;
+
+/// The shape that a body collides as, in its entity's local space.
+#[derive(Clone)]
+pub enum Collider {
+	/// A box centred on the origin.
+	Cuboid(Rc<Cuboid<Vec3>>),
+	/// The convex hull of some points.
+	Convex(Rc<Convex<Pnt3>>),
+}
+impl Collider {
+	/// Makes the shape that fits around `verts`. Returns `None` if there are no vertices.
+	pub fn from_verts(verts: &[Vec3]) -> Option<Collider> {
+		let (min, max) = match get_bounds(verts) {
+			Some(bounds) => bounds,
+			None => return None,
+		};
+		let half = (max - min) / 2.0;
+		let is_corner = |v: f32, h: f32| (v.abs() - h).abs() < CORNER_EPSILON;
+		let is_box = (min + max).norm() < CORNER_EPSILON
+			&& half.x * 2.0 >= MIN_THICKNESS && half.y * 2.0 >= MIN_THICKNESS && half.z * 2.0 >= MIN_THICKNESS
+			&& verts.iter().all(|v| is_corner(v.x, half.x) && is_corner(v.y, half.y) && is_corner(v.z, half.z));
+		if is_box {
+			return Some(Collider::Cuboid(Rc::new(Cuboid::new(half))));
+		}
+		
+		// Thicken flat meshes on both sides
+		let mut points: Vec<Vec3> = verts.to_vec();
+		let size = max - min;
+		for axis in 0..3 {
+			if size[axis] >= MIN_THICKNESS {
+				continue;
+			}
+			let mut offset = Vec3::new(0.0, 0.0, 0.0);
+			offset[axis] = (MIN_THICKNESS - size[axis]) / 2.0;
+			points = points.iter().flat_map(|&p| vec![p - offset, p + offset]).collect();
+		}
+		Some(Collider::Convex(Rc::new(Convex::new(points.iter().map(|p| p.to_pnt()).collect()))))
+	}
+	
+	/// Gets the corners of the box, or the points of the hull.
+	pub fn get_points(&self) -> Vec<Vec3> {
+		match *self {
+			Collider::Cuboid(ref s) => {
+				let h = *s.half_extents();
+				(0..8).map(|i| Vec3::new(
+					if i & 1 == 0 { -h.x } else { h.x },
+					if i & 2 == 0 { -h.y } else { h.y },
+					if i & 4 == 0 { -h.z } else { h.z })).collect()
+			},
+			Collider::Convex(ref s) => s.points().iter().map(|p| p.to_vec()).collect(),
+		}
+	}
+	
+	/// Gets the smallest and largest corners of the box around the shape, in local space.
+	pub fn get_bounds(&self) -> (Vec3, Vec3) {
+		get_bounds(&self.get_points()).unwrap_or((Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0)))
+	}
+	
+	/// Gets the point on the shape, placed at `iso`, closest to `pnt`. Points inside the shape are their own closest point.
+	pub fn project_point(&self, iso: &Iso3, pnt: &Pnt3) -> Pnt3 {
+		match *self {
+			Collider::Cuboid(ref s) => s.project_point(iso, pnt, true),
+			Collider::Convex(ref s) => s.project_point(iso, pnt, true),
+		}
+	}
+	
+	/// Finds where this shape, placed at `iso`, touches `other`, placed at `other_iso`.
+	fn contacts_with_collider(&self, iso: &Iso3, other: &Collider, other_iso: &Iso3) -> Vec<ContactPoint> {
+		match *other {
+			Collider::Cuboid(ref s) => self.contacts_with(iso, &**s, other_iso),
+			Collider::Convex(ref s) => self.contacts_with(iso, &**s, other_iso),
+		}
+	}
+	
+	/// Finds the deepest point where this shape, placed at `iso`, touches `other`, placed at `other_iso`.
+	fn find_deepest<G>(&self, iso: &Iso3, other: &G, other_iso: &Iso3) -> Option<ContactPoint>
+			where G: Repr<Pnt3, Iso3> + HasBoundingVolume<Iso3, AABB<Pnt3>> {
+		let deepest = match *self {
+			Collider::Cuboid(ref s) => geometry::contact(iso, &**s, other_iso, other, PREDICTION),
+			Collider::Convex(ref s) => geometry::contact(iso, &**s, other_iso, other, PREDICTION),
+		};
+		deepest.map(|c| ContactPoint {
+			pos: (c.world1.to_vec() + c.world2.to_vec()) / 2.0,
+			normal: c.normal,
+			depth: c.depth,
+		})
+	}
+	
+	/// Finds where this shape, placed at `iso`, touches `other`, placed at `other_iso`. The normals point from this
+	/// shape to `other`.
+	fn contacts_with<G>(&self, iso: &Iso3, other: &G, other_iso: &Iso3) -> Vec<ContactPoint>
+			where G: Repr<Pnt3, Iso3> + HasBoundingVolume<Iso3, AABB<Pnt3>> + PointQuery<Pnt3, Iso3> {
+		let deepest = match self.find_deepest(iso, other, other_iso) {
+			Some(c) => c,
+			None => return Vec::new(),
+		};
+		let normal = deepest.normal;
+		let on_other = deepest.pos - normal * (deepest.depth / 2.0);
+		let mut contacts = vec![deepest];
+		
+		// The other points of this shape that are as close to the contact plane, and are over the other shape
+		let mut candidates: Vec<(f32, Vec3)> = self.get_points().iter().filter_map(|&p| {
+			let p = iso.transform(&p.to_pnt()).to_vec();
+			let depth = (p - on_other).dot(&normal);
+			if depth > -PREDICTION { Some((depth, p)) } else { None }
+		}).collect();
+		candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+		for &(depth, p) in candidates.iter() {
+			if contacts.len() >= MAX_CONTACT_POINTS {
+				break;
+			}
+			if contacts.iter().any(|c| (c.pos - p).norm() < MERGE_DIST) {
+				continue;
+			}
+			let closest = other.project_point(other_iso, &p.to_pnt(), true).to_vec();
+			if (closest - p).norm() > depth.max(0.0) + PREDICTION {
+				continue;
+			}
+			contacts.push(ContactPoint {
+				pos: p - normal * (depth / 2.0),
+				normal: normal,
+				depth: depth,
+			});
+		}
+		contacts
+	}
+	
+	/// Finds where this shape, placed at `iso`, touches the triangles of `mesh`, placed at `mesh_iso`. Each triangle
+	/// near the shape is collided with on its own, so a shape touching two faces of the mesh touches both of them.
+	/// The contacts aren't merged, so there can be any number of them.
+	fn contacts_with_mesh(&self, iso: &Iso3, mesh: &TriMesh, mesh_iso: &Iso3) -> Vec<ContactPoint> {
+		// The box around the shape, in the space of the mesh
+		let points: Vec<Vec3> = self.get_points().iter().map(|p| mesh_iso.inv_transform(&iso.transform(&p.to_pnt())).to_vec()).collect();
+		let (min, max) = match get_bounds(&points) {
+			Some(bounds) => bounds,
+			None => return Vec::new(),
+		};
+		let bounds = AABB::new(min.to_pnt(), max.to_pnt()).loosened(PREDICTION);
+		let mut near: Vec<usize> = Vec::new();
+		mesh.bvt().visit(&mut BoundingVolumeInterferencesCollector::new(&bounds, &mut near));
+		near.iter().flat_map(|&i| self.contacts_with_triangle(iso, &mesh.triangle_at(i), mesh_iso)).collect()
+	}
+	
+	/// Finds where this shape, placed at `iso`, touches `tri`, placed at `tri_iso`. The contacts are along the normal
+	/// of the triangle, so that a shape sliding over the edge between two triangles of a flat surface doesn't catch on
+	/// it. They are the points of the shape over the triangle that are as close to its plane as the deepest point.
+	fn contacts_with_triangle(&self, iso: &Iso3, tri: &Triangle<Pnt3>, tri_iso: &Iso3) -> Vec<ContactPoint> {
+		let deepest = match self.find_deepest(iso, tri, tri_iso) {
+			Some(c) => c,
+			None => return Vec::new(),
+		};
+		let corners = [tri_iso.transform(tri.a()).to_vec(), tri_iso.transform(tri.b()).to_vec(), tri_iso.transform(tri.c()).to_vec()];
+		let face = (corners[1] - corners[0]).cross(&(corners[2] - corners[0]));
+		if face.norm() == 0.0 {
+			return vec![deepest];
+		}
+		let face = face.normalize();
+		let normal = if face.dot(&deepest.normal) < 0.0 { -face } else { face };
+		// Points just outside an edge count, so that points on the edge between two triangles aren't missed
+		let is_over = |p: Vec3| (0..3).all(|i| {
+			let (a, b) = (corners[i], corners[(i + 1) % 3]);
+			(b - a).cross(&(p - a)).dot(&face) >= -MERGE_DIST * (b - a).norm()
+		});
+		
+		let contacts: Vec<ContactPoint> = self.get_points().iter().filter_map(|&p| {
+			let p = iso.transform(&p.to_pnt()).to_vec();
+			let depth = (p - corners[0]).dot(&normal);
+			if depth > -PREDICTION && is_over(p) {
+				Some(ContactPoint {
+					pos: p - normal * (depth / 2.0),
+					normal: normal,
+					depth: depth,
+				})
+			} else {
+				None
+			}
+		}).collect();
+		if contacts.is_empty() {
+			// Only an edge or a face of the shape is over the triangle
+			vec![deepest]
+		} else {
+			contacts
+		}
+	}
+}
+
+/// Merges contact points in the same place, keeping the deepest, and keeps the deepest `MAX_CONTACT_POINTS` of the
+/// points with each normal.
+fn reduce_contacts(mut contacts: Vec<ContactPoint>) -> Vec<ContactPoint> {
+	contacts.sort_by(|a, b| b.depth.partial_cmp(&a.depth).unwrap_or(Ordering::Equal));
+	let mut kept: Vec<ContactPoint> = Vec::new();
+	for c in contacts.into_iter() {
+		let (mut count, mut merged) = (0, false);
+		for k in kept.iter().filter(|k| k.normal.dot(&c.normal) >= SAME_NORMAL) {
+			count += 1;
+			merged = merged || (k.pos - c.pos).norm() < MERGE_DIST;
+		}
+		if !merged && count < MAX_CONTACT_POINTS {
+			kept.push(c);
+		}
+	}
+	kept
+}
+
+/// Gets the smallest and largest corners of the box around `points`, or `None` if there aren't any.
+fn get_bounds(points: &[Vec3]) -> Option<(Vec3, Vec3)> {
+	let first = match points.first() {
+		Some(&p) => p,
+		None => return None,
+	};
+	Some(points.iter().fold((first, first), |(min, max), p| (
+		Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+		Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)))))
+}
+
+/// The mass, movement and material of a dynamic entity. Its linear velocity is the entity's `vel`.
+#[derive(Clone)]
+pub struct RigidBody {
+	/// The angular velocity, as axis * radians/s, in world space.
+	pub ang_vel: Vec3,
+	/// How much the body grips what it slides on, as the ratio of the friction to the force pushing them together.
+	pub friction: f32,
+	/// How much of the speed that the body hits things with it bounces back with.
+	pub restitution: f32,
+	collider: Collider,
+	/// The centre of the bounding box, in local space, which the body turns around.
+	centre: Vec3,
+	/// The size of the bounding box, at least `MIN_THICKNESS` across.
+	size: Vec3,
+	/// The radius of the sphere around the centre that the shape fits in.
+	radius: f32,
+	mass: f32,
+	sleeping: bool,
+	/// How long the body has been slow enough to sleep for, in seconds.
+	still_time: f32,
+}
+impl RigidBody {
+	/// Makes a body that collides as a shape around `verts`, with a mass from `DEFAULT_DENSITY`.
+	/// Returns `None` if there are no vertices.
+	pub fn from_verts(verts: &[Vec3]) -> Option<RigidBody> {
+		let collider = match Collider::from_verts(verts) {
+			Some(c) => c,
+			None => return None,
+		};
+		let (min, max) = collider.get_bounds();
+		let size = max - min;
+		let size = Vec3::new(size.x.max(MIN_THICKNESS), size.y.max(MIN_THICKNESS), size.z.max(MIN_THICKNESS));
+		let centre = (min + max) / 2.0;
+		let radius = collider.get_points().iter().fold(0.0, |r: f32, &p| r.max((p - centre).norm()));
+		Some(RigidBody {
+			ang_vel: Vec3::new(0.0, 0.0, 0.0),
+			friction: DEFAULT_FRICTION,
+			restitution: DEFAULT_RESTITUTION,
+			collider: collider,
+			centre: centre,
+			size: size,
+			radius: radius,
+			mass: size.x * size.y * size.z * DEFAULT_DENSITY,
+			sleeping: false,
+			still_time: 0.0,
+		})
+	}
+	
+	pub fn get_collider(&self) -> &Collider {
+		&self.collider
+	}
+	
+	/// Gets the centre of mass, in the entity's local space.
+	pub fn get_centre(&self) -> Vec3 {
+		self.centre
+	}
+	
+	/// Gets the radius of the sphere around the centre of mass that the shape fits in.
+	pub fn get_radius(&self) -> f32 {
+		self.radius
+	}
+	
+	pub fn get_mass(&self) -> f32 {
+		self.mass
+	}
+	pub fn set_mass(&mut self, mass: f32) {
+		assert!(mass > 0.0, "mass must be positive, but is {}", mass);
+		self.mass = mass;
+	}
+	
+	/// Gets the inverse of the inertia tensor around the centre of mass, in local space. The tensor of a box is
+	/// diagonal, so only the diagonal is returned.
+	fn get_inv_inertia(&self) -> Vec3 {
+		let s = self.size;
+		let k = self.mass / 12.0;
+		Vec3::new(
+			1.0 / (k * (s.y * s.y + s.z * s.z)),
+			1.0 / (k * (s.x * s.x + s.z * s.z)),
+			1.0 / (k * (s.x * s.x + s.y * s.y)))
+	}
+	
+	pub fn is_sleeping(&self) -> bool {
+		self.sleeping
+	}
+	pub fn wake(&mut self) {
+		self.sleeping = false;
+		self.still_time = 0.0;
+	}
+	
+	/// Puts the body to sleep if it has been moving at less than the sleep speeds for long enough.
+	/// Returns true if it has just fallen asleep.
+	fn update_sleep(&mut self, vel: Vec3, dt: DT) -> bool {
+		if vel.norm() >= SLEEP_SPEED || self.ang_vel.norm() >= SLEEP_ANGULAR_SPEED {
+			self.still_time = 0.0;
+			return false;
+		}
+		self.still_time += dt;
+		if self.still_time < SLEEP_TIME {
+			return false;
+		}
+		self.sleeping = true;
+		self.ang_vel = Vec3::new(0.0, 0.0, 0.0);
+		true
+	}
+	
+	/// Returns true if the body is moving, rather than sleeping or about to.
+	fn is_moving(&self) -> bool {
+		!self.sleeping && self.still_time == 0.0
+	}
+}
+
+/// A point where two shapes touch, in world space.
+#[derive(Copy, Clone, Debug)]
+struct ContactPoint {
+	pos: Vec3,
+	/// Points from the first shape to the second.
+	normal: Vec3,
+	/// How far the shapes overlap. Negative if they are apart, but close enough to count as touching.
+	depth: f32,
+}
+impl ContactPoint {
+	/// Gets the point on the surface of the second shape.
+	fn get_surface(&self) -> Vec3 {
+		self.pos - self.normal * (self.depth / 2.0)
+	}
+}
+
+/// How an entity moves while the contacts are being solved. Entities that can't be moved have no mass.
+struct Motion {
+	vel: Vec3,
+	ang_vel: Vec3,
+	/// The centre of mass, in world space.
+	centre: Vec3,
+	rot: Rot3,
+	inv_mass: f32,
+	/// The diagonal of the inverse inertia tensor, in local space.
+	inv_inertia: Vec3,
+}
+impl Motion {
+	fn new(ent: &Entity) -> Motion {
+		let rot = ent.get_rot();
+		match ent.get_body() {
+			Some(body) if !body.is_sleeping() => Motion {
+				vel: ent.vel,
+				ang_vel: body.ang_vel,
+				centre: ent.pos + rot * body.get_centre(),
+				rot: rot,
+				inv_mass: 1.0 / body.get_mass(),
+				inv_inertia: body.get_inv_inertia(),
+			},
+			_ => Motion {
+				vel: Vec3::new(0.0, 0.0, 0.0),
+				ang_vel: Vec3::new(0.0, 0.0, 0.0),
+				centre: ent.pos,
+				rot: rot,
+				inv_mass: 0.0,
+				inv_inertia: Vec3::new(0.0, 0.0, 0.0),
+			},
+		}
+	}
+	
+	/// Multiplies `v` by the inverse inertia tensor in world space.
+	fn apply_inv_inertia(&self, v: Vec3) -> Vec3 {
+		self.rot.rotate(&(self.inv_inertia * self.rot.inv_rotate(&v)))
+	}
+	
+	fn get_point_vel(&self, r: Vec3) -> Vec3 {
+		self.vel + self.ang_vel.cross(&r)
+	}
+	
+	fn apply_impulse(&mut self, r: Vec3, impulse: Vec3) {
+		self.vel = self.vel + impulse * self.inv_mass;
+		self.ang_vel = self.ang_vel + self.apply_inv_inertia(r.cross(&impulse));
+	}
+	
+	/// Gets how hard it is to change the speed of the point at `r` along `dir`, as 1 / the effective mass.
+	fn get_inv_mass_along(&self, r: Vec3, dir: Vec3) -> f32 {
+		self.inv_mass + self.apply_inv_inertia(r.cross(&dir)).cross(&r).dot(&dir)
+	}
+}
+
+/// A contact between two entities, and the impulses worked out for it so far.
+struct Constraint {
+	a: usize,
+	b: usize,
+	/// From the centres of mass of a and b to the contact point.
+	ra: Vec3,
+	rb: Vec3,
+	normal: Vec3,
+	tangents: [Vec3; 2],
+	normal_mass: f32,
+	tangent_mass: [f32; 2],
+	/// The speed that a and b should be moving apart at along the normal.
+	target_speed: f32,
+	friction: f32,
+	normal_impulse: f32,
+	tangent_impulse: [f32; 2],
+}
+impl Constraint {
+	fn new(a: usize, b: usize, c: &ContactPoint, motions: &[Motion], friction: f32, restitution: f32, dt: DT) -> Constraint {
+		let (ma, mb) = (&motions[a], &motions[b]);
+		let (ra, rb) = (c.pos - ma.centre, c.pos - mb.centre);
+		let n = c.normal;
+		let t1 = if n.x.abs() > 0.57 {
+			Vec3::new(n.y, -n.x, 0.0).normalize()
+		} else {
+			Vec3::new(0.0, n.z, -n.y).normalize()
+		};
+		let tangents = [t1, n.cross(&t1)];
+		let mass_along = |dir: Vec3| {
+			let k = ma.get_inv_mass_along(ra, dir) + mb.get_inv_mass_along(rb, dir);
+			if k > 0.0 { 1.0 / k } else { 0.0 }
+		};
+		
+		// Bounce back if hitting hard enough, and push apart if overlapping too much. Shapes that are apart can
+		// still close the gap between them this tick.
+		let approach = (mb.get_point_vel(rb) - ma.get_point_vel(ra)).dot(&n);
+		let bounce = if approach < -BOUNCE_THRESHOLD { -approach * restitution } else { 0.0 };
+		let push = if c.depth < 0.0 {
+			c.depth / dt
+		} else {
+			BAUMGARTE / dt * (c.depth - SLOP).max(0.0)
+		};
+		Constraint {
+			a: a,
+			b: b,
+			ra: ra,
+			rb: rb,
+			normal: n,
+			tangents: tangents,
+			normal_mass: mass_along(n),
+			tangent_mass: [mass_along(tangents[0]), mass_along(tangents[1])],
+			target_speed: bounce.max(push),
+			friction: friction,
+			normal_impulse: 0.0,
+			tangent_impulse: [0.0, 0.0],
+		}
+	}
+	
+	fn solve(&mut self, motions: &mut [Motion]) {
+		// Stop a and b moving into each other. The total impulse can only push them apart.
+		let speed = self.get_relative_vel(motions).dot(&self.normal);
+		let total = (self.normal_impulse + self.normal_mass * (self.target_speed - speed)).max(0.0);
+		let impulse = total - self.normal_impulse;
+		self.normal_impulse = total;
+		self.apply(motions, self.normal * impulse);
+		
+		// Stop them sliding, with no more friction than the normal impulse allows
+		let max_friction = self.friction * self.normal_impulse;
+		for i in 0..2 {
+			let speed = self.get_relative_vel(motions).dot(&self.tangents[i]);
+			let total = (self.tangent_impulse[i] - self.tangent_mass[i] * speed).max(-max_friction).min(max_friction);
+			let impulse = total - self.tangent_impulse[i];
+			self.tangent_impulse[i] = total;
+			self.apply(motions, self.tangents[i] * impulse);
+		}
+	}
+	
+	/// Gets the velocity of b's contact point relative to a's.
+	fn get_relative_vel(&self, motions: &[Motion]) -> Vec3 {
+		motions[self.b].get_point_vel(self.rb) - motions[self.a].get_point_vel(self.ra)
+	}
+	
+	/// Applies `impulse` to b, and the opposite to a.
+	fn apply(&self, motions: &mut [Motion], impulse: Vec3) {
+		motions[self.a].apply_impulse(self.ra, -impulse);
+		motions[self.b].apply_impulse(self.rb, impulse);
+	}
+}
+
+/// Gets the friction and restitution of an entity. Static entities have the defaults, but don't bounce.
+fn get_material(ent: &Entity) -> (f32, f32) {
+	match ent.get_body() {
+		Some(body) => (body.friction, body.restitution),
+		None => (DEFAULT_FRICTION, 0.0),
+	}
+}
+
+/// A portal that a body is partly through.
+pub struct Crossing<'a> {
+	pub portal: &'a Portal,
+	pub exit: &'a Portal,
+	/// 1 if the origin of the entity, which is what goes through portals, is in front of the portal, otherwise -1.
+	/// The part of the body on the other side is really at the exit portal.
+	pub side: f32,
+}
+impl<'a> Crossing<'a> {
+	/// Finds the portals whose rectangles the body of `ent` might be through.
+	fn find(ent: &Entity, body: &RigidBody, portals: &'a Option<(Portal, Portal)>) -> Vec<Crossing<'a>> {
+		let centre = ent.pos + ent.get_rot() * body.get_centre();
+		Crossing::find_near(ent.pos, centre, body.get_radius(), portals)
+	}
+	
+	/// Finds the portals whose rectangles a body within `radius` of `centre` might be through, where `origin` is the
+	/// point of the body that goes through portals.
+	pub fn find_near(origin: Vec3, centre: Vec3, radius: f32, portals: &'a Option<(Portal, Portal)>) -> Vec<Crossing<'a>> {
+		let (p1, p2) = match *portals {
+			Some((ref p1, ref p2)) => (p1, p2),
+			None => return Vec::new(),
+		};
+		let near = |p: &Portal| p.get_distance_to_rect(centre) <= radius;
+		[(p1, p2), (p2, p1)].iter().filter(|&&(p, _)| near(p)).map(|&(p, exit)| Crossing {
+			portal: p,
+			exit: exit,
+			side: if p.get_distance(origin) >= 0.0 { 1.0 } else { -1.0 },
+		}).collect()
+	}
+	
+	/// Returns true if `pos` is through the portal's rectangle, on the far side from the entity.
+	pub fn is_through(&self, pos: Vec3) -> bool {
+		self.side * self.portal.get_distance(pos) < 0.0 && self.portal.is_within(pos)
+	}
+	
+	/// Returns true if `pos`, near the exit portal, is where the part of the body through the portal comes out.
+	/// The far side of the portal leads out of the same side of the exit as the entity is on, so the surface that the
+	/// exit is on is never there.
+	pub fn is_out_of_exit(&self, pos: Vec3) -> bool {
+		self.side * self.exit.get_distance(pos) > 0.0
+	}
+	
+	/// Moves `pos` through the portal to the exit.
+	pub fn to_exit(&self, pos: Vec3) -> Vec3 {
+		self.portal.get_rotation_to(self.exit) * (pos - self.portal.pos) + self.exit.pos
+	}
+	
+	/// Moves `pos` back from the exit through the portal, to where it really is.
+	pub fn from_exit(&self, pos: Vec3) -> Vec3 {
+		self.exit.get_rotation_to(self.portal) * (pos - self.exit.pos) + self.portal.pos
+	}
+	
+	/// Finds where the part of `collider`, placed at `iso`, that is through the portal touches `mesh` around the exit
+	/// portal. The contacts are moved back through the portal, to where the body really is.
+	fn find_exit_contacts(&self, collider: &Collider, iso: &Iso3, mesh: &TriMesh, mesh_iso: &Iso3) -> Vec<ContactPoint> {
+		let exit_iso = self.portal.transform_iso(self.exit, iso);
+		let back = self.exit.get_rotation_to(self.portal);
+		let found = collider.contacts_with_mesh(&exit_iso, mesh, mesh_iso);
+		// Only the part that has come out of the exit portal is there
+		found.into_iter().filter(|c| self.is_out_of_exit(c.get_surface())).map(|c| ContactPoint {
+			pos: self.from_exit(c.pos),
+			normal: back * c.normal,
+			depth: c.depth,
+		}).collect()
+	}
+}
+
+/// Returns true if `pos` is through any of the portals in `crossings`.
+pub fn is_through(crossings: &[Crossing], pos: Vec3) -> bool {
+	crossings.iter().any(|c| c.is_through(pos))
+}
+
+/// Finds every contact between a dynamic entity and another entity, as (a, b, contact) with the normal pointing
+/// from a to b. Pairs of entities that are both asleep or static are skipped. The contacts through portals are
+/// dropped before the rest are reduced, so that they don't hide the contacts that are kept.
+fn find_contacts(entities: &[Entity], portals: &Option<(Portal, Portal)>) -> Vec<(usize, usize, ContactPoint)> {
+	let crossings: Vec<Vec<Crossing>> = entities.iter().map(|ent| match ent.get_body() {
+		Some(body) => Crossing::find(ent, body, portals),
+		None => Vec::new(),
+	}).collect();
+	
+	let mut contacts = Vec::new();
+	for (a, ent_a) in entities.iter().enumerate() {
+		let body_a = match ent_a.get_body() {
+			Some(body) => body,
+			None => continue,
+		};
+		let iso_a = ent_a.get_iso();
+		for (b, ent_b) in entities.iter().enumerate() {
+			if a == b {
+				continue;
+			}
+			let found: Vec<ContactPoint> = match (ent_b.get_body(), ent_b.get_type()) {
+				(Some(body_b), _) => {
+					// Each pair of bodies only once
+					if b < a || (body_a.is_sleeping() && body_b.is_sleeping()) {
+						continue;
+					}
+					let found = body_a.get_collider().contacts_with_collider(&iso_a, body_b.get_collider(), &ent_b.get_iso());
+					found.into_iter().filter(|c| !is_through(&crossings[a], c.pos) && !is_through(&crossings[b], c.pos)).collect()
+				},
+				(None, EntityType::Static) => match ent_b.get_collision_mesh() {
+					Some(mesh) if !body_a.is_sleeping() => {
+						let iso_b = ent_b.get_iso();
+						let found = body_a.get_collider().contacts_with_mesh(&iso_a, mesh, &iso_b);
+						// The surface behind a portal isn't really there. Each triangle has its own contacts, so the rest of
+						// the mesh is still touched.
+						let mut found: Vec<ContactPoint> = found.into_iter().filter(|c| !is_through(&crossings[a], c.get_surface())).collect();
+						for crossing in crossings[a].iter() {
+							found.extend(crossing.find_exit_contacts(body_a.get_collider(), &iso_a, mesh, &iso_b));
+						}
+						found
+					},
+					_ => continue,
+				},
+				(None, EntityType::Dynamic) => continue,
+			};
+			contacts.extend(reduce_contacts(found).into_iter().map(|c| (a, b, c)));
+		}
+	}
+	contacts
+}
+
+/// Wakes the bodies that might be through `portal`, so that they notice when it is moved.
+pub fn wake_near_portal(entities: &mut [Entity], portal: &Portal) {
+	for ent in entities.iter_mut() {
+		let near = match ent.get_body() {
+			Some(body) => portal.get_distance_to_rect(ent.pos + ent.get_rot() * body.get_centre()) <= body.get_radius(),
+			None => false,
+		};
+		if near {
+			if let Some(body) = ent.get_body_mut() {
+				body.wake();
+			}
+		}
+	}
+}
+
+/// Moves the dynamic entities on by `dt`, colliding them with each other and with the static entities.
+/// Entities that go through a portal come out of the other one.
+pub fn step(entities: &mut [Entity], portals: &Option<(Portal, Portal)>, dt: DT) {
+	for ent in entities.iter_mut() {
+		if ent.get_body().map_or(false, |b| !b.is_sleeping()) {
+			ent.vel.y -= GRAVITY * dt;
+		}
+	}
+	
+	// Sleeping bodies are woken by anything moving that touches them
+	let contacts = find_contacts(entities, portals);
+	for &(a, b, _) in contacts.iter() {
+		for &(i, j) in [(a, b), (b, a)].iter() {
+			let moving = entities[j].get_body().map_or(false, |body| body.is_moving());
+			if moving {
+				if let Some(body) = entities[i].get_body_mut() {
+					body.wake();
+				}
+			}
+		}
+	}
+	
+	let mut motions: Vec<Motion> = entities.iter().map(Motion::new).collect();
+	let mut constraints: Vec<Constraint> = contacts.iter().map(|&(a, b, ref c)| {
+		let ((fa, ra), (fb, rb)) = (get_material(&entities[a]), get_material(&entities[b]));
+		Constraint::new(a, b, c, &motions, (fa * fb).sqrt(), ra.max(rb), dt)
+	}).collect();
+	for _ in 0..SOLVER_ITERATIONS {
+		for c in constraints.iter_mut() {
+			c.solve(&mut motions);
+		}
+	}
+	
+	for (ent, motion) in entities.iter_mut().zip(motions.iter()) {
+		let has_body = match ent.get_body() {
+			Some(body) if body.is_sleeping() => continue,
+			Some(_) => true,
+			None => false,
+		};
+		if has_body {
+			ent.vel = motion.vel;
+			if let Some(body) = ent.get_body_mut() {
+				body.ang_vel = motion.ang_vel;
+			}
+		}
+		ent.tick(dt, portals);
+		
+		let vel = ent.vel;
+		let fell_asleep = match ent.get_body_mut() {
+			Some(body) => body.update_sleep(vel, dt),
+			None => false,
+		};
+		if fell_asleep {
+			ent.vel = Vec3::new(0.0, 0.0, 0.0);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prelude::*;
+	use entity::Entity;
+	use render::{Mesh, MeshBuilder};
+	use na;
+	
+	const TICK: DT = 1.0 / 60.0;
+	
+	/// Makes a static entity out of quads, each with its corners in order around it.
+	fn quads(quads: &[[Vec3; 4]]) -> Entity {
+		let mut mb = MeshBuilder::new();
+		let color = Vec3::new(1.0, 1.0, 1.0);
+		for q in quads.iter() {
+			let i = mb.push(q[0], color);
+			for &v in q[1..].iter() {
+				mb.push(v, color);
+			}
+			mb.index(na::Vec3::new(i, i + 1, i + 2));
+			mb.index(na::Vec3::new(i, i + 2, i + 3));
+		}
+		let mut ent = Entity::new_static(Vec3::new(0.0, 0.0, 0.0), mb.finish());
+		ent.set_collision_mesh(mb.to_trimesh());
+		ent
+	}
+	
+	/// A 10x10 floor at y = 0, split into a grid so that things rest over the edges between the triangles.
+	fn floor() -> Entity {
+		let white = Vec3::new(1.0, 1.0, 1.0);
+		let mb = MeshBuilder::new_planes(4, 4, 10.0, 10.0, white, white);
+		let mut ent = Entity::new_static(Vec3::new(0.0, 0.0, 0.0), mb.finish());
+		ent.set_collision_mesh(mb.to_trimesh());
+		ent
+	}
+	
+	fn cuboid(pos: Vec3, size: f32) -> Entity {
+		Entity::new(pos, Vec3::new(0.0, 0.0, 0.0), Mesh::new_cuboid(size, size, size, Vec3::new(1.0, 1.0, 1.0)))
+	}
+	
+	fn run(entities: &mut [Entity], portals: &Option<(Portal, Portal)>, secs: f32) {
+		for _ in 0..(secs / TICK) as usize {
+			step(entities, portals, TICK);
+		}
+	}
+	
+	fn is_sleeping(ent: &Entity) -> bool {
+		ent.get_body().unwrap().is_sleeping()
+	}
+	
+	#[test]
+	fn dropped_cuboid_comes_to_rest() {
+		let mut ents = vec![floor(), cuboid(Vec3::new(0.0, 1.0, 0.0), 0.5)];
+		run(&mut ents, &None, 3.0);
+		let ent = &ents[1];
+		assert!((ent.pos.y - 0.25).abs() < 0.01, "resting at {:?}", ent.pos);
+		assert!(ent.pos.x.abs() < 0.01 && ent.pos.z.abs() < 0.01, "slid to {:?}", ent.pos);
+		assert!(ent.get_rot().rotation().norm() < 0.01, "tipped over to {:?}", ent.get_rot().rotation());
+		assert!(is_sleeping(ent));
+	}
+	
+	#[test]
+	fn stack_stays_stacked() {
+		let (bottom, top) = (Vec3::new(0.3, 0.25, 0.3), Vec3::new(0.3, 0.75, 0.3));
+		let mut ents = vec![floor(), cuboid(bottom, 0.5), cuboid(top, 0.5)];
+		run(&mut ents, &None, 3.0);
+		assert!((ents[1].pos - bottom).norm() < 0.02, "bottom box moved to {:?}", ents[1].pos);
+		assert!((ents[2].pos - top).norm() < 0.02, "top box moved to {:?}", ents[2].pos);
+		assert!(is_sleeping(&ents[1]) && is_sleeping(&ents[2]));
+	}
+	
+	/// Drops a box with `restitution` onto the floor, and returns the highest it gets after first hitting it.
+	fn rebound_height(restitution: f32) -> f32 {
+		let mut ents = vec![floor(), cuboid(Vec3::new(0.0, 1.5, 0.0), 0.5)];
+		ents[1].get_body_mut().unwrap().restitution = restitution;
+		let (mut landed, mut highest) = (false, 0.0f32);
+		for _ in 0..(2.0 / TICK) as usize {
+			step(&mut ents, &None, TICK);
+			landed = landed || ents[1].vel.y > 0.0;
+			if landed {
+				highest = highest.max(ents[1].pos.y);
+			}
+		}
+		assert!(landed);
+		highest
+	}
+	
+	#[test]
+	fn restitution_changes_rebound() {
+		let (dead, bouncy) = (rebound_height(0.0), rebound_height(0.4));
+		assert!(dead < 0.35, "bounced up to {} without any restitution", dead);
+		assert!(bouncy > dead + 0.05, "bounced up to {} with restitution, and {} without", bouncy, dead);
+	}
+	
+	#[test]
+	fn sleeping_bodies_wake() {
+		let mut ents = vec![floor(), cuboid(Vec3::new(0.0, 0.25, 0.0), 0.5)];
+		run(&mut ents, &None, 1.0);
+		assert!(is_sleeping(&ents[1]));
+		
+		// Pushed by the player
+		ents[1].push(Vec3::new(1.0, 0.0, 0.0), 0.0, 1.0);
+		assert!(!is_sleeping(&ents[1]));
+		let x = ents[1].pos.x;
+		step(&mut ents, &None, TICK);
+		assert!(ents[1].pos.x > x);
+		
+		// Hit by another body
+		run(&mut ents, &None, 2.0);
+		assert!(is_sleeping(&ents[1]));
+		let top = ents[1].pos + Vec3::new(0.0, 1.0, 0.0);
+		ents.push(cuboid(top, 0.3));
+		let mut woken = false;
+		for _ in 0..60 {
+			step(&mut ents, &None, TICK);
+			woken = woken || !is_sleeping(&ents[1]);
+		}
+		assert!(woken);
+	}
+	
+	#[test]
+	fn cuboid_in_corner_touches_both_faces() {
+		// A floor and a wall in one mesh
+		let corner = quads(&[
+			[Vec3::new(-3.0, 0.0, -3.0), Vec3::new(3.0, 0.0, -3.0), Vec3::new(3.0, 0.0, 1.0), Vec3::new(-3.0, 0.0, 1.0)],
+			[Vec3::new(-3.0, 0.0, 1.0), Vec3::new(3.0, 0.0, 1.0), Vec3::new(3.0, 3.0, 1.0), Vec3::new(-3.0, 3.0, 1.0)],
+		]);
+		let mut ents = vec![corner, cuboid(Vec3::new(0.0, 0.25, 0.0), 0.5)];
+		ents[1].vel = Vec3::new(0.0, 0.0, 3.0);
+		run(&mut ents, &None, 2.0);
+		let pos = ents[1].pos;
+		assert!((pos.y - 0.25).abs() < 0.01, "sank to {:?} while against the wall", pos);
+		assert!(pos.z < 0.76 && pos.z > 0.7, "not stopped by the wall at {:?}", pos);
+	}
+	
+	#[test]
+	fn cuboid_rests_across_portal() {
+		// A floor in front of a wall, in one mesh, with both portals on the wall just reaching the floor
+		let room = quads(&[
+			[Vec3::new(-3.0, 0.0, -3.0), Vec3::new(3.0, 0.0, -3.0), Vec3::new(3.0, 0.0, 1.0), Vec3::new(-3.0, 0.0, 1.0)],
+			[Vec3::new(-3.0, 0.0, 1.0), Vec3::new(3.0, 0.0, 1.0), Vec3::new(3.0, 3.0, 1.0), Vec3::new(-3.0, 3.0, 1.0)],
+		]);
+		let facing_room = Rot3::new(Vec3::new(0.0, ::std::f32::consts::PI, 0.0));
+		let portals = Some((
+			Portal::new(Vec3::new(-1.5, 0.6, 0.999), facing_room, 0.9, 1.4),
+			Portal::new(Vec3::new( 1.5, 0.6, 0.999), facing_room, 0.9, 1.4)));
+		
+		// A fifth of the box is through the first portal, and sticks out of the second
+		let start = Vec3::new(-1.5, 0.25, 0.95);
+		let mut ents = vec![room, cuboid(start, 0.5)];
+		run(&mut ents, &portals, 2.0);
+		let ent = &ents[1];
+		assert_eq!(ent.get_transits(), 0);
+		assert!((ent.pos.y - 0.25).abs() < 0.01, "sank to {:?}", ent.pos);
+		assert!((ent.pos - start).norm() < 0.02, "pushed to {:?}", ent.pos);
+		assert!(ent.get_rot().rotation().norm() < 0.02, "tipped over to {:?}", ent.get_rot().rotation());
+	}
+}
@@ -0,0 +1,177 @@
+use prelude::*;
+
+use entity::{Entity, EntityType, Camera, Portal};
+use physics::{self, Crossing};
+use nc::point::PointQuery;
+use nc::bounding_volume::{BoundingVolumeInterferencesCollector, AABB};
+
+/// Acceleration due to gravity, in units/s^2.
+pub const GRAVITY: f32 = 9.81;
+/// The upwards speed given by a jump, in units/s.
+pub const JUMP_SPEED: f32 = 2.8;
+/// The height of the camera above the player's feet.
+pub const EYE_HEIGHT: f32 = 1.0;
+/// The radius of the spheres that make up the player's body.
+pub const RADIUS: f32 = 0.2;
+/// The number of spheres that make up the player's body, from the feet up to the eyes.
+pub const NUM_SPHERES: usize = 3;
+/// The minimum y component of a surface normal that counts as ground.
+pub const GROUND_NORMAL_Y: f32 = 0.7;
+/// The number of times collisions are resolved each tick.
+pub const COLLISION_ITERATIONS: usize = 4;
+
+/// The body and movement state of the player. The position is the camera's position.
+#[derive(Copy, Clone)]
+pub struct Player {
+	pub vel: Vec3,
+	on_ground: bool,
+	noclip: bool,
+}
+
+impl Player {
+	pub fn new() -> Player {
+		Player {
+			vel: Vec3::new(0.0, 0.0, 0.0),
+			on_ground: false,
+			noclip: false,
+		}
+	}
+	
+	pub fn is_on_ground(&self) -> bool {
+		self.on_ground
+	}
+	
+	pub fn is_noclip(&self) -> bool {
+		self.noclip
+	}
+	pub fn toggle_noclip(&mut self) {
+		self.noclip = !self.noclip;
+		self.vel = Vec3::new(0.0, 0.0, 0.0);
+		self.on_ground = false;
+	}
+	
+	/// Moves the player for one tick. `walk_vel` is the horizontal velocity the player wants to move at.
+	pub fn walk(&mut self, cam: &mut Camera, walk_vel: Vec3, jump: bool, dt: DT, entities: &mut [Entity], portals: &Option<(Portal, Portal)>) {
+		self.vel.x = walk_vel.x;
+		self.vel.z = walk_vel.z;
+		if jump && self.on_ground {
+			self.vel.y = JUMP_SPEED;
+		}
+		self.vel.y -= GRAVITY * dt;
+		
+		let mov = self.vel * dt;
+		if mov != Vec3::new(0.0, 0.0, 0.0) {
+			if let Some(rot) = cam.translate(mov, portals) {
+				// Keep the velocity relative to the portal
+				self.vel = rot * self.vel;
+			}
+		}
+		
+		self.resolve_collisions(cam, entities, portals);
+	}
+	
+	/// Gets the centres of the spheres that make up the body of a player with their eyes at `eye`.
+	fn get_spheres(eye: Vec3) -> [Vec3; NUM_SPHERES] {
+		let feet = eye - Vec3::new(0.0, EYE_HEIGHT - RADIUS, 0.0);
+		let step = (eye - feet) / (NUM_SPHERES - 1) as f32;
+		let mut spheres = [feet; NUM_SPHERES];
+		for i in 0..NUM_SPHERES {
+			spheres[i] = feet + step * i as f32;
+		}
+		spheres
+	}
+	
+	/// Gets the point of the triangles of `mesh`, placed at `iso`, that is closest to the sphere at `centre`, out of
+	/// the points that `keep` returns true for.
+	fn closest_on_mesh<F: Fn(Vec3) -> bool>(mesh: &TriMesh, iso: &Iso3, centre: Vec3, keep: F) -> Option<Vec3> {
+		let local = iso.inv_transform(&centre.to_pnt());
+		let r = Vec3::new(RADIUS, RADIUS, RADIUS);
+		let bounds = AABB::new(local - r, local + r);
+		let mut near: Vec<usize> = Vec::new();
+		mesh.bvt().visit(&mut BoundingVolumeInterferencesCollector::new(&bounds, &mut near));
+		let mut closest: Option<Vec3> = None;
+		for &i in near.iter() {
+			let p = mesh.triangle_at(i).project_point(iso, &centre.to_pnt(), true).to_vec();
+			if keep(p) && closest.map_or(true, |c| (p - centre).norm() < (c - centre).norm()) {
+				closest = Some(p);
+			}
+		}
+		closest
+	}
+	
+	/// Gets the point of a static mesh that is closest to the sphere at `centre`. Like the bodies in `physics`, the
+	/// surface behind a portal that the sphere is through isn't there, and the part of the sphere through the portal
+	/// touches what is around the exit instead, which is moved back through the portal to where the player really is.
+	fn closest_on_static(mesh: &TriMesh, iso: &Iso3, centre: Vec3, crossings: &[Crossing]) -> Option<Vec3> {
+		let mut closest = Player::closest_on_mesh(mesh, iso, centre, |p| !physics::is_through(crossings, p));
+		for crossing in crossings.iter() {
+			let exit_closest = Player::closest_on_mesh(mesh, iso, crossing.to_exit(centre), |p| crossing.is_out_of_exit(p));
+			if let Some(p) = exit_closest.map(|p| crossing.from_exit(p)) {
+				if closest.map_or(true, |c| (p - centre).norm() < (c - centre).norm()) {
+					closest = Some(p);
+				}
+			}
+		}
+		closest
+	}
+	
+	/// Pushes the player out of any geometry that they are inside of. Dynamic entities can be stood on, but are
+	/// pushed away when walked into. Walls that a portal is on don't stop the player inside the portal's rectangle.
+	fn resolve_collisions(&mut self, cam: &mut Camera, entities: &mut [Entity], portals: &Option<(Portal, Portal)>) {
+		self.on_ground = false;
+		for _ in 0..COLLISION_ITERATIONS {
+			let mut collided = false;
+			for ent in entities.iter_mut() {
+				for centre in Player::get_spheres(cam.get_pos()).iter() {
+					let crossings = Crossing::find_near(cam.get_pos(), *centre, RADIUS, portals);
+					let iso = ent.get_iso();
+					let closest = match (ent.get_type(), ent.get_body()) {
+						(EntityType::Static, _) => match ent.get_collision_mesh() {
+							Some(shape) => match Player::closest_on_static(shape, &iso, *centre, &crossings) {
+								Some(p) => p,
+								None => continue,
+							},
+							None => break,
+						},
+						(EntityType::Dynamic, Some(body)) => {
+							let p = body.get_collider().project_point(&iso, &centre.to_pnt()).to_vec();
+							// Entities behind a portal that the sphere is through are somewhere else
+							if physics::is_through(&crossings, p) {
+								continue;
+							}
+							p
+						},
+						(EntityType::Dynamic, None) => break,
+					};
+					let diff = *centre - closest;
+					let dist = diff.norm();
+					if dist >= RADIUS || dist <= 0.0 {
+						continue;
+					}
+					
+					let normal = diff / dist;
+					if ent.get_type() == EntityType::Dynamic && normal.y < GROUND_NORMAL_Y {
+						// Push the entity out of the way, at the speed the player is walking into it
+						ent.push(-normal, RADIUS - dist, self.vel.dot(&-normal).max(0.0));
+						continue;
+					}
+					
+					// Push out along the normal, and stop moving into the surface
+					let pos = cam.get_pos() + normal * (RADIUS - dist);
+					cam.set_pos(pos);
+					let into = self.vel.dot(&normal);
+					if into < 0.0 {
+						self.vel = self.vel - normal * into;
+					}
+					if normal.y >= GROUND_NORMAL_Y {
+						self.on_ground = true;
+					}
+					collided = true;
+				}
+			}
+			if !collided {
+				break;
+			}
+		}
+	}
+}
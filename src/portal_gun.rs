@@ -0,0 +1,155 @@
+use prelude::*;
+
+use entity::{Camera, Portal};
+use world::World;
+use nc::ray::Ray;
+
+/// How far the portal gun can shoot.
+pub const MAX_RANGE: f32 = 100.0;
+/// How far in front of the surface portals are placed, so that they don't z-fight with it.
+pub const SURFACE_OFFSET: f32 = 0.001;
+/// How far in front of the portal the rays that check the surface under it start.
+const PROBE_DIST: f32 = 0.05;
+/// How far the surface under the portal can be from the plane of the portal and still count as flush.
+const FLUSH_TOLERANCE: f32 = 0.01;
+/// The minimum dot product between two normals for them to count as the same surface.
+const SAME_NORMAL: f32 = 0.99;
+
+/// The two portals that the gun can place.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PortalColor {
+	/// The first portal of the pair, shot with the left mouse button.
+	Blue,
+	/// The second portal of the pair, shot with the right mouse button.
+	Orange,
+}
+
+/// Works out the rotation of a portal lying on a surface with the normal `normal`.
+/// On walls the top of the portal points up, on floors and ceilings it points away from `forward`.
+pub fn get_surface_rotation(normal: Vec3, forward: Vec3) -> Rot3 {
+	let mut up = if normal.y.abs() < SAME_NORMAL {
+		Vec3::new(0.0, 1.0, 0.0)
+	} else {
+		forward - normal * forward.dot(&normal)
+	};
+	if up.norm() < 0.001 {
+		// Looking straight at the floor or ceiling
+		up = Vec3::new(0.0, 0.0, 1.0);
+	}
+	Rot3::look_at_z(&normal, &up)
+}
+
+/// Fires a portal from `cam`, returning where `portal` should be moved to, or why it can't be placed.
+/// `other` is the other portal of the pair, which the new portal must not overlap.
+pub fn fire(world: &World, cam: &Camera, portal: &Portal, other: &Portal) -> Result<(Vec3, Rot3), String> {
+	let forward = cam.get_forward();
+	let ray = Ray::new(cam.get_pos().to_pnt(), forward);
+	let hit = match world.cast_ray(&ray, MAX_RANGE) {
+		Some(hit) => hit,
+		None => return Err(String::from("nothing was hit")),
+	};
+	
+	let normal = hit.normal;
+	let pos = cam.get_pos() + forward * hit.toi + normal * SURFACE_OFFSET;
+	let rot = get_surface_rotation(normal, forward);
+	
+	try!(check_fits(world, pos, rot, portal.get_w(), portal.get_h()));
+	if overlaps(pos, rot, portal.get_w(), portal.get_h(), other) {
+		return Err(String::from("the portal would overlap the other portal"));
+	}
+	Ok((pos, rot))
+}
+
+/// Checks that a portal of size `w` by `h` at `pos` and `rot` lies flat on a surface of the world.
+/// The corners, edges and centre of the portal are checked by casting rays back onto the surface.
+pub fn check_fits(world: &World, pos: Vec3, rot: Rot3, w: f32, h: f32) -> Result<(), String> {
+	let normal = rot * Vec3::new(0.0, 0.0, 1.0);
+	for &x in [-0.5, 0.0, 0.5].iter() {
+		for &y in [-0.5, 0.0, 0.5].iter() {
+			let sample = pos + rot * Vec3::new(x * w, y * h, 0.0);
+			let ray = Ray::new((sample + normal * PROBE_DIST).to_pnt(), -normal);
+			match world.cast_ray(&ray, PROBE_DIST * 2.0) {
+				Some(hit) => {
+					if (hit.toi - PROBE_DIST).abs() > FLUSH_TOLERANCE + SURFACE_OFFSET {
+						return Err(String::from("the surface is not flat enough"));
+					}
+					if hit.normal.dot(&normal) < SAME_NORMAL {
+						return Err(String::from("the portal would go over an edge"));
+					}
+				},
+				None => return Err(String::from("the portal would hang off the surface")),
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Returns true if a portal of size `w` by `h` at `pos` and `rot` would overlap `other`.
+pub fn overlaps(pos: Vec3, rot: Rot3, w: f32, h: f32, other: &Portal) -> bool {
+	let normal = rot * Vec3::new(0.0, 0.0, 1.0);
+	// Portals on different surfaces can't overlap
+	if other.get_normal().dot(&normal) < SAME_NORMAL || normal.dot(&(other.pos - pos)).abs() > FLUSH_TOLERANCE {
+		return false;
+	}
+	
+	// Check the bounds of the other portal in the plane of this one
+	let (ow2, oh2) = (other.get_w() / 2.0, other.get_h() / 2.0);
+	let (mut min, mut max) = (Vec2::new(::std::f32::MAX, ::std::f32::MAX), Vec2::new(::std::f32::MIN, ::std::f32::MIN));
+	for &(x, y) in [(-ow2, -oh2), (ow2, -oh2), (-ow2, oh2), (ow2, oh2)].iter() {
+		let corner = rot.inv_rotate(&(other.pos + other.rot * Vec3::new(x, y, 0.0) - pos));
+		min.x = min.x.min(corner.x);
+		min.y = min.y.min(corner.y);
+		max.x = max.x.max(corner.x);
+		max.y = max.y.max(corner.y);
+	}
+	min.x < w / 2.0 && max.x > -w / 2.0 && min.y < h / 2.0 && max.y > -h / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prelude::*;
+	use entity::Portal;
+	use level;
+	use std::path::Path;
+	
+	/// A floor, with a wall across the far end of it.
+	const ROOM: &'static str = "
+camera 0 1 0
+static 0 0 0  planes 1 1 10 10  1 1 1  1 1 1
+static 0 1.5 5  rectangle 4 3  1 1 1
+portal -3 1 -3  0 0 0  0.9 1.4
+portal  3 1 -3  0 0 0  0.9 1.4
+";
+	
+	/// Gets how far `pos` is in front of `portal`.
+	fn distance_in_front(portal: &Portal, pos: Vec3) -> f32 {
+		(pos - portal.pos).dot(&portal.get_normal())
+	}
+	
+	#[test]
+	fn fired_portals_lead_out_of_their_surfaces() {
+		let mut world = level::parse(ROOM, Path::new(".")).unwrap();
+		// Straight ahead at the wall, then down at the floor two units in front of the camera
+		world.camera.set_rotation(0.0, 0.0);
+		world.fire_portal(PortalColor::Blue);
+		world.camera.set_rotation(0.0, (1.0 / 5.0f32.sqrt()).asin());
+		world.fire_portal(PortalColor::Orange);
+		
+		let (blue, orange) = world.get_portals().unwrap();
+		assert!((blue.pos - Vec3::new(0.0, 1.0, 5.0 - SURFACE_OFFSET)).norm() < 0.001, "blue portal at {:?}", blue.pos);
+		assert!((orange.pos - Vec3::new(0.0, SURFACE_OFFSET, 2.0)).norm() < 0.001, "orange portal at {:?}", orange.pos);
+		
+		// Walk into the wall through the blue portal
+		let vel = Vec3::new(0.0, 0.0, 1.0);
+		let (pos, rot) = blue.get_transit(&orange, blue.pos - vel * 0.1, vel * 0.2).unwrap();
+		assert!(distance_in_front(&orange, pos) > 0.0, "came out at {:?}, below the floor", pos);
+		let out_vel = rot * vel;
+		assert!(out_vel.dot(&Vec3::new(0.0, 1.0, 0.0)) > 0.99, "came out moving {:?}, not up out of the floor", out_vel);
+		
+		// And back again
+		let (pos, rot) = orange.get_transit(&blue, pos, -out_vel * 0.2).unwrap();
+		assert!(distance_in_front(&blue, pos) > 0.0, "came back out at {:?}, behind the wall", pos);
+		assert!((rot * -out_vel).dot(&Vec3::new(0.0, 0.0, -1.0)) > 0.99);
+	}
+}
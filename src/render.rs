@@ -1,14 +1,25 @@
 use prelude::*;
 
 use entity::Camera;
+use draw_list::{Backend, DrawCommand, DrawList, DrawSettings, Pass, Shading};
+use light::{Light, Lighting, MAX_LIGHTS};
+use texture::{Image, Texture};
+use capture;
+use shader_source;
+use uniform::{self, UniformInfo, UniformValue, Sampler};
 
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::prelude::*;
 use std::ops::Drop;
 use std::mem;
 use std::ptr::null;
-use std::ffi::CString;
-use std::ffi::CStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use na;
 //use rand::{Rand, XorShiftRng, SeedableRng, Rng};
@@ -18,31 +29,152 @@ use sdl2::video::{GLContext, Window};
 use gl::{self};
 use gl::types::*;
 
-pub type Index = GLushort;
+/// An index into the vertices of a mesh. Meshes that are small enough are uploaded with 16-bit indices instead.
+pub type Index = GLuint;
+
+/// The type of the indices in an index buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IndexType {
+	U16,
+	U32,
+}
+impl IndexType {
+	/// Gets the smallest index type that can refer to every one of `num_verts` vertices.
+	pub fn for_vert_count(num_verts: usize) -> IndexType {
+		if num_verts <= GLushort::max_value() as usize + 1 {
+			IndexType::U16
+		} else {
+			IndexType::U32
+		}
+	}
+	
+	pub fn gl_type(&self) -> GLenum {
+		match *self {
+			IndexType::U16 => gl::UNSIGNED_SHORT,
+			IndexType::U32 => gl::UNSIGNED_INT,
+		}
+	}
+}
+
+/// Converts indices to 16 bits, or returns None if any of them are too big to fit.
+pub fn to_u16_indices(indices: &[na::Vec3<Index>]) -> Option<Vec<na::Vec3<GLushort>>> {
+	let max = GLushort::max_value() as Index;
+	if indices.iter().any(|i| i.x > max || i.y > max || i.z > max) {
+		return None;
+	}
+	Some(indices.iter().map(|i| na::Vec3::new(i.x as GLushort, i.y as GLushort, i.z as GLushort)).collect())
+}
+
+/// The maximum number of portals that can be seen through each other.
+/// Each level doubles the number of times the scene is drawn, and is limited by the 8 bit stencil buffer.
+pub const MAX_PORTAL_DEPTH: u32 = 8;
+
+pub const Z_NEAR: f32 = 0.01;
+pub const Z_FAR: f32 = 500.0;
+
+/// Returns true if OpenGL has been loaded, so that meshes can be uploaded to the GPU.
+/// When running headless this is false, and meshes are created without any GPU resources and never drawn.
+pub fn has_gl() -> bool {
+	gl::GenVertexArrays::is_loaded()
+}
+
+/// How the projection matrix is calculated for a camera.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProjectionMode {
+	/// A perspective projection with a fixed near plane at `Z_NEAR`.
+	Perspective,
+	/// A perspective projection with the near plane replaced by the camera's clip plane, if it has one.
+	/// This stops geometry behind the exit portal from being drawn over the view through it.
+	Oblique,
+}
+
+/// Modifies the projection matrix `proj` so that its near plane is `clip_plane`, given in camera space.
+/// The far plane is moved as little as possible so that the depth range is preserved.
+///
+/// See Eric Lengyel, "Oblique View Frustum Depth Projection and Clipping".
+pub fn oblique_projection(proj: &Mat4, clip_plane: &Vec4) -> Mat4 {
+	let inv_proj = match proj.inv() {
+		Some(m) => m,
+		None => return *proj,
+	};
+	// The clip plane in clip space
+	let c = na::transpose(&inv_proj) * *clip_plane;
+	let sgn = |x: f32| if x > 0.0 { 1.0 } else if x < 0.0 { -1.0 } else { 0.0 };
+	// The corner of the view frustum opposite the clip plane, in camera space
+	let q = inv_proj * Vec4::new(sgn(c.x), sgn(c.y), 1.0, 1.0);
+	
+	let w_row = proj.row(3);
+	let a = 2.0 * w_row.dot(&q) / clip_plane.dot(&q);
+	
+	let mut ret = *proj;
+	ret.set_row(2, *clip_plane * a - w_row);
+	ret
+}
+
+/// What is drawn inside of the portals at the deepest level of recursion.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PortalFallback {
+	/// Fill the portal with a solid color.
+	Color(Vec4),
+	/// Fill the portal with what was on the screen last frame.
+	LastFrame,
+	/// Leave the portal empty, so that whatever is behind it shows through.
+	Nothing,
+}
+
+/// The number of OpenGL objects (buffers, vertex arrays, textures, shaders and programs) that have been
+/// created and not yet deleted. Only for catching leaks.
+static LIVE_GL_OBJECTS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Gets the number of OpenGL objects that are currently alive.
+pub fn get_live_gl_objects() -> usize {
+	LIVE_GL_OBJECTS.load(Ordering::Relaxed)
+}
+
+pub fn add_live_gl_objects(n: usize) {
+	LIVE_GL_OBJECTS.fetch_add(n, Ordering::Relaxed);
+}
+
+pub fn remove_live_gl_objects(n: usize) {
+	LIVE_GL_OBJECTS.fetch_sub(n, Ordering::Relaxed);
+}
 
 pub struct Render<'a> {
 	pub win: &'a mut Window,
 	pub gl_context: &'a mut GLContext,
 	pub main_shader: Shader,
 	pub solid_color_shader: Shader,
+	pub last_frame_shader: Shader,
 	pub vp_mat: Mat4,
 	pub m_mat: Mat4,
 	// arrow_mesh: Mesh,
 	view_wireframes: bool,
-	render_portals: bool
+	render_portals: bool,
+	projection_mode: ProjectionMode,
+	portal_depth: u32,
+	portal_fallback: PortalFallback,
+	last_frame_tex: GLuint,
+	/// Where to save the frame that is being drawn, when it is finished.
+	screenshot: Option<PathBuf>,
 }
 
 impl<'a> Render<'a> {
-	pub fn new(win: &'a mut Window, context: &'a mut GLContext) -> Render<'a> {
+	/// Creates the renderer, loading the shaders from the `shaders` directory of `asset_root`.
+	pub fn new(win: &'a mut Window, context: &'a mut GLContext, asset_root: &Path) -> Render<'a> {
 		//let _ = win.gl_set_context_to_current();
-		let ren = Render {
+		let shaders = asset_root.join("shaders");
+		let mut ren = Render {
 			win: win,
 			gl_context: context,
-			main_shader: match Shader::from_files("shaders/main.vs", "shaders/main.fs") {
+			main_shader: match Shader::from_files(&shaders.join("main.vs"), &shaders.join("main.fs")) {
 				Ok(s) => s,
 				Err(e) => panic!("{}", e),
 				},
-			solid_color_shader: match Shader::from_files("shaders/solid_color.vs", "shaders/solid_color.fs") {
+			solid_color_shader: match Shader::from_files(&shaders.join("solid_color.vs"), &shaders.join("solid_color.fs")) {
+				Ok(s) => s,
+				Err(e) => panic!("{}", e),
+			},
+			last_frame_shader: match Shader::from_files(&shaders.join("last_frame.vs"), &shaders.join("last_frame.fs")) {
 				Ok(s) => s,
 				Err(e) => panic!("{}", e),
 			},
@@ -50,8 +182,21 @@ impl<'a> Render<'a> {
 			m_mat: Mat4::new_identity(4),
 			view_wireframes: false,
 			render_portals: true,
+			projection_mode: ProjectionMode::Oblique,
+			portal_depth: 3,
+			portal_fallback: PortalFallback::Color(Render::get_background_color()),
+			last_frame_tex: 0,
+			screenshot: None,
 		};
 		unsafe {
+			gl::GenTextures(1, &mut ren.last_frame_tex);
+			add_live_gl_objects(1);
+			gl::BindTexture(gl::TEXTURE_2D, ren.last_frame_tex);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+			gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+			
 			gl::Enable(gl::CULL_FACE);
 			gl::Enable(gl::DEPTH_TEST);
 			// gl::Enable(gl::LINE_SMOOTH);
@@ -64,6 +209,20 @@ impl<'a> Render<'a> {
 	}
 	
 	pub fn swap(&mut self) {
+		if self.portal_fallback == PortalFallback::LastFrame {
+			// Keep a copy of the frame to fill the deepest portals with next frame
+			let (w, h) = self.win.drawable_size();
+			unsafe {
+				gl::BindTexture(gl::TEXTURE_2D, self.last_frame_tex);
+				gl::CopyTexImage2D(gl::TEXTURE_2D, 0, gl::RGB, 0, 0, w as GLsizei, h as GLsizei, 0);
+			}
+		}
+		if let Some(path) = self.screenshot.take() {
+			match capture::save_image(&path, &self.read_pixels()) {
+				Ok(()) => println!("saved a screenshot to {}", path.display()),
+				Err(e) => println!("{}", e),
+			}
+		}
 		self.win.show();
 		self.win.gl_swap_window();
 		unsafe {
@@ -91,24 +250,156 @@ impl<'a> Render<'a> {
 		}
 	}
 	
+	/// Saves the frame that is being drawn to `path` as a PNG, once it is finished.
+	pub fn request_screenshot(&mut self, path: PathBuf) {
+		self.screenshot = Some(path);
+	}
+	
+	/// Reads what has been drawn to the back buffer so far.
+	pub fn read_pixels(&self) -> Image {
+		let (w, h) = self.win.drawable_size();
+		let mut data: Vec<u8> = vec![0; w as usize * h as usize * 4];
+		unsafe {
+			gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+			gl::ReadBuffer(gl::BACK);
+			gl::ReadPixels(0, 0, w as GLsizei, h as GLsizei, gl::RGBA, gl::UNSIGNED_BYTE, data.as_mut_ptr() as *mut GLvoid);
+		}
+		Image {
+			width: w,
+			height: h,
+			data: capture::flip_rows(w, h, &data),
+		}
+	}
+	
 	pub fn set_camera(&mut self, cam: &Camera) {
 		// Recalculate VP matrix
 		let (w, h) = self.win.drawable_size();
-		let projection = Persp3::new(w as f32 / h as f32, cam.get_fov(), 0.01, 500.0).to_mat();
 		let view = cam.get_view();
+		let projection = Render::get_projection(self.projection_mode, w as f32 / h as f32, cam.get_fov(), &view, cam.get_clip_plane());
 		self.vp_mat = projection * view;
+		// For specular highlights. Through a portal this is where the camera appears to be on the other side.
+		let p = cam.get_pos();
+		self.main_shader.set_uniform("in_cam_pos", p);
+	}
+	
+	/// Sets the lights that the main shader lights meshes with. Only the first `MAX_LIGHTS` lights are used.
+	pub fn set_lighting(&mut self, lighting: &Lighting) {
+		let s = &self.main_shader;
+		s.set_uniform("in_ambient", lighting.ambient);
+		let num = ::std::cmp::min(lighting.lights.len(), MAX_LIGHTS);
+		s.set_uniform("in_num_lights", num as i32);
+		for (i, light) in lighting.lights.iter().take(num).enumerate() {
+			// w is 0 for directions and 1 for positions
+			let (pos, color, range) = match *light {
+				Light::Directional { dir, color } => (Vec4::new(dir.x, dir.y, dir.z, 0.0), color, 0.0),
+				Light::Point { pos, color, range } => (Vec4::new(pos.x, pos.y, pos.z, 1.0), color, range),
+			};
+			s.set_uniform(&format!("in_light_pos[{}]", i), pos);
+			s.set_uniform(&format!("in_light_color[{}]", i), color);
+			// The range is only used by point lights, so it can be optimised out of shaders without them
+			if s.has_uniform("in_light_range") {
+				s.set_uniform(&format!("in_light_range[{}]", i), range);
+			}
+		}
+	}
+	
+	/// Gets the projection matrix for a camera with the view matrix `view` and the world space clip plane `clip_plane`.
+	pub fn get_projection(mode: ProjectionMode, aspect: f32, fov: f32, view: &Mat4, clip_plane: Option<Vec4>) -> Mat4 {
+		let projection = Persp3::new(aspect, fov, Z_NEAR, Z_FAR).to_mat();
+		match (mode, clip_plane) {
+			(ProjectionMode::Oblique, Some(plane)) => {
+				// Planes are transformed by the inverse transpose of the matrix that transforms points
+				let inv_view = match view.inv() {
+					Some(m) => m,
+					None => return projection,
+				};
+				let cam_plane = na::transpose(&inv_view) * plane;
+				// If the camera is (almost) on the plane the projection degenerates, so keep the normal near plane
+				if cam_plane.w.abs() < Z_NEAR {
+					projection
+				} else {
+					oblique_projection(&projection, &cam_plane)
+				}
+			},
+			_ => projection,
+		}
 	}
 	
 	pub fn set_model_mat(&mut self, s: &Shader, mat: Mat4) {
 		self.m_mat = mat;
 		s.set_mvp(self.vp_mat * self.m_mat);
+		// Only shaders that light their surfaces need to know where they are
+		if s.has_uniform("in_model") {
+			s.set_uniform("in_model", self.m_mat);
+		}
 	}
 	
-	// // Renders p_in in the stencil buffer from the camera c transformed through the portal n number of times.
-	// // Returns the integer that is used to mark the area where p_in is seen through.
-	// fn stencil_portal(c: Camera, p_in: Portal, p_out: Portal, n: u32) -> u32 {
-		
-	// }
+	fn restore_depth_test(&self) {
+		unsafe {
+			if self.view_wireframes {
+				gl::Disable(gl::DEPTH_TEST);
+			} else {
+				gl::Enable(gl::DEPTH_TEST);
+			}
+		}
+	}
+	
+	/// Sets up the colour, depth and stencil buffers for the draws that follow. See `Pass`.
+	pub fn begin_pass(&mut self, pass: Pass) {
+		unsafe {
+			gl::DepthFunc(gl::LESS);
+		}
+		self.restore_depth_test();
+		unsafe {
+			match pass {
+				Pass::StencilPortal { level } => {
+					gl::Enable(gl::STENCIL_TEST);
+					gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+					gl::DepthMask(gl::FALSE);
+					gl::Disable(gl::DEPTH_TEST);
+					// Fail the stencil test (and so increment) only in the area that is currently being drawn
+					gl::StencilMask(0xFF);
+					gl::StencilFunc(gl::NOTEQUAL, level as GLint, 0xFF);
+					gl::StencilOp(gl::INCR, gl::KEEP, gl::KEEP);
+				},
+				Pass::UnstencilPortal { level } => {
+					gl::Enable(gl::STENCIL_TEST);
+					gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+					gl::DepthMask(gl::FALSE);
+					gl::Disable(gl::DEPTH_TEST);
+					gl::StencilMask(0xFF);
+					gl::StencilFunc(gl::NOTEQUAL, level as GLint + 1, 0xFF);
+					gl::StencilOp(gl::DECR, gl::KEEP, gl::KEEP);
+				},
+				Pass::ProtectPortals { level } => {
+					gl::Enable(gl::STENCIL_TEST);
+					gl::StencilMask(0x00);
+					gl::StencilFunc(gl::LEQUAL, level as GLint, 0xFF);
+					gl::StencilOp(gl::KEEP, gl::KEEP, gl::KEEP);
+					gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+					gl::DepthMask(gl::TRUE);
+					gl::Clear(gl::DEPTH_BUFFER_BIT);
+					gl::DepthFunc(gl::ALWAYS);
+				},
+				Pass::Level { level, clear_depth } => {
+					gl::Enable(gl::STENCIL_TEST);
+					gl::StencilMask(0x00);
+					gl::StencilFunc(gl::LEQUAL, level as GLint, 0xFF);
+					gl::StencilOp(gl::KEEP, gl::KEEP, gl::KEEP);
+					gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+					gl::DepthMask(gl::TRUE);
+					if clear_depth {
+						gl::Clear(gl::DEPTH_BUFFER_BIT);
+					}
+				},
+				Pass::Normal => {
+					gl::Disable(gl::STENCIL_TEST);
+					gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+					gl::DepthMask(gl::TRUE);
+				},
+			}
+		}
+	}
 	
 	pub fn get_drawable_size(&self) -> (u32, u32) {
 		self.win.drawable_size()
@@ -133,6 +424,40 @@ impl<'a> Render<'a> {
 		self.render_portals
 	}
 	
+	pub fn set_projection_mode(&mut self, mode: ProjectionMode) {
+		self.projection_mode = mode;
+	}
+	pub fn get_projection_mode(&self) -> ProjectionMode {
+		self.projection_mode
+	}
+	pub fn toggle_projection_mode(&mut self) {
+		self.projection_mode = match self.projection_mode {
+			ProjectionMode::Perspective => ProjectionMode::Oblique,
+			ProjectionMode::Oblique => ProjectionMode::Perspective,
+		};
+	}
+	
+	pub fn set_portal_depth(&mut self, depth: u32) {
+		self.portal_depth = ::std::cmp::min(depth, MAX_PORTAL_DEPTH);
+	}
+	pub fn get_portal_depth(&self) -> u32 {
+		self.portal_depth
+	}
+	
+	pub fn set_portal_fallback(&mut self, fallback: PortalFallback) {
+		self.portal_fallback = fallback;
+	}
+	pub fn get_portal_fallback(&self) -> PortalFallback {
+		self.portal_fallback
+	}
+	pub fn cycle_portal_fallback(&mut self) {
+		self.portal_fallback = match self.portal_fallback {
+			PortalFallback::Color(_) => PortalFallback::LastFrame,
+			PortalFallback::LastFrame => PortalFallback::Nothing,
+			PortalFallback::Nothing => PortalFallback::Color(Render::get_background_color()),
+		};
+	}
+	
 	pub fn get_background_color() -> Vec4 {
 		Vec4::new(0.0, 0.0, 0.3, 1.0)
 	}
@@ -141,6 +466,18 @@ impl<'a> Render<'a> {
 		
 	}
 	
+	/// Recompiles any shaders whose files have changed since they were loaded. If a shader fails to compile,
+	/// the error is printed and the old one is kept.
+	pub fn reload_changed_shaders(&mut self) {
+		for shader in [&mut self.main_shader, &mut self.solid_color_shader, &mut self.last_frame_shader].iter_mut() {
+			match shader.reload_if_changed() {
+				Ok(true) => println!("reloaded shader {}", shader.get_name()),
+				Ok(false) => {},
+				Err(e) => println!("{}", e),
+			}
+		}
+	}
+	
 	pub fn print(&self) {
 		if !self.view_wireframes {
 			print!("NO_");
@@ -149,7 +486,17 @@ impl<'a> Render<'a> {
 		if !self.render_portals {
 			print!("NO_");
 		}
-		print!("RENDER_PORTALS");
+		print!("RENDER_PORTALS - ");
+		if self.projection_mode != ProjectionMode::Oblique {
+			print!("NO_");
+		}
+		print!("OBLIQUE - DEPTH: {} - FALLBACK: ", self.portal_depth);
+		match self.portal_fallback {
+			PortalFallback::Color(_) => print!("COLOR"),
+			PortalFallback::LastFrame => print!("LAST_FRAME"),
+			PortalFallback::Nothing => print!("NOTHING"),
+		}
+		print!(" - GL_OBJECTS: {}", get_live_gl_objects());
 	}
 	
 	// pub fn render_arrow(pos: Vec3, vec: Vec3) {
@@ -157,52 +504,153 @@ impl<'a> Render<'a> {
 	// }
 }
 
+impl<'a> Backend for Render<'a> {
+	fn get_draw_settings(&self) -> DrawSettings {
+		DrawSettings {
+			render_portals: self.render_portals,
+			wireframe: self.view_wireframes,
+			portal_depth: self.portal_depth,
+			portal_fallback: self.portal_fallback,
+		}
+	}
+	
+	fn execute(&mut self, list: &DrawList) {
+		for cmd in list.commands.iter() {
+			match *cmd {
+				DrawCommand::SetLighting(ref lighting) => self.set_lighting(lighting),
+				DrawCommand::SetCamera(ref cam) => self.set_camera(cam),
+				DrawCommand::BeginPass(pass) => self.begin_pass(pass),
+				DrawCommand::Draw { ref mesh, model, shading } => match shading {
+					Shading::Lit => mesh.render(self, model),
+					Shading::Color(c) => mesh.render_color(self, model, c.as_array()),
+					Shading::LastFrame => mesh.render_last_frame(self, model),
+				},
+			}
+		}
+	}
+}
+
+impl<'a> Drop for Render<'a> {
+	fn drop(&mut self) {
+		unsafe {
+			gl::DeleteTextures(1, &self.last_frame_tex);
+		}
+		remove_live_gl_objects(1);
+	}
+}
+
+fn get_modified(path: &Path) -> Option<SystemTime> {
+	fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Where a shader was loaded from, so that it can be reloaded when the files change.
+#[derive(Clone, Debug)]
+struct ShaderFiles {
+	vs: PathBuf,
+	fs: PathBuf,
+	/// Every file the shader was made from, including the ones that were included, and when it was last modified.
+	modified: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
 pub struct Shader {
 	prog: GLuint,
 	vs: GLuint,
 	fs: GLuint,
-	mvp_pos: GLint,
+	/// Every active uniform of the program, found when it was linked.
+	uniforms: Rc<HashMap<String, UniformInfo>>,
+	/// The uniforms that have been warned about, so that each one is only warned about once.
+	warned: Rc<RefCell<HashSet<String>>>,
+	/// The files the shader was loaded from, for messages.
+	name: Rc<String>,
 	owned: bool,
+	/// None if the shader wasn't loaded from files.
+	files: Option<ShaderFiles>,
 }
 
 impl Shader {
-	pub fn from_files(vs: &str, fs: &str) -> Result<Shader, String> {
-		let mut vs_file = match File::open(vs) {
-			Ok(f) => f,
-			Err(e) => return Err(format!("error opening file '{}': {}", vs, e)),
-		};
-		let mut vs_str = Vec::new();
-		match vs_file.read_to_end(&mut vs_str) {
-			Ok(_) => {},
-			Err(e) => return Err(format!("error reading file '{}': {}", vs, e)),
+	/// Loads a shader from a vertex shader file and a fragment shader file, which can use `#include`.
+	pub fn from_files(vs: &Path, fs: &Path) -> Result<Shader, String> {
+		let read_file = |path: &Path| {
+			let mut src = String::new();
+			match File::open(path).and_then(|mut f| f.read_to_string(&mut src)) {
+				Ok(_) => Ok(src),
+				Err(e) => Err(format!("error reading file '{}': {}", path.display(), e)),
+			}
 		};
+		let vs_src = try!(shader_source::load(vs, &read_file));
+		let fs_src = try!(shader_source::load(fs, &read_file));
 		
-		let mut fs_file = match File::open(fs) {
-			Ok(f) => f,
-			Err(e) => return Err(format!("error opening file '{}': {}", fs, e)),
+		let mut shader = match Shader::from_strs(vs_src.text.as_bytes(), fs_src.text.as_bytes()) {
+			Ok(s) => s,
+			Err(e) => return Err(format!("error in shader {} + {}: {}\nfiles in the vertex shader:\n{}files in the fragment shader:\n{}",
+				vs.display(), fs.display(), e, vs_src.describe_files(), fs_src.describe_files())),
 		};
-		let mut fs_str = Vec::new();
-		match fs_file.read_to_end(&mut fs_str) {
-			Ok(_) => {},
-			Err(e) => return Err(format!("error reading file '{}': {}", fs, e)),
+		let files = vs_src.files.iter().chain(fs_src.files.iter());
+		shader.name = Rc::new(format!("{} + {}", vs.display(), fs.display()));
+		shader.files = Some(ShaderFiles {
+			vs: vs.to_path_buf(),
+			fs: fs.to_path_buf(),
+			modified: files.map(|f| (f.clone(), get_modified(f))).collect(),
+		});
+		Ok(shader)
+	}
+	
+	/// Gets the files the shader was loaded from, for messages.
+	pub fn get_name(&self) -> &str {
+		&self.name
+	}
+	
+	/// Reloads the shader if any of its files have changed, returning whether it was reloaded.
+	/// If the new shader fails to compile the old one is kept, and it isn't tried again until the files change again.
+	pub fn reload_if_changed(&mut self) -> Result<bool, String> {
+		let (vs, fs) = match self.files {
+			Some(ref f) if f.modified.iter().any(|&(ref path, time)| get_modified(path) != time) => (f.vs.clone(), f.fs.clone()),
+			_ => return Ok(false),
 		};
-		
-		Shader::from_strs(&vs_str, &fs_str)
+		match Shader::from_files(&vs, &fs) {
+			Ok(shader) => {
+				// The old program is deleted when it is dropped
+				*self = shader;
+				Ok(true)
+			},
+			Err(e) => {
+				if let Some(ref mut f) = self.files {
+					for &mut (ref path, ref mut time) in f.modified.iter_mut() {
+						*time = get_modified(path);
+					}
+				}
+				Err(e)
+			},
+		}
 	}
+	
 	pub fn from_strs(vs_str: &[u8], fs_str: &[u8]) -> Result<Shader, String> {
 		unsafe {
 			let prog = gl::CreateProgram();
 			let vs = gl::CreateShader(gl::VERTEX_SHADER);
 			let fs = gl::CreateShader(gl::FRAGMENT_SHADER);
+			let delete = || {
+				gl::DeleteProgram(prog);
+				gl::DeleteShader(vs);
+				gl::DeleteShader(fs);
+			};
 			
-			try!(Shader::compile_shader(vs, vs_str));
-			try!(Shader::compile_shader(fs, fs_str));
+			if let Err(e) = Shader::compile_shader(vs, vs_str) {
+				delete();
+				return Err(format!("vertex shader: {}", e));
+			}
+			if let Err(e) = Shader::compile_shader(fs, fs_str) {
+				delete();
+				return Err(format!("fragment shader: {}", e));
+			}
 			
 			gl::AttachShader(prog, vs);
 			gl::AttachShader(prog, fs);
 			
-			gl::BindAttribLocation(prog, 0, "in_pos".as_ptr() as *const i8);
-			gl::BindAttribLocation(prog, 1, "in_color".as_ptr() as *const i8);
+			gl::BindAttribLocation(prog, 0, "in_pos\0".as_ptr() as *const i8);
+			gl::BindAttribLocation(prog, 1, "in_color\0".as_ptr() as *const i8);
+			gl::BindAttribLocation(prog, 2, "in_normal\0".as_ptr() as *const i8);
+			gl::BindAttribLocation(prog, 3, "in_uv\0".as_ptr() as *const i8);
 			
 			gl::LinkProgram(prog);
 			
@@ -218,17 +666,22 @@ impl Shader {
 				
 				let mut s = String::from("error linking program: \n");
 				s.push_str(&String::from_utf8_lossy(&log));
+				delete();
 				return Err(s);
 			}
 			
 			gl::UseProgram(prog);
+			add_live_gl_objects(3);
 						
 			Ok(Shader {
 				prog: prog,
 				vs: vs,
 				fs: fs,
-				mvp_pos: gl::GetUniformLocation(prog, CString::new("in_mvp").unwrap().as_ptr()),
+				uniforms: Rc::new(uniform::get_uniforms(prog)),
+				warned: Rc::new(RefCell::new(HashSet::new())),
+				name: Rc::new(String::from("(from strings)")),
 				owned: true,
+				files: None,
 			})
 		}
 	}
@@ -262,19 +715,36 @@ impl Shader {
 	}
 	
 	pub fn set_mvp(&self, mvp: Mat4) {
-		unsafe {
-			gl::UseProgram(self.prog);
-			gl::UniformMatrix4fv(self.mvp_pos, 1, gl::FALSE, mvp.as_ref() as *const GLfloat);
-		}
+		self.set_uniform("in_mvp", mvp);
 	}
 	
-	pub fn set_uniform_4f(&self, name: &CStr, data: &[f32; 4]) {
-		unsafe {
-			let loc = gl::GetUniformLocation(self.prog, name.as_ptr());
-			if loc != 0 {
+	/// Returns true if the program has an active uniform called `name`. Uniforms that are declared but never
+	/// used can be optimised out, so they don't count.
+	pub fn has_uniform(&self, name: &str) -> bool {
+		self.uniforms.contains_key(name)
+	}
+	
+	/// Sets a uniform of the program. Elements of arrays are named like `lights[2]`.
+	/// If there is no such uniform, or it is of another type, a warning is printed the first time and nothing is set.
+	pub fn set_uniform<T: UniformValue>(&self, name: &str, value: T) {
+		match self.uniforms.get(name) {
+			Some(u) if T::matches(u.ty) => unsafe {
 				gl::UseProgram(self.prog);
-				gl::Uniform4f(loc, data[0] as GLfloat, data[1] as GLfloat, data[2] as GLfloat, data[3] as GLfloat);
-			}
+				value.set(u.loc);
+			},
+			Some(u) => self.warn(name, &format!("is a {}, so it can't be set to a {}", uniform::type_name(u.ty), T::type_name())),
+			None => self.warn(name, "does not exist, or is not used by the shader"),
+		}
+	}
+	
+	/// Makes the sampler `name` read from texture unit `unit`.
+	pub fn set_sampler(&self, name: &str, unit: u32) {
+		self.set_uniform(name, Sampler(unit));
+	}
+	
+	fn warn(&self, name: &str, problem: &str) {
+		if self.warned.borrow_mut().insert(String::from(name)) {
+			println!("warning: uniform '{}' of shader {} {}", name, self.name, problem);
 		}
 	}
 }
@@ -287,6 +757,7 @@ impl Drop for Shader {
 				gl::DeleteProgram(self.prog);
 				gl::DeleteShader(self.vs);
 				gl::DeleteShader(self.fs);
+				remove_live_gl_objects(3);
 			}
 		}
 	}
@@ -294,15 +765,36 @@ impl Drop for Shader {
 impl Clone for Shader {
 	fn clone(&self) -> Shader {
 		Shader {
+			prog: self.prog,
+			vs: self.vs,
+			fs: self.fs,
+			uniforms: self.uniforms.clone(),
+			warned: self.warned.clone(),
+			name: self.name.clone(),
 			owned: false,
-			..*self
+			files: None,
 		}
 	}
 }
 
+/// Faces that meet at an angle with a cosine above this share their vertices, so that lighting is smooth across
+/// them. Sharper edges get separate vertices on each side, so that they stay sharp.
+pub const SMOOTH_ANGLE_COS: f32 = 0.5;
+
+/// Gets the normal of the triangle `a`, `b`, `c`, which faces the side it is seen from when it is counterclockwise.
+/// The length is twice the area of the triangle, so that bigger faces count for more when normals are averaged.
+pub fn face_normal(a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+	(b - a).cross(&(c - a))
+}
+
+#[derive(Clone, Debug)]
 pub struct MeshBuilder {
 	verts: Vec<Vec3>,
 	colors: Vec<Vec3>,
+	/// Empty until `with_normals` has worked them out.
+	normals: Vec<Vec3>,
+	/// Texture coordinates, or (0, 0) for vertices without any.
+	uvs: Vec<Vec2>,
 	indices: Vec<na::Vec3<Index>>,
 }
 impl MeshBuilder {
@@ -310,101 +802,119 @@ impl MeshBuilder {
 		MeshBuilder {
 			verts: Vec::new(),
 			colors: Vec::new(),
+			normals: Vec::new(),
+			uvs: Vec::new(),
 			indices: Vec::new(),
 		}
 	}
 	
 	pub fn push(&mut self, vert: Vec3, color: Vec3) -> Index {
+		self.push_uv(vert, color, Vec2::new(0.0, 0.0))
+	}
+	pub fn push_uv(&mut self, vert: Vec3, color: Vec3, uv: Vec2) -> Index {
+		if self.verts.len() > Index::max_value() as usize {
+			panic!("too many vertices in mesh: {} can't be indexed", self.verts.len() + 1);
+		}
 		self.verts.push(vert);
 		self.colors.push(color);
-		self.verts.len() as Index - 1
+		self.uvs.push(uv);
+		(self.verts.len() - 1) as Index
 	}
 	pub fn index(&mut self, i: na::Vec3<Index>) {
 		self.indices.push(i);
 	}
 	
-	pub fn finish(&self) -> Mesh {
-		if self.indices.len() == 0 {
-			// println!("===============================");
-			// println!("verts:   {:?}", self.verts);
-			// println!("colors:  {:?}", self.colors);
-			Mesh::new(&self.verts, &self.colors)
-		} else {
-			Mesh::indexed(&self.verts, &self.indices, &self.colors)
+	pub fn len(&self) -> usize {
+		self.verts.len()
+	}
+	
+	/// Gets the type of index buffer the mesh will be uploaded with.
+	pub fn get_index_type(&self) -> IndexType {
+		IndexType::for_vert_count(self.verts.len())
+	}
+	
+	pub fn from_indexed(verts: &[Vec3], indices: &[na::Vec3<Index>], colors: &[Vec3]) -> MeshBuilder {
+		MeshBuilder {
+			verts: verts.to_vec(),
+			colors: colors.to_vec(),
+			normals: Vec::new(),
+			uvs: vec![Vec2::new(0.0, 0.0); verts.len()],
+			indices: indices.to_vec(),
 		}
 	}
-}
-
-// This makes it so that Meshes leak during the program as entities die, but it does speed up the process of cloning the world
-#[derive(Debug, Copy, Clone)]
-pub struct Mesh {
-	vao: GLuint,
-	vert_len: GLsizei,
-	len: GLsizei,
-	indices: Option<GLuint>,
-	verts: GLuint,
-	colors: GLuint,
-}
-impl Mesh {
-	pub fn indexed(verts: &[Vec3], indices: &[na::Vec3<Index>], colors: &[Vec3]) -> Mesh {
-		unsafe {
-			// println!("===============================");
-			// println!("verts:   {:?}", verts);
-			// println!("colors:  {:?}", colors);
-			// println!("indices: {:?}", indices);
-			
-			let mut m = Mesh::new(verts, colors);
-			
-			let mut inds = 0;
-			gl::GenBuffers(1, &mut inds);
-			gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, inds);
-			gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (indices.len() * mem::size_of::<na::Vec3<Index>>()) as isize, mem::transmute(indices.as_ptr()), gl::STATIC_DRAW);
-			gl::Flush();
-			
-			m.indices = Some(inds);
-			m.len = indices.len() as GLsizei * 3;
-			m
+	
+	/// Gets the triangles of the mesh. Meshes without indices use every three vertices as a triangle.
+	fn get_triangles(&self) -> Vec<[usize; 3]> {
+		if self.indices.len() == 0 {
+			(0..self.verts.len() / 3).map(|i| [i * 3, i * 3 + 1, i * 3 + 2]).collect()
+		} else {
+			self.indices.iter().map(|i| [i.x as usize, i.y as usize, i.z as usize]).collect()
 		}
 	}
 	
-	pub fn new(verts: &[Vec3], colors: &[Vec3]) -> Mesh {
-		unsafe {
-			let mut vao: GLuint = 0;
-			gl::GenVertexArrays(1, &mut vao);
-			gl::BindVertexArray(vao);
-			
-			let mut vbo: [GLuint; 2] = [0, 0];
-			gl::GenBuffers(2, &mut vbo[0]);
-			
-			// Specify that the data to be pushed is the verts
-			gl::BindBuffer(gl::ARRAY_BUFFER, vbo[0]);
-			// Push the verts to the GPU
-			gl::BufferData(gl::ARRAY_BUFFER, (verts.len() * mem::size_of::<Vec3>()) as isize, mem::transmute(verts.as_ptr()), gl::STATIC_DRAW);
-			// Specify that it is attribute 0
-			gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0, null());
-			gl::EnableVertexAttribArray(0);
-			
-			// Do the same, but with the colors
-			gl::BindBuffer(gl::ARRAY_BUFFER, vbo[1]);
-			gl::BufferData(gl::ARRAY_BUFFER, (colors.len() * mem::size_of::<Vec3>()) as isize, mem::transmute(colors.as_ptr()), gl::STATIC_DRAW);
-			gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, 0, null());
-			gl::EnableVertexAttribArray(1);
-			
-			// So that we copy the verts + colors over before they are freed...
-			gl::Flush();
-			
-			Mesh {
-				vao: vao,
-				vert_len: verts.len() as GLsizei,
-				len: verts.len() as GLsizei,
-				indices: None,
-				verts: vbo[0],
-				colors: vbo[1],
+	/// Works out the normal of every vertex from the faces around it, and returns the mesh with them.
+	/// Vertices on edges sharper than `SMOOTH_ANGLE_COS` are split, as are vertices shared by the front and back
+	/// of double sided meshes, so the returned mesh can have more vertices than this one. It is always indexed.
+	pub fn with_normals(&self) -> MeshBuilder {
+		let tris = self.get_triangles();
+		let face_normals: Vec<Vec3> = tris.iter().map(|t| face_normal(self.verts[t[0]], self.verts[t[1]], self.verts[t[2]])).collect();
+		let unit = |n: Vec3| if n.norm() > 0.0 { n.normalize() } else { n };
+		
+		// The faces that use each vertex
+		let mut vert_faces: Vec<Vec<usize>> = vec![Vec::new(); self.verts.len()];
+		for (f, t) in tris.iter().enumerate() {
+			for &v in t.iter() {
+				if !vert_faces[v].contains(&f) {
+					vert_faces[v].push(f);
+				}
 			}
 		}
+		
+		let mut mb = MeshBuilder::new();
+		// The vertices made from each vertex of this mesh, by their normal
+		let mut made: HashMap<usize, Vec<(Vec3, Index)>> = HashMap::new();
+		for (f, t) in tris.iter().enumerate() {
+			let mut tri = [0 as Index; 3];
+			for (corner, &v) in t.iter().enumerate() {
+				// Average the faces around the vertex that are close enough to this one to be smooth with it
+				let n = unit(face_normals[f]);
+				let mut sum = Vec3::new(0.0, 0.0, 0.0);
+				for &g in vert_faces[v].iter() {
+					if g == f || unit(face_normals[g]).dot(&n) >= SMOOTH_ANGLE_COS {
+						sum = sum + face_normals[g];
+					}
+				}
+				// Degenerate faces have no direction, so just pick one
+				let normal = if sum.norm() > 0.0 { sum.normalize() } else { Vec3::new(0.0, 1.0, 0.0) };
+				
+				let variants = made.entry(v).or_insert(Vec::new());
+				tri[corner] = match variants.iter().find(|&&(vn, _)| vn.dot(&normal) > 0.9999) {
+					Some(&(_, i)) => i,
+					None => {
+						let i = mb.push_uv(self.verts[v], self.colors[v], self.uvs[v]);
+						mb.normals.push(normal);
+						variants.push((normal, i));
+						i
+					},
+				};
+			}
+			mb.index(na::Vec3::new(tri[0], tri[1], tri[2]));
+		}
+		mb
 	}
 	
-	pub fn new_rect_torus(w: f32, h: f32, d: f32) -> Mesh {
+	/// Builds a triangle mesh from the vertices, for collision detection.
+	pub fn to_trimesh(&self) -> TriMesh {
+		let verts: Vec<Pnt3> = self.verts.iter().map(|v| v.to_pnt()).collect();
+		let indices: Vec<na::Pnt3<usize>> = if self.indices.len() == 0 {
+			(0..self.verts.len() / 3).map(|i| na::Pnt3::new(i * 3, i * 3 + 1, i * 3 + 2)).collect()
+		} else {
+			self.indices.iter().map(|i| na::Pnt3::new(i.x as usize, i.y as usize, i.z as usize)).collect()
+		};
+		TriMesh::new(Arc::new(verts), Arc::new(indices), None, None)
+	}
+	
+	pub fn new_rect_torus(w: f32, h: f32, d: f32) -> MeshBuilder {
 		/*let plane_x = normal.cross(&tangent).normalize();
 		let x = plane_x * w;
 		let out_x = x + plane_x * d;
@@ -451,7 +961,7 @@ impl Mesh {
 			color[i] = Vec3::new(Rand::rand(&mut rng), Rand::rand(&mut rng), Rand::rand(&mut rng));
 		}*/
 		
-		Mesh::indexed(&[
+		MeshBuilder::from_indexed(&[
 				- out_x + out_y - z, // 0
 				-     x + out_y - z,
 				- out_x +     y - z,
@@ -546,13 +1056,13 @@ impl Mesh {
 				
 			], &color)
 	}
-	pub fn new_rectangle(w: f32, h: f32, color: Vec3) -> Mesh {
+	pub fn new_rectangle(w: f32, h: f32, color: Vec3) -> MeshBuilder {
 		//let plane_x = normal.cross(&tangent).normalize() * w;
 		//let plane_y = normal.cross(&plane_x).normalize() * h;
 		let plane_x = Vec3::new(w / 2.0, 0.0, 0.0);
 		let plane_y = Vec3::new(0.0, h / 2.0, 0.0);
 		
-		Mesh::indexed(&[
+		MeshBuilder::from_indexed(&[
 				- plane_x + plane_y,
 				- plane_x - plane_y,
 				  plane_x - plane_y,
@@ -567,13 +1077,13 @@ impl Mesh {
 				color,
 			])
 	}
-	pub fn new_rectangle_double(w: f32, h: f32, color: Vec3) -> Mesh {
+	pub fn new_rectangle_double(w: f32, h: f32, color: Vec3) -> MeshBuilder {
 		//let plane_x = normal.cross(&tangent).normalize() * w;
 		//let plane_y = normal.cross(&plane_x).normalize() * h;
 		let plane_x = Vec3::new(w / 2.0, 0.0, 0.0);
 		let plane_y = Vec3::new(0.0, h / 2.0, 0.0);
 		
-		Mesh::indexed(&[
+		MeshBuilder::from_indexed(&[
 				- plane_x + plane_y,
 				- plane_x - plane_y,
 				  plane_x - plane_y,
@@ -590,8 +1100,8 @@ impl Mesh {
 				color,
 			])
 	}
-	pub fn new_triangle(scale: f32) -> Mesh {
-		Mesh::indexed(&[
+	pub fn new_triangle(scale: f32) -> MeshBuilder {
+		MeshBuilder::from_indexed(&[
 			Vec3::new(-0.5,  0.0, 0.0) * scale,
 			Vec3::new( 0.5,  0.0, 0.0) * scale,
 			Vec3::new( 0.0,  1.0, 0.0) * scale,
@@ -604,8 +1114,8 @@ impl Mesh {
 			Vec3::new(0.0, 0.0, 1.0),
 		])
 	}
-	pub fn new_square(scale: f32) -> Mesh {
-		Mesh::indexed(&[
+	pub fn new_square(scale: f32) -> MeshBuilder {
+		MeshBuilder::from_indexed(&[
 			Vec3::new(-0.5,  1.0, 0.0) * scale,
 			Vec3::new(-0.5,  0.0, 0.0) * scale,
 			Vec3::new( 0.5,  0.0, 0.0) * scale,
@@ -623,7 +1133,22 @@ impl Mesh {
 			Vec3::new(1.0, 1.0, 1.0),
 		])
 	}
-	pub fn new_planes(num_w: u32, num_h: u32, w: f32, h: f32, color1: Vec3, color2: Vec3) -> Mesh {
+	/// Creates a box of size `w` by `h` by `d`, centred on the origin.
+	pub fn new_cuboid(w: f32, h: f32, d: f32, color: Vec3) -> MeshBuilder {
+		let (x, y, z) = (w / 2.0, h / 2.0, d / 2.0);
+		// Corner i is on the +x side if bit 0 is set, +y for bit 1 and +z for bit 2
+		let verts: Vec<Vec3> = (0..8).map(|i| Vec3::new(
+			if i & 1 == 0 { -x } else { x },
+			if i & 2 == 0 { -y } else { y },
+			if i & 4 == 0 { -z } else { z })).collect();
+		let mut indices = Vec::new();
+		for q in [[0, 2, 3, 1], [4, 5, 7, 6], [0, 4, 6, 2], [1, 3, 7, 5], [0, 1, 5, 4], [2, 6, 7, 3]].iter() {
+			indices.push(na::Vec3::new(q[0], q[1], q[2]));
+			indices.push(na::Vec3::new(q[0], q[2], q[3]));
+		}
+		MeshBuilder::from_indexed(&verts, &indices, &[color; 8])
+	}
+	pub fn new_planes(num_w: u32, num_h: u32, w: f32, h: f32, color1: Vec3, color2: Vec3) -> MeshBuilder {
 		let mut mb = MeshBuilder::new();
 		let offset_x: f32 = w as f32 / 2.0;
 		let offset_y: f32 = h as f32 / 2.0;
@@ -634,20 +1159,257 @@ impl Mesh {
 				} else {
 					color2
 				};
+				// Each plane shows the whole texture once, so it tiles across the planes
+				let (u, v) = (x as f32, y as f32);
 				let mut i = [0 as Index, 0, 0, 0];
-				i[0] = mb.push(Vec3::new((x as f32      ) * (w / num_w as f32) - offset_x, 0.0, (y as f32      ) * (h / num_h as f32) - offset_y), col);
-				i[1] = mb.push(Vec3::new((x as f32      ) * (w / num_w as f32) - offset_x, 0.0, (y as f32 + 1.0) * (h / num_h as f32) - offset_y), col);
-				i[2] = mb.push(Vec3::new((x as f32 + 1.0) * (w / num_w as f32) - offset_x, 0.0, (y as f32 + 1.0) * (h / num_h as f32) - offset_y), col);
-				i[3] = mb.push(Vec3::new((x as f32 + 1.0) * (w / num_w as f32) - offset_x, 0.0, (y as f32      ) * (h / num_h as f32) - offset_y), col);
+				i[0] = mb.push_uv(Vec3::new((x as f32      ) * (w / num_w as f32) - offset_x, 0.0, (y as f32      ) * (h / num_h as f32) - offset_y), col, Vec2::new(u,       v));
+				i[1] = mb.push_uv(Vec3::new((x as f32      ) * (w / num_w as f32) - offset_x, 0.0, (y as f32 + 1.0) * (h / num_h as f32) - offset_y), col, Vec2::new(u,       v + 1.0));
+				i[2] = mb.push_uv(Vec3::new((x as f32 + 1.0) * (w / num_w as f32) - offset_x, 0.0, (y as f32 + 1.0) * (h / num_h as f32) - offset_y), col, Vec2::new(u + 1.0, v + 1.0));
+				i[3] = mb.push_uv(Vec3::new((x as f32 + 1.0) * (w / num_w as f32) - offset_x, 0.0, (y as f32      ) * (h / num_h as f32) - offset_y), col, Vec2::new(u + 1.0, v));
 				
 				mb.index(na::Vec3::new(i[0], i[1], i[2]));
 				mb.index(na::Vec3::new(i[2], i[3], i[0]));
 			}
 		}
-		mb.finish()
+		mb
+	}
+	
+	pub fn finish(&self) -> Mesh {
+		if self.normals.len() != self.verts.len() {
+			return self.with_normals().finish();
+		}
+		if self.indices.len() == 0 {
+			// println!("===============================");
+			// println!("verts:   {:?}", self.verts);
+			// println!("colors:  {:?}", self.colors);
+			Mesh::new(&self.verts, &self.colors, &self.normals, &self.uvs)
+		} else {
+			Mesh::indexed(&self.verts, &self.indices, &self.colors, &self.normals, &self.uvs)
+		}
+	}
+}
+
+/// The OpenGL objects of a mesh, which are deleted when it is dropped.
+#[derive(Debug)]
+struct MeshBuffers {
+	vao: GLuint,
+	verts: GLuint,
+	colors: GLuint,
+	normals: GLuint,
+	uvs: GLuint,
+	indices: Option<GLuint>,
+}
+impl Drop for MeshBuffers {
+	fn drop(&mut self) {
+		if self.vao == 0 {
+			return;
+		}
+		// Tests make buffers without a context, which still have to be counted
+		if has_gl() {
+			unsafe {
+				gl::DeleteBuffers(1, &self.verts);
+				gl::DeleteBuffers(1, &self.colors);
+				gl::DeleteBuffers(1, &self.normals);
+				gl::DeleteBuffers(1, &self.uvs);
+				if let Some(inds) = self.indices {
+					gl::DeleteBuffers(1, &inds);
+				}
+				gl::DeleteVertexArrays(1, &self.vao);
+			}
+		}
+		remove_live_gl_objects(if self.indices.is_some() { 6 } else { 5 });
+	}
+}
+
+/// The vertices of a mesh, kept on the CPU for backends that don't use OpenGL, such as `SoftRender`.
+/// Meshes only keep this when there is no OpenGL context, as otherwise everything is drawn from the GPU.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Geometry {
+	pub verts: Vec<Vec3>,
+	pub colors: Vec<Vec3>,
+	pub normals: Vec<Vec3>,
+	pub uvs: Vec<Vec2>,
+	/// Every triangle, as indices into the vertices.
+	pub triangles: Vec<[usize; 3]>,
+}
+impl Geometry {
+	/// Creates the geometry of an unindexed mesh, where every three vertices are a triangle.
+	pub fn new(verts: &[Vec3], colors: &[Vec3], normals: &[Vec3], uvs: &[Vec2]) -> Geometry {
+		Geometry {
+			verts: verts.to_vec(),
+			colors: colors.to_vec(),
+			normals: normals.to_vec(),
+			uvs: uvs.to_vec(),
+			triangles: (0..verts.len() / 3).map(|i| [i * 3, i * 3 + 1, i * 3 + 2]).collect(),
+		}
+	}
+}
+
+// Cloning a mesh only copies the handle, so cloning the world stays cheap. The buffers are freed when the last clone is dropped.
+#[derive(Debug, Clone)]
+pub struct Mesh {
+	buffers: Rc<MeshBuffers>,
+	/// The positions of the vertices, which are needed for collision shapes even when the mesh is on the GPU.
+	verts: Rc<Vec<Vec3>>,
+	geometry: Option<Rc<Geometry>>,
+	vert_len: GLsizei,
+	len: GLsizei,
+	index_type: IndexType,
+	texture: Option<Texture>,
+}
+impl Mesh {
+	pub fn indexed(verts: &[Vec3], indices: &[na::Vec3<Index>], colors: &[Vec3], normals: &[Vec3], uvs: &[Vec2]) -> Mesh {
+		if let Some(i) = indices.iter().find(|i| i.x as usize >= verts.len() || i.y as usize >= verts.len() || i.z as usize >= verts.len()) {
+			panic!("mesh index {:?} is out of range of the {} vertices", i, verts.len());
+		}
+		unsafe {
+			// println!("===============================");
+			// println!("verts:   {:?}", verts);
+			// println!("colors:  {:?}", colors);
+			// println!("indices: {:?}", indices);
+			
+			let mut m = Mesh::new(verts, colors, normals, uvs);
+			m.len = indices.len() as GLsizei * 3;
+			m.index_type = IndexType::for_vert_count(verts.len());
+			if let Some(ref mut geometry) = m.geometry {
+				Rc::get_mut(geometry).unwrap().triangles = indices.iter().map(|i| [i.x as usize, i.y as usize, i.z as usize]).collect();
+			}
+			if !m.is_uploaded() {
+				return m;
+			}
+			
+			let mut inds = 0;
+			gl::GenBuffers(1, &mut inds);
+			gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, inds);
+			match m.index_type {
+				IndexType::U16 => {
+					// Every index is below the number of vertices, so this can't fail
+					let small = to_u16_indices(indices).unwrap();
+					gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (small.len() * mem::size_of::<na::Vec3<GLushort>>()) as isize, mem::transmute(small.as_ptr()), gl::STATIC_DRAW);
+				},
+				IndexType::U32 => {
+					gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (indices.len() * mem::size_of::<na::Vec3<Index>>()) as isize, mem::transmute(indices.as_ptr()), gl::STATIC_DRAW);
+				},
+			}
+			gl::Flush();
+			add_live_gl_objects(1);
+			
+			// Nothing else can have a reference to the buffers yet
+			Rc::get_mut(&mut m.buffers).unwrap().indices = Some(inds);
+			m
+		}
+	}
+	
+	pub fn new(verts: &[Vec3], colors: &[Vec3], normals: &[Vec3], uvs: &[Vec2]) -> Mesh {
+		if !has_gl() {
+			return Mesh {
+				buffers: Rc::new(MeshBuffers { vao: 0, verts: 0, colors: 0, normals: 0, uvs: 0, indices: None }),
+				verts: Rc::new(verts.to_vec()),
+				geometry: Some(Rc::new(Geometry::new(verts, colors, normals, uvs))),
+				vert_len: verts.len() as GLsizei,
+				len: verts.len() as GLsizei,
+				index_type: IndexType::U16,
+				texture: None,
+			};
+		}
+		unsafe {
+			let mut vao: GLuint = 0;
+			gl::GenVertexArrays(1, &mut vao);
+			gl::BindVertexArray(vao);
+			
+			let mut vbo: [GLuint; 4] = [0, 0, 0, 0];
+			gl::GenBuffers(4, &mut vbo[0]);
+			
+			// Specify that the data to be pushed is the verts
+			gl::BindBuffer(gl::ARRAY_BUFFER, vbo[0]);
+			// Push the verts to the GPU
+			gl::BufferData(gl::ARRAY_BUFFER, (verts.len() * mem::size_of::<Vec3>()) as isize, mem::transmute(verts.as_ptr()), gl::STATIC_DRAW);
+			// Specify that it is attribute 0
+			gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0, null());
+			gl::EnableVertexAttribArray(0);
+			
+			// Do the same, but with the colors
+			gl::BindBuffer(gl::ARRAY_BUFFER, vbo[1]);
+			gl::BufferData(gl::ARRAY_BUFFER, (colors.len() * mem::size_of::<Vec3>()) as isize, mem::transmute(colors.as_ptr()), gl::STATIC_DRAW);
+			gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, 0, null());
+			gl::EnableVertexAttribArray(1);
+			
+			// And the normals
+			gl::BindBuffer(gl::ARRAY_BUFFER, vbo[2]);
+			gl::BufferData(gl::ARRAY_BUFFER, (normals.len() * mem::size_of::<Vec3>()) as isize, mem::transmute(normals.as_ptr()), gl::STATIC_DRAW);
+			gl::VertexAttribPointer(2, 3, gl::FLOAT, gl::FALSE, 0, null());
+			gl::EnableVertexAttribArray(2);
+			
+			// And the texture coordinates
+			gl::BindBuffer(gl::ARRAY_BUFFER, vbo[3]);
+			gl::BufferData(gl::ARRAY_BUFFER, (uvs.len() * mem::size_of::<Vec2>()) as isize, mem::transmute(uvs.as_ptr()), gl::STATIC_DRAW);
+			gl::VertexAttribPointer(3, 2, gl::FLOAT, gl::FALSE, 0, null());
+			gl::EnableVertexAttribArray(3);
+			
+			// So that we copy the verts + colors + normals + uvs over before they are freed...
+			gl::Flush();
+			add_live_gl_objects(5);
+			
+			Mesh {
+				buffers: Rc::new(MeshBuffers { vao: vao, verts: vbo[0], colors: vbo[1], normals: vbo[2], uvs: vbo[3], indices: None }),
+				verts: Rc::new(verts.to_vec()),
+				geometry: None,
+				vert_len: verts.len() as GLsizei,
+				len: verts.len() as GLsizei,
+				index_type: IndexType::U16,
+				texture: None,
+			}
+		}
+	}
+	
+	/// Sets the texture that the mesh is drawn with. The texture is multiplied by the vertex colours.
+	pub fn set_texture(&mut self, texture: Option<Texture>) {
+		self.texture = texture;
+	}
+	pub fn get_texture(&self) -> Option<&Texture> {
+		self.texture.as_ref()
+	}
+	
+	pub fn get_verts(&self) -> &[Vec3] {
+		&self.verts
+	}
+	
+	/// Gets the vertices kept on the CPU for drawing, which are only there if the mesh isn't uploaded.
+	pub fn get_geometry(&self) -> Option<&Geometry> {
+		self.geometry.as_ref().map(|g| &**g)
+	}
+	
+	/// Returns false if the mesh only exists on the CPU, because there was no OpenGL context when it was created.
+	pub fn is_uploaded(&self) -> bool {
+		self.buffers.vao != 0
+	}
+	
+	pub fn new_rect_torus(w: f32, h: f32, d: f32) -> Mesh {
+		MeshBuilder::new_rect_torus(w, h, d).finish()
+	}
+	pub fn new_rectangle(w: f32, h: f32, color: Vec3) -> Mesh {
+		MeshBuilder::new_rectangle(w, h, color).finish()
+	}
+	pub fn new_rectangle_double(w: f32, h: f32, color: Vec3) -> Mesh {
+		MeshBuilder::new_rectangle_double(w, h, color).finish()
+	}
+	pub fn new_triangle(scale: f32) -> Mesh {
+		MeshBuilder::new_triangle(scale).finish()
+	}
+	pub fn new_square(scale: f32) -> Mesh {
+		MeshBuilder::new_square(scale).finish()
+	}
+	pub fn new_cuboid(w: f32, h: f32, d: f32, color: Vec3) -> Mesh {
+		MeshBuilder::new_cuboid(w, h, d, color).finish()
+	}
+	pub fn new_planes(num_w: u32, num_h: u32, w: f32, h: f32, color1: Vec3, color2: Vec3) -> Mesh {
+		MeshBuilder::new_planes(num_w, num_h, w, h, color1, color2).finish()
 	}
 	
 	pub fn render(&self, ren: &mut Render, model_mat: Mat4) {
+		if !self.is_uploaded() {
+			return;
+		}
 		unsafe {
 			ren.main_shader.use_prog();
 			{
@@ -655,12 +1417,22 @@ impl Mesh {
 				ren.set_model_mat(&s, model_mat);
 			}
 			
-			gl::BindVertexArray(self.vao);
+			let s = &ren.main_shader;
+			match self.texture {
+				Some(ref tex) if tex.is_uploaded() => {
+					tex.bind(0);
+					s.set_sampler("in_texture", 0);
+					s.set_uniform("in_textured", true);
+				},
+				_ => s.set_uniform("in_textured", false),
+			}
+			
+			gl::BindVertexArray(self.buffers.vao);
 			
-			match self.indices {
+			match self.buffers.indices {
 				Some(_) => {
 					//gl::DrawArrays(gl::POINTS, 0, self.vert_len);
-					gl::DrawElements(gl::TRIANGLES, self.len, gl::UNSIGNED_SHORT, null());
+					gl::DrawElements(gl::TRIANGLES, self.len, self.index_type.gl_type(), null());
 				},
 				None => gl::DrawArrays(gl::TRIANGLES, 0, self.len),
 			}
@@ -668,36 +1440,166 @@ impl Mesh {
 	}
 	
 	pub fn draw(&self) {
+		if !self.is_uploaded() {
+			return;
+		}
 		unsafe {
-			gl::BindVertexArray(self.vao);
+			gl::BindVertexArray(self.buffers.vao);
 			
-			match self.indices {
+			match self.buffers.indices {
 				Some(_) => {
 					//gl::DrawArrays(gl::POINTS, 0, self.vert_len);
-					gl::DrawElements(gl::TRIANGLES, self.len, gl::UNSIGNED_SHORT, null());
+					gl::DrawElements(gl::TRIANGLES, self.len, self.index_type.gl_type(), null());
 				},
 				None => gl::DrawArrays(gl::TRIANGLES, 0, self.len),
 			}
 		}
 	}
+	pub fn render_last_frame(&self, ren: &mut Render, model_mat: Mat4) {
+		{
+			let shdr = ren.last_frame_shader.clone();
+			ren.set_model_mat(&shdr, model_mat);
+		}
+		ren.last_frame_shader.use_prog();
+		unsafe {
+			gl::ActiveTexture(gl::TEXTURE0);
+			gl::BindTexture(gl::TEXTURE_2D, ren.last_frame_tex);
+		}
+		self.draw();
+	}
 	pub fn render_color(&self, ren: &mut Render, model_mat: Mat4, color: &[f32; 4]) {
+		if !self.is_uploaded() {
+			return;
+		}
 		unsafe {
 			{
 				let shdr = ren.solid_color_shader.clone();
 				ren.set_model_mat(&shdr, model_mat);
 			}
 			ren.solid_color_shader.use_prog();
-			ren.solid_color_shader.set_uniform_4f(CStr::from_ptr(mem::transmute("in_color\0".as_ptr())), &color);
+			ren.solid_color_shader.set_uniform("in_color", Vec4::new(color[0], color[1], color[2], color[3]));
 			
-			gl::BindVertexArray(self.vao);
+			gl::BindVertexArray(self.buffers.vao);
 			
-			match self.indices {
+			match self.buffers.indices {
 				Some(_) => {
 					//gl::DrawArrays(gl::POINTS, 0, self.vert_len);
-					gl::DrawElements(gl::TRIANGLES, self.len, gl::UNSIGNED_SHORT, null());
+					gl::DrawElements(gl::TRIANGLES, self.len, self.index_type.gl_type(), null());
 				},
 				None => gl::DrawArrays(gl::TRIANGLES, 0, self.len),
 			}
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prelude::*;
+	use na;
+	use super::MeshBuffers;
+	use entity::{Camera, Entity, Portal};
+	use world::World;
+	use std::rc::Rc;
+	
+	const ASPECT: f32 = 4.0 / 3.0;
+	const FOV: f32 = 1.0;
+	
+	/// Gets the depth of `p` in normalized device coordinates, after transforming it by `mat`.
+	fn ndc_z(mat: &Mat4, p: Vec3) -> f32 {
+		let clip = *mat * Vec4::new(p.x, p.y, p.z, 1.0);
+		assert!(clip.w > 0.0, "{:?} is behind the camera", p);
+		clip.z / clip.w
+	}
+	
+	#[test]
+	fn oblique_projection_moves_near_plane_to_clip_plane() {
+		let proj = Persp3::new(ASPECT, FOV, Z_NEAR, Z_FAR).to_mat();
+		// Keep everything further away than z = 2
+		let oblique = oblique_projection(&proj, &Vec4::new(0.0, 0.0, 1.0, -2.0));
+		for &(x, y) in [(0.0, 0.0), (0.5, -0.3), (-0.4, 0.6)].iter() {
+			assert!((ndc_z(&oblique, Vec3::new(x, y, 2.0)) + 1.0).abs() < 0.001);
+			assert!(ndc_z(&oblique, Vec3::new(x, y, 1.5)) < -1.0);
+			assert!(ndc_z(&oblique, Vec3::new(x, y, 10.0)) > -1.0);
+			// A clip plane facing the camera leaves the far plane where it was
+			let far = Vec3::new(x * Z_FAR, y * Z_FAR, Z_FAR);
+			assert!((ndc_z(&oblique, far) - ndc_z(&proj, far)).abs() < 0.001);
+		}
+	}
+	
+	#[test]
+	fn oblique_projection_clips_at_portal() {
+		let cam = Camera::new(Vec3::new(0.0, 1.0, 0.0), 60.0);
+		let view = cam.get_view();
+		let portal = Portal::new(Vec3::new(0.3, 1.1, 3.0), Rot3::new(Vec3::new(0.0, 0.4, 0.0)), 0.9, 1.4);
+		let plane = portal.get_clip_plane(cam.get_pos());
+		let mvp = Render::get_projection(ProjectionMode::Oblique, ASPECT, cam.get_fov(), &view, Some(plane)) * view;
+		
+		let (normal, towards_cam) = (portal.get_normal(), if portal.get_distance(cam.get_pos()) > 0.0 { 1.0 } else { -1.0 });
+		for &(x, y) in [(0.0, 0.0), (0.4, 0.6), (-0.4, -0.6)].iter() {
+			let on_plane = portal.pos + portal.rot * Vec3::new(x, y, 0.0);
+			assert!((ndc_z(&mvp, on_plane) + 1.0).abs() < 0.001, "{:?} on the portal is at depth {}", on_plane, ndc_z(&mvp, on_plane));
+			// Between the camera and the portal
+			assert!(ndc_z(&mvp, on_plane + normal * (towards_cam * 0.5)) < -1.0);
+			// Through the portal
+			let beyond = on_plane - normal * (towards_cam * 2.0);
+			let z = ndc_z(&mvp, beyond);
+			assert!(z > -1.0 && z < 1.0);
+		}
+	}
+	
+	#[test]
+	fn projection_without_clip_plane_is_plain() {
+		let cam = Camera::new(Vec3::new(0.0, 1.0, 0.0), 60.0);
+		let view = cam.get_view();
+		let plain = Persp3::new(ASPECT, cam.get_fov(), Z_NEAR, Z_FAR).to_mat();
+		let plane = Vec4::new(0.0, 0.0, -1.0, 3.0);
+		assert_eq!(Render::get_projection(ProjectionMode::Oblique, ASPECT, cam.get_fov(), &view, None), plain);
+		assert_eq!(Render::get_projection(ProjectionMode::Perspective, ASPECT, cam.get_fov(), &view, Some(plane)), plain);
+		// The camera is on the plane
+		let through_cam = Vec4::new(0.0, 0.0, -1.0, 0.0);
+		assert_eq!(Render::get_projection(ProjectionMode::Oblique, ASPECT, cam.get_fov(), &view, Some(through_cam)), plain);
+	}
+	
+	#[test]
+	fn index_type_fits_every_vertex() {
+		// Indices go up to one less than the number of vertices
+		assert_eq!(IndexType::for_vert_count(0), IndexType::U16);
+		assert_eq!(IndexType::for_vert_count(65535), IndexType::U16);
+		assert_eq!(IndexType::for_vert_count(65536), IndexType::U16);
+		assert_eq!(IndexType::for_vert_count(65537), IndexType::U32);
+	}
+	
+	#[test]
+	fn u16_indices_must_fit() {
+		let small = [na::Vec3::new(0, 1, 2), na::Vec3::new(65535, 3, 65534)];
+		assert_eq!(to_u16_indices(&small), Some(vec![na::Vec3::new(0, 1, 2), na::Vec3::new(65535, 3, 65534)]));
+		assert_eq!(to_u16_indices(&[]), Some(vec![]));
+		for &big in [na::Vec3::new(65536, 0, 1), na::Vec3::new(0, 70000, 1), na::Vec3::new(0, 1, Index::max_value())].iter() {
+			assert_eq!(to_u16_indices(&[na::Vec3::new(0, 1, 2), big]), None);
+		}
+	}
+	
+	#[test]
+	fn cloned_worlds_share_meshes() {
+		let start = get_live_gl_objects();
+		let mut mesh = MeshBuilder::new_cuboid(1.0, 1.0, 1.0, Vec3::new(1.0, 1.0, 1.0)).finish();
+		// Pretend the mesh is on the GPU. There is no context, so dropping it only updates the count.
+		mesh.buffers = Rc::new(MeshBuffers { vao: 1, verts: 2, colors: 3, normals: 4, uvs: 5, indices: Some(6) });
+		add_live_gl_objects(6);
+		
+		let mut world = World::new(Camera::new(Vec3::new(0.0, 1.0, 0.0), 60.0));
+		world.entities.push(Entity::new_static(Vec3::new(0.0, 0.0, 3.0), mesh.clone()));
+		world.entities.push(Entity::new_static(Vec3::new(2.0, 0.0, 3.0), mesh.clone()));
+		assert_eq!(Rc::strong_count(&mesh.buffers), 3);
+		let copy = world.clone();
+		assert_eq!(Rc::strong_count(&mesh.buffers), 5);
+		assert_eq!(get_live_gl_objects(), start + 6);
+		
+		drop(world);
+		assert_eq!(Rc::strong_count(&mesh.buffers), 3);
+		drop(copy);
+		drop(mesh);
+		assert_eq!(get_live_gl_objects(), start);
+	}
+}
@@ -0,0 +1,405 @@
+//! Recording and replaying of input, so that a play session can be reproduced exactly.
+//!
+//! Recordings are plain text. Each tick is a list of the events that happened before it, followed by a
+//! `tick` line. Held actions carry on from one tick to the next, so only the actions that were pressed or
+//! released since the last tick are written. Lines starting with `#` are comments.
+//!
+//! Actions are recorded rather than keys, so recordings still work after the controls are rebound.
+//!
+//! ```text
+//! tick_rate 60
+//! # action <action that happens once>
+//! action ToggleNoclip
+//! # motion <x> <y>
+//! motion 3 -2
+//! # press <held action>
+//! press MoveForward
+//! # release <held action>
+//! release MoveForward
+//! # move <right> <forward>, analog movement from a controller
+//! move 0.5 1
+//! tick
+//! ```
+//!
+//! As the world is only ever stepped in fixed ticks, feeding a recording back through `World::tick` in the
+//! same order gives the exact same result as the session it was recorded from.
+use prelude::*;
+
+use world::World;
+use actions::{Action, ActionInput, HeldActions};
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// A single input event that happened between two ticks.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputEvent {
+	Action(Action),
+	MouseMotion(f32, f32),
+}
+impl InputEvent {
+	/// Sends the event to the world, the same way the game does.
+	pub fn apply(&self, world: &mut World) {
+		match *self {
+			InputEvent::Action(action) => world.handle_action(action),
+			InputEvent::MouseMotion(x, y) => world.handle_mouse_motion(x, y),
+		}
+	}
+}
+
+/// The input for one tick: the events that happened before it, and the actions held during it.
+#[derive(Clone, Debug)]
+pub struct TickInput {
+	pub events: Vec<InputEvent>,
+	pub actions: HeldActions,
+}
+impl TickInput {
+	/// Sends the events to the world, then steps it by one tick of `dt` seconds.
+	pub fn apply(&self, world: &mut World, dt: DT) {
+		for event in self.events.iter() {
+			event.apply(world);
+		}
+		world.tick(dt, &self.actions);
+	}
+}
+
+/// All of the input of a session, tick by tick.
+#[derive(Clone, Debug)]
+pub struct Recording {
+	pub tick_rate: u32,
+	pub ticks: Vec<TickInput>,
+}
+impl Recording {
+	pub fn new(tick_rate: u32) -> Recording {
+		Recording {
+			tick_rate: tick_rate,
+			ticks: Vec::new(),
+		}
+	}
+	
+	/// Gets the length of a tick in seconds.
+	pub fn get_tick_dt(&self) -> DT {
+		1.0 / self.tick_rate as DT
+	}
+	
+	/// Steps `world` through every tick of the recording.
+	pub fn replay(&self, world: &mut World) {
+		let dt = self.get_tick_dt();
+		for tick in self.ticks.iter() {
+			tick.apply(world, dt);
+		}
+	}
+	
+	/// Loads a recording from a file.
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Recording, String> {
+		let path = path.as_ref();
+		let mut file = match File::open(path) {
+			Ok(f) => f,
+			Err(e) => return Err(format!("error opening recording '{}': {}", path.display(), e)),
+		};
+		let mut src = String::new();
+		match file.read_to_string(&mut src) {
+			Ok(_) => {},
+			Err(e) => return Err(format!("error reading recording '{}': {}", path.display(), e)),
+		}
+		match parse(&src) {
+			Ok(rec) => Ok(rec),
+			Err(e) => Err(format!("error loading recording '{}': {}", path.display(), e)),
+		}
+	}
+	
+	/// Saves the recording to a file.
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+		let path = path.as_ref();
+		let mut file = match File::create(path) {
+			Ok(f) => f,
+			Err(e) => return Err(format!("error creating recording '{}': {}", path.display(), e)),
+		};
+		match file.write_all(write(self).as_bytes()) {
+			Ok(_) => Ok(()),
+			Err(e) => Err(format!("error writing recording '{}': {}", path.display(), e)),
+		}
+	}
+}
+
+/// Builds up a recording while the game is being played.
+pub struct Recorder {
+	recording: Recording,
+	/// Events that have happened since the last tick.
+	pending: Vec<InputEvent>,
+}
+impl Recorder {
+	pub fn new(tick_rate: u32) -> Recorder {
+		Recorder {
+			recording: Recording::new(tick_rate),
+			pending: Vec::new(),
+		}
+	}
+	
+	/// Records an event that was sent to the world.
+	pub fn record_event(&mut self, event: InputEvent) {
+		self.pending.push(event);
+	}
+	
+	/// Records that a tick was simulated with `actions` held.
+	pub fn record_tick(&mut self, actions: &HeldActions) {
+		let events = ::std::mem::replace(&mut self.pending, Vec::new());
+		self.recording.ticks.push(TickInput {
+			events: events,
+			actions: actions.clone(),
+		});
+	}
+	
+	pub fn get_recording(&self) -> &Recording {
+		&self.recording
+	}
+}
+
+/// Writes the recording out in the recording format.
+pub fn write(rec: &Recording) -> String {
+	let mut s = String::new();
+	s.push_str(&format!("tick_rate {}\n", rec.tick_rate));
+	let mut held = HeldActions::new();
+	for tick in rec.ticks.iter() {
+		for event in tick.events.iter() {
+			match *event {
+				InputEvent::Action(action) => s.push_str(&format!("action {}\n", action.name())),
+				InputEvent::MouseMotion(x, y) => s.push_str(&format!("motion {} {}\n", x, y)),
+			}
+		}
+		for action in held.get_actions().iter().filter(|a| !tick.actions.get_actions().contains(a)) {
+			s.push_str(&format!("release {}\n", action.name()));
+		}
+		for action in tick.actions.get_actions().iter().filter(|a| !held.get_actions().contains(a)) {
+			s.push_str(&format!("press {}\n", action.name()));
+		}
+		if tick.actions.get_move_axis() != held.get_move_axis() {
+			let axis = tick.actions.get_move_axis();
+			s.push_str(&format!("move {} {}\n", axis.x, axis.y));
+		}
+		s.push_str("tick\n");
+		held = tick.actions.clone();
+	}
+	s
+}
+
+/// Splits off the first word of `s`, returning it and the rest of the line.
+fn split_word<'a>(s: &'a str, what: &str) -> Result<(&'a str, &'a str), String> {
+	let s = s.trim_left();
+	if s.is_empty() {
+		return Err(format!("expected {}, but the line ended", what));
+	}
+	Ok(match s.find(char::is_whitespace) {
+		Some(i) => (&s[..i], s[i..].trim()),
+		None => (s, ""),
+	})
+}
+
+fn parse_num<T: ::std::str::FromStr>(s: &str, what: &str) -> Result<T, String> {
+	match s.parse() {
+		Ok(v) => Ok(v),
+		Err(_) => Err(format!("expected {}, but found '{}'", what, s)),
+	}
+}
+
+fn parse_action(name: &str) -> Result<Action, String> {
+	match Action::from_name(name) {
+		Some(action) => Ok(action),
+		None => Err(format!("unknown action '{}'", name)),
+	}
+}
+
+/// The state of a recording part way through being parsed.
+struct Parser {
+	rec: Recording,
+	/// Events since the last tick.
+	pending: Vec<InputEvent>,
+	held: HeldActions,
+}
+impl Parser {
+	fn parse_line(&mut self, line: &str) -> Result<(), String> {
+		let (kind, rest) = try!(split_word(line, "a line type"));
+		match kind {
+			"tick_rate" => {
+				self.rec.tick_rate = try!(parse_num(rest, "a tick rate"));
+				if self.rec.tick_rate == 0 {
+					return Err(String::from("tick rate must be above 0"));
+				}
+			},
+			"action" => {
+				self.pending.push(InputEvent::Action(try!(parse_action(rest))));
+			},
+			"motion" => {
+				let (x, y) = try!(split_word(rest, "x motion"));
+				self.pending.push(InputEvent::MouseMotion(try!(parse_num(x, "x motion")), try!(parse_num(y, "y motion"))));
+			},
+			"move" => {
+				let (x, y) = try!(split_word(rest, "right movement"));
+				self.held.set_move_axis(Vec2::new(try!(parse_num(x, "right movement")), try!(parse_num(y, "forward movement"))));
+			},
+			"press" => self.held.press(try!(parse_action(rest))),
+			"release" => self.held.release(try!(parse_action(rest))),
+			"tick" => {
+				self.rec.ticks.push(TickInput {
+					events: ::std::mem::replace(&mut self.pending, Vec::new()),
+					actions: self.held.clone(),
+				});
+			},
+			_ => return Err(format!("unknown line type '{}'", kind)),
+		}
+		Ok(())
+	}
+}
+
+/// Parses a recording from its text form.
+pub fn parse(src: &str) -> Result<Recording, String> {
+	let mut parser = Parser {
+		rec: Recording::new(::game::DEFAULT_TICK_RATE),
+		pending: Vec::new(),
+		held: HeldActions::new(),
+	};
+	for (i, line) in src.lines().enumerate() {
+		if line.trim().is_empty() || line.trim_left().starts_with('#') {
+			continue;
+		}
+		if let Err(e) = parser.parse_line(line) {
+			return Err(format!("line {}: {}", i + 1, e));
+		}
+	}
+	if !parser.pending.is_empty() {
+		// Events after the last tick never reached the world
+		println!("warning: {} events after the last tick of the recording were ignored", parser.pending.len());
+	}
+	Ok(parser.rec)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prelude::*;
+	use actions::{Action, ActionInput, HeldActions};
+	use headless;
+	use level;
+	use std::path::Path;
+	
+	/// Returns true if the same actions are held in both, in any order.
+	fn same_actions(a: &HeldActions, b: &HeldActions) -> bool {
+		a.get_actions().len() == b.get_actions().len() && a.get_actions().iter().all(|x| b.get_actions().contains(x))
+			&& a.get_move_axis() == b.get_move_axis()
+	}
+	
+	fn assert_same(a: &Recording, b: &Recording) {
+		assert_eq!(a.tick_rate, b.tick_rate);
+		assert_eq!(a.ticks.len(), b.ticks.len());
+		for (i, (x, y)) in a.ticks.iter().zip(b.ticks.iter()).enumerate() {
+			assert!(x.events == y.events, "events of tick {}: {:?} and {:?}", i, x.events, y.events);
+			assert!(same_actions(&x.actions, &y.actions), "held actions of tick {}: {:?} and {:?}", i, x.actions, y.actions);
+		}
+	}
+	
+	const TEXT: &'static str = "\
+tick_rate 30
+press MoveForward
+tick
+action ToggleNoclip
+motion 3.5 -2
+press Sprint
+move 0.25 -1
+tick
+release MoveForward
+tick
+action FireBluePortal
+move 0 0
+tick
+";
+	
+	#[test]
+	fn parse_and_write() {
+		let rec = parse(TEXT).unwrap();
+		assert_eq!(rec.tick_rate, 30);
+		assert_eq!(rec.ticks.len(), 4);
+		assert!(rec.ticks[0].events.is_empty());
+		assert_eq!(rec.ticks[0].actions.get_actions(), &[Action::MoveForward]);
+		assert_eq!(rec.ticks[1].events, vec![InputEvent::Action(Action::ToggleNoclip), InputEvent::MouseMotion(3.5, -2.0)]);
+		assert_eq!(rec.ticks[1].actions.get_actions(), &[Action::MoveForward, Action::Sprint]);
+		assert_eq!(rec.ticks[1].actions.get_move_axis(), Vec2::new(0.25, -1.0));
+		// Held actions and movement carry on until they are changed
+		assert_eq!(rec.ticks[2].actions.get_actions(), &[Action::Sprint]);
+		assert_eq!(rec.ticks[2].actions.get_move_axis(), Vec2::new(0.25, -1.0));
+		assert_eq!(rec.ticks[3].actions.get_move_axis(), Vec2::new(0.0, 0.0));
+		
+		assert_eq!(write(&rec), TEXT);
+		assert_same(&parse(&write(&rec)).unwrap(), &rec);
+	}
+	
+	#[test]
+	fn comments_and_blank_lines_are_skipped() {
+		let rec = parse("# a recording\n\ntick_rate 60\n  # held\npress Jump\n\ntick\n").unwrap();
+		assert_eq!(rec.tick_rate, 60);
+		assert_eq!(rec.ticks.len(), 1);
+		assert_eq!(rec.ticks[0].actions.get_actions(), &[Action::Jump]);
+	}
+	
+	#[test]
+	fn malformed_lines_are_errors() {
+		for &(src, line, message) in [
+			("tick\nbounce\n", 2, "unknown line type 'bounce'"),
+			("press Fly\n", 1, "unknown action 'Fly'"),
+			("tick_rate 0\n", 1, "tick rate must be above 0"),
+			("tick_rate fast\n", 1, "expected a tick rate, but found 'fast'"),
+			("tick\n\nmotion 3\n", 3, "expected y motion"),
+			("motion 3 up\n", 1, "expected y motion, but found 'up'"),
+			("move 1\n", 1, "expected forward movement"),
+			("action\n", 1, "unknown action ''"),
+		].iter() {
+			let err = parse(src).unwrap_err();
+			assert!(err.starts_with(&format!("line {}: ", line)), "{:?} gave '{}'", src, err);
+			assert!(err.contains(message), "{:?} gave '{}'", src, err);
+		}
+	}
+	
+	const LEVEL: &'static str = "
+camera 0 1 0
+static 0 0 0  planes 10 10 10 10  1 1 1  0 0 0
+dynamic 1.3 2.5 2.0  0 0 0  cuboid 0.3 0.3 0.3  0.3 0.5 0.8
+portal  0.0 1 4.0  0 0 0  0.9 1.4
+portal -1.5 1 2.5  0 3.14159 0  0.9 1.4
+";
+	
+	#[test]
+	fn replays_give_the_same_camera() {
+		let mut world = level::parse(LEVEL, Path::new(".")).unwrap();
+		let mut recorder = Recorder::new(60);
+		let dt = recorder.get_recording().get_tick_dt();
+		let mut held = HeldActions::new();
+		for i in 0..240 {
+			// Walk forward, turning and jumping now and then
+			match i {
+				0 => held.press(Action::MoveForward),
+				60 => held.press(Action::Sprint),
+				90 => held.press(Action::Jump),
+				95 => held.release(Action::Jump),
+				150 => held.set_move_axis(Vec2::new(0.5, 0.25)),
+				200 => held.release(Action::MoveForward),
+				_ => {},
+			}
+			if i % 20 == 10 {
+				let event = InputEvent::MouseMotion(7.0, if i < 120 { 1.5 } else { -2.0 });
+				event.apply(&mut world);
+				recorder.record_event(event);
+			}
+			world.tick(dt, &held);
+			recorder.record_tick(&held);
+		}
+		
+		// Through the text format, as a saved recording would be
+		let rec = parse(&write(recorder.get_recording())).unwrap();
+		assert_same(&rec, recorder.get_recording());
+		let replayed = headless::replay_and_dump(level::parse(LEVEL, Path::new(".")).unwrap(), &rec);
+		assert_eq!(replayed.camera.get_pos(), world.camera.get_pos());
+		assert_eq!(replayed.camera.get_view(), world.camera.get_view());
+		assert_eq!(replayed.camera.get_transits(), world.camera.get_transits());
+		assert!(world.camera.get_pos() != Vec3::new(0.0, 1.0, 0.0));
+	}
+}
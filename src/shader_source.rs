@@ -0,0 +1,180 @@
+//! Reading of shader source files, with support for `#include`.
+//!
+//! A line of the form `#include "path"` is replaced by the contents of that file, where the path is relative
+//! to the file that includes it. Included files can include other files, but not themselves. Each file is only
+//! included once, so a file that is included from two places is only in the source the first time.
+//!
+//! So that compile errors point at the right place, `#line` directives are inserted around included files.
+//! GLSL can't name files in `#line`, so the file is given as its index in `Source::files` instead. An error at
+//! `1(12)` is on line 12 of the second file.
+use std::path::{Path, PathBuf};
+
+/// A shader, with every include resolved.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Source {
+	pub text: String,
+	/// The main file first, followed by every file it includes.
+	pub files: Vec<PathBuf>,
+}
+impl Source {
+	/// Lists the files with their index, to make sense of the file numbers in compile errors.
+	pub fn describe_files(&self) -> String {
+		let mut s = String::new();
+		for (i, file) in self.files.iter().enumerate() {
+			s.push_str(&format!("    {}: {}\n", i, file.display()));
+		}
+		s
+	}
+}
+
+/// Gets the path in an `#include "path"` line, or None if the line isn't an include.
+pub fn parse_include(line: &str) -> Option<Result<&str, String>> {
+	let line = line.trim();
+	if !line.starts_with("#include") {
+		return None;
+	}
+	let rest = line["#include".len()..].trim();
+	if rest.len() >= 2 && rest.starts_with('"') && rest.ends_with('"') {
+		Some(Ok(&rest[1..rest.len() - 1]))
+	} else {
+		Some(Err(format!("expected a path in quotes after #include, but found '{}'", rest)))
+	}
+}
+
+/// Reads the shader at `path`, replacing includes with the files they refer to. `read_file` reads a single file.
+pub fn load<F: Fn(&Path) -> Result<String, String>>(path: &Path, read_file: &F) -> Result<Source, String> {
+	let mut source = Source {
+		text: String::new(),
+		files: Vec::new(),
+	};
+	try!(append_file(&mut source, path, &mut Vec::new(), read_file));
+	Ok(source)
+}
+
+/// Adds the file at `path` to the end of the source. `stack` is the files that are being included, innermost last.
+fn append_file<F: Fn(&Path) -> Result<String, String>>(source: &mut Source, path: &Path, stack: &mut Vec<PathBuf>, read_file: &F) -> Result<(), String> {
+	if stack.iter().any(|p| p.as_path() == path) {
+		return Err(format!("'{}' includes itself", path.display()));
+	}
+	let src = try!(read_file(path));
+	let index = source.files.len();
+	source.files.push(path.to_path_buf());
+	stack.push(path.to_path_buf());
+	
+	let dir = path.parent().unwrap_or(Path::new(""));
+	for (i, line) in src.lines().enumerate() {
+		match parse_include(line) {
+			Some(Ok(name)) => {
+				let included = dir.join(name);
+				if source.files.contains(&included) && !stack.contains(&included) {
+					// Already included, but keep the line so that the lines after it are numbered the same
+					source.text.push('\n');
+					continue;
+				}
+				source.text.push_str(&format!("#line 1 {}\n", source.files.len()));
+				if let Err(e) = append_file(source, &included, stack, read_file) {
+					return Err(format!("{}:{}: {}", path.display(), i + 1, e));
+				}
+				// Carry on from the next line of this file
+				source.text.push_str(&format!("#line {} {}\n", i + 2, index));
+			},
+			Some(Err(e)) => return Err(format!("{}:{}: {}", path.display(), i + 1, e)),
+			None => {
+				source.text.push_str(line);
+				source.text.push('\n');
+			},
+		}
+	}
+	
+	stack.pop();
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashMap;
+	use std::path::{Path, PathBuf};
+	
+	/// Loads `path` from files in memory.
+	fn load_from(files: &[(&str, &str)], path: &str) -> Result<Source, String> {
+		let files: HashMap<PathBuf, String> = files.iter().map(|&(p, src)| (PathBuf::from(p), String::from(src))).collect();
+		load(Path::new(path), &|p: &Path| match files.get(p) {
+			Some(src) => Ok(src.clone()),
+			None => Err(format!("'{}' not found", p.display())),
+		})
+	}
+	
+	#[test]
+	fn includes_are_expanded_with_line_numbers() {
+		let source = load_from(&[
+			("shaders/main.frag", "#version 330\n#include \"lib/light.glsl\"\nvoid main() {}\n"),
+			("shaders/lib/light.glsl", "float a;\n  #include  \"util.glsl\"\nfloat b;\n"),
+			("shaders/lib/util.glsl", "float c;\n"),
+		], "shaders/main.frag").unwrap();
+		assert_eq!(source.files, vec![
+			PathBuf::from("shaders/main.frag"),
+			PathBuf::from("shaders/lib/light.glsl"),
+			PathBuf::from("shaders/lib/util.glsl"),
+		]);
+		assert_eq!(source.text, "#version 330\n\
+			#line 1 1\n\
+			float a;\n\
+			#line 1 2\n\
+			float c;\n\
+			#line 3 1\n\
+			float b;\n\
+			#line 3 0\n\
+			void main() {}\n");
+	}
+	
+	#[test]
+	fn shared_includes_are_only_included_once() {
+		let source = load_from(&[
+			("main.frag", "#include \"a.glsl\"\n#include \"b.glsl\"\nvoid main() {}\n"),
+			("a.glsl", "#include \"common.glsl\"\nfloat a;\n"),
+			("b.glsl", "#include \"common.glsl\"\nfloat b;\n"),
+			("common.glsl", "float common;\n"),
+		], "main.frag").unwrap();
+		assert_eq!(source.files.len(), 4);
+		assert_eq!(source.text.matches("float common;").count(), 1);
+		assert_eq!(source.text, "#line 1 1\n\
+			#line 1 2\n\
+			float common;\n\
+			#line 2 1\n\
+			float a;\n\
+			#line 2 0\n\
+			#line 1 3\n\
+			\n\
+			float b;\n\
+			#line 3 0\n\
+			void main() {}\n");
+	}
+	
+	#[test]
+	fn self_includes_are_errors() {
+		let direct = load_from(&[("a.glsl", "#include \"a.glsl\"\n")], "a.glsl").unwrap_err();
+		assert!(direct.contains("'a.glsl' includes itself"), "{}", direct);
+		
+		let indirect = load_from(&[
+			("a.glsl", "float a;\n#include \"b.glsl\"\n"),
+			("b.glsl", "#include \"a.glsl\"\n"),
+		], "a.glsl").unwrap_err();
+		assert!(indirect.starts_with("a.glsl:2: b.glsl:1: "), "{}", indirect);
+		assert!(indirect.contains("includes itself"), "{}", indirect);
+	}
+	
+	#[test]
+	fn malformed_includes_are_errors() {
+		assert_eq!(parse_include("#include \"x.glsl\""), Some(Ok("x.glsl")));
+		assert_eq!(parse_include("float x;"), None);
+		assert!(parse_include("#include x.glsl").unwrap().is_err());
+		assert!(parse_include("#include \"").unwrap().is_err());
+		assert!(parse_include("#include").unwrap().is_err());
+		
+		let err = load_from(&[("main.frag", "void f();\n#include <x.glsl>\n")], "main.frag").unwrap_err();
+		assert!(err.starts_with("main.frag:2: "), "{}", err);
+		let missing = load_from(&[("main.frag", "#include \"gone.glsl\"\n")], "main.frag").unwrap_err();
+		assert!(missing.contains("'gone.glsl' not found"), "{}", missing);
+	}
+}
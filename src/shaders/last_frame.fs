@@ -0,0 +1,11 @@
+#version 330 core
+
+precision highp float;
+
+uniform sampler2D in_last_frame;
+
+out vec4 gl_FragColor;
+
+void main(void) {
+	gl_FragColor = texture(in_last_frame, gl_FragCoord.xy / vec2(textureSize(in_last_frame, 0)));
+}
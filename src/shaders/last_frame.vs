@@ -0,0 +1,10 @@
+#version 330 core
+
+uniform mat4 in_mvp;
+
+layout(location = 0) in vec3 in_pos;
+layout(location = 1) in vec3 attrib_color;
+
+void main() {
+	gl_Position = in_mvp * vec4(in_pos, 1.0);
+}
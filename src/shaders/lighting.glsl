@@ -0,0 +1,45 @@
+// Lighting shared by shaders that light their surfaces. Set with Render::set_lighting.
+
+// Must match MAX_LIGHTS, SHININESS and SPECULAR in light.rs, which lights surfaces the same way on the CPU
+const int MAX_LIGHTS = 8;
+const float SHININESS = 32.0;
+const float SPECULAR = 0.25;
+
+uniform vec3 in_cam_pos;
+uniform vec3 in_ambient;
+uniform int in_num_lights;
+// The direction of a directional light when w is 0, or the position of a point light when w is 1
+uniform vec4 in_light_pos[MAX_LIGHTS];
+uniform vec3 in_light_color[MAX_LIGHTS];
+uniform float in_light_range[MAX_LIGHTS];
+
+// Lights a surface of colour albedo at pos, facing normal, all in world space
+vec3 light_surface(vec3 albedo, vec3 pos, vec3 normal) {
+	vec3 to_cam = normalize(in_cam_pos - pos);
+	vec3 color = in_ambient * albedo;
+	
+	for (int i = 0; i < in_num_lights; i++) {
+		vec3 to_light;
+		float strength;
+		if (in_light_pos[i].w == 0.0) {
+			to_light = -normalize(in_light_pos[i].xyz);
+			strength = 1.0;
+		} else {
+			vec3 d = in_light_pos[i].xyz - pos;
+			float dist = length(d);
+			to_light = d / max(dist, 0.0001);
+			float falloff = clamp(1.0 - dist / in_light_range[i], 0.0, 1.0);
+			strength = falloff * falloff;
+		}
+		
+		// Lambert diffuse, and Blinn-Phong specular
+		float diffuse = max(dot(normal, to_light), 0.0);
+		float specular = 0.0;
+		if (diffuse > 0.0) {
+			vec3 halfway = normalize(to_light + to_cam);
+			specular = pow(max(dot(normal, halfway), 0.0), SHININESS) * SPECULAR;
+		}
+		color += (albedo * diffuse + vec3(specular)) * in_light_color[i] * strength;
+	}
+	return color;
+}
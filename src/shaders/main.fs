@@ -2,9 +2,22 @@
 
 precision highp float;
 
+#include "lighting.glsl"
+
+// The texture is multiplied by the vertex colour, if the mesh has one
+uniform bool in_textured;
+uniform sampler2D in_texture;
+
 in  vec3 ex_color;
+in  vec3 ex_pos;
+in  vec3 ex_normal;
+in  vec2 ex_uv;
 out vec4 gl_FragColor;
 
 void main(void) {
-	gl_FragColor = vec4(ex_color, 1.0);
+	vec3 albedo = ex_color;
+	if (in_textured) {
+		albedo *= texture(in_texture, ex_uv).rgb;
+	}
+	gl_FragColor = vec4(light_surface(albedo, ex_pos, normalize(ex_normal)), 1.0);
 }
@@ -1,13 +1,23 @@
 #version 330 core
 
 uniform mat4 in_mvp;
+uniform mat4 in_model;
 
 layout(location = 0) in vec3 in_pos;
 layout(location = 1) in vec3 in_color;
+layout(location = 2) in vec3 in_normal;
+layout(location = 3) in vec2 in_uv;
 
 out vec3 ex_color;
+// In world space, so that lighting is the same when seen through a portal
+out vec3 ex_pos;
+out vec3 ex_normal;
+out vec2 ex_uv;
 
 void main() {
 	gl_Position = in_mvp * vec4(in_pos, 1.0);
 	ex_color = in_color;
+	ex_pos = (in_model * vec4(in_pos, 1.0)).xyz;
+	ex_normal = transpose(inverse(mat3(in_model))) * in_normal;
+	ex_uv = in_uv;
 }
@@ -0,0 +1,658 @@
+//! A renderer that draws on the CPU, into a framebuffer in memory.
+//!
+//! `SoftRender` carries out the same draw lists as `Render`, including the stencil passes that portals are
+//! drawn with, so frames can be drawn and saved as PNGs on machines without a GPU. The depth and stencil tests
+//! follow the same rules as OpenGL, and meshes are lit the same way as by the main shader. Textures are sampled
+//! without filtering, so textured surfaces won't match the GPU exactly.
+use prelude::*;
+
+use entity::Camera;
+use draw_list::{Backend, DrawCommand, DrawList, DrawSettings, Pass, Shading};
+use light::Lighting;
+use render::{Mesh, PortalFallback, ProjectionMode, Render};
+use texture::{self, Image};
+
+use std::cmp;
+use std::path::Path;
+
+use na;
+
+/// The number of steps that each pixel is split into when vertices are snapped, so that triangles that share
+/// an edge meet exactly, without gaps or pixels that are drawn twice.
+const SUBPIXELS: i64 = 16;
+
+/// How far past the edges of the screen triangles are clipped, as a multiple of the screen size. Clipping
+/// outside of the screen keeps the new edges that clipping makes out of sight.
+const GUARD_BAND: f32 = 2.0;
+
+/// The smallest w that a vertex can have after clipping, to keep it away from the plane of the camera.
+const MIN_W: f32 = 0.00001;
+
+/// The stencil test, which compares the reference value with the value in the stencil buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StencilFunc {
+	Always,
+	/// Passes where the stencil isn't the reference value.
+	NotEqual,
+	/// Passes where the reference value is less than or equal to the stencil.
+	LEqual,
+}
+impl StencilFunc {
+	pub fn test(&self, reference: u8, stencil: u8) -> bool {
+		match *self {
+			StencilFunc::Always => true,
+			StencilFunc::NotEqual => reference != stencil,
+			StencilFunc::LEqual => reference <= stencil,
+		}
+	}
+}
+
+/// What happens to the stencil where the stencil test fails.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StencilOp {
+	Keep,
+	Incr,
+	Decr,
+}
+impl StencilOp {
+	/// Like in OpenGL, incrementing and decrementing stop at the ends of the range rather than wrapping around.
+	pub fn apply(&self, stencil: u8) -> u8 {
+		match *self {
+			StencilOp::Keep => stencil,
+			StencilOp::Incr => stencil.saturating_add(1),
+			StencilOp::Decr => stencil.saturating_sub(1),
+		}
+	}
+}
+
+/// The parts of the OpenGL state that `Render::begin_pass` sets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PassState {
+	pub color_mask: bool,
+	/// Whether depth is written, if the depth test is on.
+	pub depth_mask: bool,
+	pub depth_test: bool,
+	/// If true the depth test always passes, rather than only for things nearer than what is already drawn.
+	pub depth_always: bool,
+	/// Whether the whole depth buffer is cleared when the pass begins. Like `glClear`, this ignores the stencil.
+	pub clear_depth: bool,
+	pub stencil_func: StencilFunc,
+	pub stencil_ref: u8,
+	pub stencil_fail: StencilOp,
+}
+impl PassState {
+	/// Gets the state for `pass`. In wireframe mode the depth test is off, as it is in `Render`.
+	pub fn new(pass: Pass, wireframe: bool) -> PassState {
+		let mut state = PassState {
+			color_mask: true,
+			depth_mask: true,
+			depth_test: !wireframe,
+			depth_always: false,
+			clear_depth: false,
+			stencil_func: StencilFunc::Always,
+			stencil_ref: 0,
+			stencil_fail: StencilOp::Keep,
+		};
+		match pass {
+			Pass::StencilPortal { level } => {
+				state.color_mask = false;
+				state.depth_mask = false;
+				state.depth_test = false;
+				// Fail the stencil test (and so increment) only in the area that is currently being drawn
+				state.stencil_func = StencilFunc::NotEqual;
+				state.stencil_ref = level as u8;
+				state.stencil_fail = StencilOp::Incr;
+			},
+			Pass::UnstencilPortal { level } => {
+				state.color_mask = false;
+				state.depth_mask = false;
+				state.depth_test = false;
+				state.stencil_func = StencilFunc::NotEqual;
+				state.stencil_ref = level as u8 + 1;
+				state.stencil_fail = StencilOp::Decr;
+			},
+			Pass::ProtectPortals { level } => {
+				state.color_mask = false;
+				state.clear_depth = true;
+				state.depth_always = true;
+				state.stencil_func = StencilFunc::LEqual;
+				state.stencil_ref = level as u8;
+			},
+			Pass::Level { level, clear_depth } => {
+				state.clear_depth = clear_depth;
+				state.stencil_func = StencilFunc::LEqual;
+				state.stencil_ref = level as u8;
+			},
+			Pass::Normal => {},
+		}
+		state
+	}
+}
+
+/// A vertex after the vertex shader: where it is in clip space, and what is interpolated across triangles.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Vertex {
+	clip: Vec4,
+	/// In world space, like `ex_pos` in the main shader.
+	pos: Vec3,
+	color: Vec3,
+	normal: Vec3,
+	uv: Vec2,
+}
+impl Vertex {
+	fn lerp(&self, other: &Vertex, t: f32) -> Vertex {
+		Vertex::blend(&[*self, *other, *other], [1.0 - t, t, 0.0])
+	}
+	
+	/// Adds up the vertices, each multiplied by its weight.
+	fn blend(verts: &[Vertex; 3], weights: [f32; 3]) -> Vertex {
+		let [a, b, c] = *verts;
+		let [wa, wb, wc] = weights;
+		Vertex {
+			clip: a.clip * wa + b.clip * wb + c.clip * wc,
+			pos: a.pos * wa + b.pos * wb + c.pos * wc,
+			color: a.color * wa + b.color * wb + c.color * wc,
+			normal: a.normal * wa + b.normal * wb + c.normal * wc,
+			uv: a.uv * wa + b.uv * wb + c.uv * wc,
+		}
+	}
+}
+
+/// Clips a polygon to the part where `dist` is at least 0, with the Sutherland-Hodgman algorithm.
+fn clip_polygon<F: Fn(&Vertex) -> f32>(poly: &[Vertex], dist: F) -> Vec<Vertex> {
+	let mut ret = Vec::new();
+	for i in 0..poly.len() {
+		let (a, b) = (&poly[i], &poly[(i + 1) % poly.len()]);
+		let (da, db) = (dist(a), dist(b));
+		if da >= 0.0 {
+			ret.push(*a);
+		}
+		if (da >= 0.0) != (db >= 0.0) {
+			ret.push(a.lerp(b, da / (da - db)));
+		}
+	}
+	ret
+}
+
+/// Clips a triangle to the near plane and the guard band, which OpenGL would do before rasterizing it.
+fn clip_triangle(tri: [Vertex; 3]) -> Vec<Vertex> {
+	let mut poly = clip_polygon(&tri, |v| v.clip.w - MIN_W);
+	poly = clip_polygon(&poly, |v| v.clip.z + v.clip.w);
+	poly = clip_polygon(&poly, |v| v.clip.w * GUARD_BAND - v.clip.x);
+	poly = clip_polygon(&poly, |v| v.clip.w * GUARD_BAND + v.clip.x);
+	poly = clip_polygon(&poly, |v| v.clip.w * GUARD_BAND - v.clip.y);
+	clip_polygon(&poly, |v| v.clip.w * GUARD_BAND + v.clip.y)
+}
+
+/// A vertex in window coordinates, in subpixels with y up, like OpenGL.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct ScreenVert {
+	x: i64,
+	y: i64,
+	/// Depth from 0 at the near plane to 1 at the far plane.
+	depth: f32,
+	inv_w: f32,
+}
+
+/// Twice the signed area of the triangle `a`, `b`, (`x`, `y`), which is positive if it is counterclockwise.
+fn edge(a: &ScreenVert, b: &ScreenVert, x: i64, y: i64) -> i64 {
+	(b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+}
+
+/// Returns true if the edge from `a` to `b` of a counterclockwise triangle is a top or left edge. Pixels exactly
+/// on an edge are only drawn for top and left edges, so that they are drawn by only one of the triangles
+/// that share the edge.
+fn is_top_left(a: &ScreenVert, b: &ScreenVert) -> bool {
+	let (dx, dy) = (b.x - a.x, b.y - a.y);
+	dy < 0 || (dy == 0 && dx < 0)
+}
+
+/// Gets the colour of `img` at `uv`, repeating outside of 0 to 1, without filtering.
+pub fn sample(img: &Image, uv: Vec2) -> Vec3 {
+	let wrap = |t: f32, size: u32| cmp::min(((t - t.floor()) * size as f32) as u32, size - 1);
+	from_rgba8(img.get_pixel(wrap(uv.x, img.width), wrap(uv.y, img.height)))
+}
+
+fn from_rgba8(c: [u8; 4]) -> Vec3 {
+	Vec3::new(c[0] as f32 / 255.0, c[1] as f32 / 255.0, c[2] as f32 / 255.0)
+}
+
+fn to_u8(f: f32) -> u8 {
+	(f.max(0.0).min(1.0) * 255.0).round() as u8
+}
+
+/// Gets the matrix that transforms normals for the model matrix `model`, like `ex_normal` in the main shader.
+fn get_normal_mat(model: &Mat4) -> Mat3 {
+	let m = Mat3::new(model.m11, model.m12, model.m13,
+	                  model.m21, model.m22, model.m23,
+	                  model.m31, model.m32, model.m33);
+	match m.inv() {
+		Some(inv) => na::transpose(&inv),
+		None => m,
+	}
+}
+
+/// Draws on the CPU into colour, depth and stencil buffers in memory.
+pub struct SoftRender {
+	width: u32,
+	height: u32,
+	color: Image,
+	/// Rows from top to bottom, like `color`.
+	depth: Vec<f32>,
+	stencil: Vec<u8>,
+	/// The colour buffer from before the last swap, for `PortalFallback::LastFrame`.
+	last_frame: Option<Image>,
+	state: PassState,
+	settings: DrawSettings,
+	projection_mode: ProjectionMode,
+	vp_mat: Mat4,
+	cam_pos: Vec3,
+	lighting: Lighting,
+}
+
+impl SoftRender {
+	/// Creates a renderer that draws frames of `width` by `height` pixels, with the same settings as `Render` starts with.
+	pub fn new(width: u32, height: u32) -> SoftRender {
+		let len = width as usize * height as usize;
+		let settings = DrawSettings {
+			render_portals: true,
+			wireframe: false,
+			portal_depth: 3,
+			portal_fallback: PortalFallback::Color(Render::get_background_color()),
+		};
+		let mut ren = SoftRender {
+			width: width,
+			height: height,
+			color: Image::new(width, height, [0, 0, 0, 255]),
+			depth: vec![1.0; len],
+			stencil: vec![0; len],
+			last_frame: None,
+			state: PassState::new(Pass::Normal, settings.wireframe),
+			settings: settings,
+			projection_mode: ProjectionMode::Oblique,
+			vp_mat: Mat4::new_identity(4),
+			cam_pos: na::zero(),
+			lighting: Lighting::default(),
+		};
+		ren.clear();
+		ren
+	}
+	
+	/// Clears the colour to the background colour, and the depth and stencil.
+	pub fn clear(&mut self) {
+		let bg = Render::get_background_color();
+		let pixel = [to_u8(bg.x), to_u8(bg.y), to_u8(bg.z), to_u8(bg.w)];
+		self.color = Image::new(self.width, self.height, pixel);
+		for d in self.depth.iter_mut() {
+			*d = 1.0;
+		}
+		for s in self.stencil.iter_mut() {
+			*s = 0;
+		}
+		self.state = PassState::new(Pass::Normal, self.settings.wireframe);
+	}
+	
+	/// Finishes the frame: keeps it if the portals are filled with the last frame, then clears the buffers.
+	pub fn swap(&mut self) {
+		if self.settings.portal_fallback == PortalFallback::LastFrame {
+			self.last_frame = Some(self.color.clone());
+		}
+		self.clear();
+	}
+	
+	pub fn get_size(&self) -> (u32, u32) {
+		(self.width, self.height)
+	}
+	
+	/// Gets what has been drawn so far.
+	pub fn get_image(&self) -> &Image {
+		&self.color
+	}
+	
+	/// Saves what has been drawn so far to a PNG file.
+	pub fn save_png(&self, path: &Path) -> Result<(), String> {
+		texture::save_png(path, &self.color)
+	}
+	
+	pub fn set_draw_settings(&mut self, settings: DrawSettings) {
+		self.settings = settings;
+	}
+	
+	pub fn set_projection_mode(&mut self, mode: ProjectionMode) {
+		self.projection_mode = mode;
+	}
+	pub fn get_projection_mode(&self) -> ProjectionMode {
+		self.projection_mode
+	}
+	
+	pub fn set_camera(&mut self, cam: &Camera) {
+		let view = cam.get_view();
+		let projection = Render::get_projection(self.projection_mode, self.width as f32 / self.height as f32, cam.get_fov(), &view, cam.get_clip_plane());
+		self.vp_mat = projection * view;
+		self.cam_pos = cam.get_pos();
+	}
+	
+	/// Sets up the colour, depth and stencil buffers for the draws that follow, like `Render::begin_pass`.
+	pub fn begin_pass(&mut self, pass: Pass) {
+		self.state = PassState::new(pass, self.settings.wireframe);
+		if self.state.clear_depth {
+			for d in self.depth.iter_mut() {
+				*d = 1.0;
+			}
+		}
+	}
+	
+	pub fn draw_mesh(&mut self, mesh: &Mesh, model: Mat4, shading: Shading) {
+		// Meshes on the GPU don't keep their vertices on the CPU, so only meshes made without OpenGL can be drawn
+		let geo = match mesh.get_geometry() {
+			Some(geo) => geo,
+			None => return,
+		};
+		let texture = mesh.get_texture().and_then(|t| t.get_image());
+		let mvp = self.vp_mat * model;
+		let normal_mat = get_normal_mat(&model);
+		
+		// The vertex shader
+		let verts: Vec<Vertex> = geo.verts.iter().enumerate().map(|(i, v)| {
+			let p = Vec4::new(v.x, v.y, v.z, 1.0);
+			let world = model * p;
+			Vertex {
+				clip: mvp * p,
+				pos: Vec3::new(world.x, world.y, world.z),
+				color: geo.colors[i],
+				normal: normal_mat * geo.normals[i],
+				uv: geo.uvs[i],
+			}
+		}).collect();
+		
+		for tri in geo.triangles.iter() {
+			let poly = clip_triangle([verts[tri[0]], verts[tri[1]], verts[tri[2]]]);
+			if poly.len() < 3 {
+				continue;
+			}
+			if self.settings.wireframe {
+				for i in 0..poly.len() {
+					self.draw_line(&poly[i], &poly[(i + 1) % poly.len()], shading, texture);
+				}
+			} else {
+				for i in 1..poly.len() - 1 {
+					self.draw_triangle([poly[0], poly[i], poly[i + 1]], shading, texture);
+				}
+			}
+		}
+	}
+	
+	/// Gets where a clipped vertex is on the screen, in pixels.
+	fn to_window(&self, v: &Vertex) -> (f32, f32, f32) {
+		let ndc = v.clip / v.clip.w;
+		((ndc.x * 0.5 + 0.5) * self.width as f32, (ndc.y * 0.5 + 0.5) * self.height as f32, ndc.z * 0.5 + 0.5)
+	}
+	
+	fn to_screen_vert(&self, v: &Vertex) -> ScreenVert {
+		let (x, y, depth) = self.to_window(v);
+		ScreenVert {
+			x: (x * SUBPIXELS as f32).round() as i64,
+			y: (y * SUBPIXELS as f32).round() as i64,
+			depth: depth,
+			inv_w: 1.0 / v.clip.w,
+		}
+	}
+	
+	fn draw_triangle(&mut self, tri: [Vertex; 3], shading: Shading, texture: Option<&Image>) {
+		let s = [self.to_screen_vert(&tri[0]), self.to_screen_vert(&tri[1]), self.to_screen_vert(&tri[2])];
+		let area = edge(&s[0], &s[1], s[2].x, s[2].y);
+		// Back faces (clockwise on the screen) are culled, as in `Render`
+		if area <= 0 {
+			return;
+		}
+		
+		let min_x = cmp::max(0, cmp::min(s[0].x, cmp::min(s[1].x, s[2].x)) / SUBPIXELS);
+		let min_y = cmp::max(0, cmp::min(s[0].y, cmp::min(s[1].y, s[2].y)) / SUBPIXELS);
+		let max_x = cmp::min(self.width as i64 - 1, cmp::max(s[0].x, cmp::max(s[1].x, s[2].x)) / SUBPIXELS);
+		let max_y = cmp::min(self.height as i64 - 1, cmp::max(s[0].y, cmp::max(s[1].y, s[2].y)) / SUBPIXELS);
+		let edges = [(1, 2), (2, 0), (0, 1)];
+		
+		for y in min_y..max_y + 1 {
+			for x in min_x..max_x + 1 {
+				// Sample at the centre of the pixel
+				let (px, py) = (x * SUBPIXELS + SUBPIXELS / 2, y * SUBPIXELS + SUBPIXELS / 2);
+				let mut weights = [0.0; 3];
+				let mut inside = true;
+				for (i, &(a, b)) in edges.iter().enumerate() {
+					let e = edge(&s[a], &s[b], px, py);
+					if e < 0 || (e == 0 && !is_top_left(&s[a], &s[b])) {
+						inside = false;
+						break;
+					}
+					weights[i] = e as f32 / area as f32;
+				}
+				if !inside {
+					continue;
+				}
+				
+				// Depth is linear on the screen, but everything else needs to be corrected for perspective
+				let depth = weights[0] * s[0].depth + weights[1] * s[1].depth + weights[2] * s[2].depth;
+				if depth < 0.0 || depth > 1.0 {
+					continue;
+				}
+				let q = [weights[0] * s[0].inv_w, weights[1] * s[1].inv_w, weights[2] * s[2].inv_w];
+				let sum = q[0] + q[1] + q[2];
+				let v = Vertex::blend(&tri, [q[0] / sum, q[1] / sum, q[2] / sum]);
+				self.fragment(x as u32, y as u32, depth, &v, shading, texture);
+			}
+		}
+	}
+	
+	fn draw_line(&mut self, a: &Vertex, b: &Vertex, shading: Shading, texture: Option<&Image>) {
+		let (ax, ay, a_depth) = self.to_window(a);
+		let (bx, by, b_depth) = self.to_window(b);
+		let steps = cmp::max(1, (bx - ax).abs().max((by - ay).abs()).ceil() as i64);
+		for i in 0..steps + 1 {
+			let t = i as f32 / steps as f32;
+			let (x, y) = ((ax + (bx - ax) * t).floor(), (ay + (by - ay) * t).floor());
+			let depth = a_depth + (b_depth - a_depth) * t;
+			if x < 0.0 || y < 0.0 || x >= self.width as f32 || y >= self.height as f32 || depth < 0.0 || depth > 1.0 {
+				continue;
+			}
+			self.fragment(x as u32, y as u32, depth, &a.lerp(b, t), shading, texture);
+		}
+	}
+	
+	/// Runs the stencil and depth tests for the pixel at `x`, `y` (counting up from the bottom left), and draws
+	/// to whichever buffers pass.
+	fn fragment(&mut self, x: u32, y: u32, depth: f32, v: &Vertex, shading: Shading, texture: Option<&Image>) {
+		let i = (self.height - 1 - y) as usize * self.width as usize + x as usize;
+		let state = self.state;
+		if !state.stencil_func.test(state.stencil_ref, self.stencil[i]) {
+			self.stencil[i] = state.stencil_fail.apply(self.stencil[i]);
+			return;
+		}
+		if state.depth_test {
+			if !state.depth_always && !(depth < self.depth[i]) {
+				return;
+			}
+			if state.depth_mask {
+				self.depth[i] = depth;
+			}
+		}
+		if state.color_mask {
+			let c = self.shade(x, y, v, shading, texture);
+			let pixel = [to_u8(c.x), to_u8(c.y), to_u8(c.z), 255];
+			for (dst, src) in self.color.data[i * 4..i * 4 + 4].iter_mut().zip(pixel.iter()) {
+				*dst = *src;
+			}
+		}
+	}
+	
+	/// Gets the colour of a pixel, as the fragment shaders of `Render` would.
+	fn shade(&self, x: u32, y: u32, v: &Vertex, shading: Shading, texture: Option<&Image>) -> Vec3 {
+		match shading {
+			Shading::Lit => {
+				let mut albedo = v.color;
+				if let Some(img) = texture {
+					albedo = albedo * sample(img, v.uv);
+				}
+				self.lighting.light_surface(self.cam_pos, albedo, v.pos, v.normal.normalize())
+			},
+			Shading::Color(c) => Vec3::new(c.x, c.y, c.z),
+			Shading::LastFrame => match self.last_frame {
+				Some(ref img) if img.width == self.width && img.height == self.height => from_rgba8(img.get_pixel(x, self.height - 1 - y)),
+				_ => {
+					let bg = Render::get_background_color();
+					Vec3::new(bg.x, bg.y, bg.z)
+				},
+			},
+		}
+	}
+}
+
+impl Backend for SoftRender {
+	fn get_draw_settings(&self) -> DrawSettings {
+		self.settings
+	}
+	
+	fn execute(&mut self, list: &DrawList) {
+		for cmd in list.commands.iter() {
+			match *cmd {
+				DrawCommand::SetLighting(ref lighting) => self.lighting = lighting.clone(),
+				DrawCommand::SetCamera(ref cam) => self.set_camera(cam),
+				DrawCommand::BeginPass(pass) => self.begin_pass(pass),
+				DrawCommand::Draw { ref mesh, model, shading } => self.draw_mesh(mesh, model, shading),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prelude::*;
+	use super::Vertex;
+	use na;
+	use draw_list::{DrawList, Pass, Shading};
+	use entity::{Camera, Portal};
+	
+	#[test]
+	fn pass_states() {
+		let stencil = PassState::new(Pass::StencilPortal { level: 2 }, false);
+		assert!(!stencil.color_mask && !stencil.depth_mask && !stencil.depth_test);
+		assert_eq!((stencil.stencil_func, stencil.stencil_ref, stencil.stencil_fail), (StencilFunc::NotEqual, 2, StencilOp::Incr));
+		
+		let unstencil = PassState::new(Pass::UnstencilPortal { level: 2 }, false);
+		assert!(!unstencil.color_mask && !unstencil.depth_mask && !unstencil.depth_test);
+		assert_eq!((unstencil.stencil_func, unstencil.stencil_ref, unstencil.stencil_fail), (StencilFunc::NotEqual, 3, StencilOp::Decr));
+		
+		let protect = PassState::new(Pass::ProtectPortals { level: 1 }, false);
+		assert!(!protect.color_mask && protect.depth_mask && protect.depth_test && protect.depth_always && protect.clear_depth);
+		assert_eq!((protect.stencil_func, protect.stencil_ref, protect.stencil_fail), (StencilFunc::LEqual, 1, StencilOp::Keep));
+		
+		for &clear_depth in [true, false].iter() {
+			let level = PassState::new(Pass::Level { level: 4, clear_depth: clear_depth }, false);
+			assert!(level.color_mask && level.depth_mask && level.depth_test && !level.depth_always);
+			assert_eq!(level.clear_depth, clear_depth);
+			assert_eq!((level.stencil_func, level.stencil_ref, level.stencil_fail), (StencilFunc::LEqual, 4, StencilOp::Keep));
+		}
+		
+		let normal = PassState::new(Pass::Normal, false);
+		assert!(normal.color_mask && normal.depth_mask && normal.depth_test && !normal.depth_always && !normal.clear_depth);
+		assert_eq!(normal.stencil_func, StencilFunc::Always);
+		
+		assert!(!PassState::new(Pass::Normal, true).depth_test);
+		assert!(!PassState::new(Pass::Level { level: 0, clear_depth: true }, true).depth_test);
+	}
+	
+	#[test]
+	fn stencil_saturates() {
+		assert_eq!(StencilOp::Incr.apply(254), 255);
+		assert_eq!(StencilOp::Incr.apply(255), 255);
+		assert_eq!(StencilOp::Decr.apply(1), 0);
+		assert_eq!(StencilOp::Decr.apply(0), 0);
+		assert_eq!(StencilOp::Keep.apply(7), 7);
+		
+		assert!(StencilFunc::Always.test(3, 0));
+		assert!(StencilFunc::NotEqual.test(3, 2) && !StencilFunc::NotEqual.test(3, 3));
+		assert!(StencilFunc::LEqual.test(3, 3) && StencilFunc::LEqual.test(3, 4) && !StencilFunc::LEqual.test(3, 2));
+	}
+	
+	/// A vertex at `x`, `y` in window coordinates on a `size` by `size` screen.
+	fn window_vert(x: f32, y: f32, size: u32) -> Vertex {
+		let half = size as f32 / 2.0;
+		Vertex {
+			clip: Vec4::new(x / half - 1.0, y / half - 1.0, 0.0, 1.0),
+			pos: na::zero(),
+			color: Vec3::new(1.0, 1.0, 1.0),
+			normal: Vec3::new(0.0, 0.0, 1.0),
+			uv: na::zero(),
+		}
+	}
+	
+	#[test]
+	fn shared_edges_are_drawn_once() {
+		let size = 8;
+		let mut ren = SoftRender::new(size, size);
+		// Count every fragment in the stencil buffer
+		ren.state.stencil_func = StencilFunc::NotEqual;
+		ren.state.stencil_ref = 255;
+		ren.state.stencil_fail = StencilOp::Incr;
+		
+		// A square with its corners and diagonal on the centres of pixels, split into two counterclockwise triangles
+		let (a, b) = (1.5, 5.5);
+		let (bl, br, tr, tl) = (window_vert(a, a, size), window_vert(b, a, size), window_vert(b, b, size), window_vert(a, b, size));
+		ren.draw_triangle([bl, br, tr], Shading::Lit, None);
+		ren.draw_triangle([bl, tr, tl], Shading::Lit, None);
+		
+		assert!(ren.stencil.iter().all(|&s| s <= 1));
+		// Of the 5x5 pixel centres in the square, only one of each pair of opposite edges is included
+		assert_eq!(ren.stencil.iter().filter(|&&s| s == 1).count(), 16);
+		// The pixels on the diagonal, between the corners, are drawn by exactly one of the triangles
+		for i in 2..5 {
+			let row = size - 1 - i;
+			assert_eq!(ren.stencil[(row * size + i) as usize], 1);
+		}
+	}
+	
+	#[test]
+	fn stencil_pass_only_marks_the_portal() {
+		let size = 60;
+		let mut ren = SoftRender::new(size, size);
+		let background = ren.get_image().clone();
+		let cam = Camera::new(Vec3::new(0.0, 0.0, 0.0), 90.0);
+		// One unit across, three units away, with a 90 degree field of view: 10 pixels across in the middle
+		let portal = Portal::new(Vec3::new(0.0, 0.0, 3.0), Rot3::new_identity(3), 1.0, 1.0);
+		
+		let mut list = DrawList::new();
+		list.set_camera(&cam);
+		list.begin_pass(Pass::StencilPortal { level: 0 });
+		portal.draw(&mut list, Shading::Lit);
+		ren.execute(&list);
+		
+		let inside = |x: u32, y: u32, margin: i32| {
+			let (lo, hi) = (25 - margin, 35 + margin);
+			(x as i32) >= lo && (x as i32) < hi && (y as i32) >= lo && (y as i32) < hi
+		};
+		for y in 0..size {
+			for x in 0..size {
+				let stencil = ren.stencil[(y * size + x) as usize];
+				if inside(x, y, -1) {
+					assert_eq!(stencil, 1);
+				} else if !inside(x, y, 1) {
+					assert_eq!(stencil, 0);
+				}
+			}
+		}
+		// Nothing visible is drawn, and the depth is left alone
+		assert_eq!(ren.get_image().data, background.data);
+		assert!(ren.depth.iter().all(|&d| d == 1.0));
+		
+		// Drawing at the next level only changes the pixels in the portal
+		let wall = Portal::new(Vec3::new(0.0, 0.0, 5.0), Rot3::new_identity(3), 20.0, 20.0);
+		let mut list = DrawList::new();
+		list.begin_pass(Pass::Level { level: 1, clear_depth: true });
+		wall.draw(&mut list, Shading::Color(Vec4::new(1.0, 0.0, 0.0, 1.0)));
+		ren.execute(&list);
+		for i in 0..(size * size) as usize {
+			let changed = ren.get_image().data[i * 4..i * 4 + 4] != background.data[i * 4..i * 4 + 4];
+			assert_eq!(changed, ren.stencil[i] == 1);
+		}
+	}
+}
@@ -0,0 +1,279 @@
+//! Loading of images, and textures made from them.
+//!
+//! Images are decoded to 8-bit RGBA on the CPU, so decoding doesn't need an OpenGL context. Texture coordinates
+//! have (0, 0) at the top left of the image, and repeat outside of 0 to 1.
+use render::{self, has_gl};
+
+use png::{self, BitDepth, ColorType, HasParameters};
+
+use gl;
+use gl::types::*;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::prelude::*;
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+
+/// An image in 8-bit RGBA, with rows from top to bottom.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+	pub width: u32,
+	pub height: u32,
+	pub data: Vec<u8>,
+}
+impl Image {
+	/// Creates an image of one colour.
+	pub fn new(width: u32, height: u32, color: [u8; 4]) -> Image {
+		let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+		for _ in 0..width * height {
+			data.extend(color.iter().cloned());
+		}
+		Image {
+			width: width,
+			height: height,
+			data: data,
+		}
+	}
+	
+	/// Gets the colour of the pixel at `x`, `y`, counting down from the top left.
+	pub fn get_pixel(&self, x: u32, y: u32) -> [u8; 4] {
+		let i = (y as usize * self.width as usize + x as usize) * 4;
+		[self.data[i], self.data[i + 1], self.data[i + 2], self.data[i + 3]]
+	}
+}
+
+/// Converts pixels of any PNG colour type (after palettes have been expanded) to 8-bit RGBA.
+/// 16-bit samples are cut down to their high byte. `data` must be a whole number of pixels.
+pub fn to_rgba8(data: &[u8], color_type: ColorType, bit_depth: BitDepth) -> Result<Vec<u8>, String> {
+	let bytes = match bit_depth {
+		BitDepth::Eight => 1,
+		BitDepth::Sixteen => 2,
+		_ => return Err(format!("bit depth {:?} is not supported", bit_depth)),
+	};
+	let channels = match color_type {
+		ColorType::Grayscale => 1,
+		ColorType::GrayscaleAlpha => 2,
+		ColorType::RGB => 3,
+		ColorType::RGBA => 4,
+		ColorType::Indexed => return Err(String::from("indexed colour should have been expanded")),
+	};
+	if data.len() % (bytes * channels) != 0 {
+		return Err(String::from("the image data is cut off part way through a pixel"));
+	}
+	// PNG is big endian, so the high byte comes first
+	let samples: Vec<u8> = data.chunks(bytes).map(|s| s[0]).collect();
+	let mut rgba = Vec::with_capacity(samples.len() / channels * 4);
+	for p in samples.chunks(channels) {
+		match channels {
+			1 => rgba.extend([p[0], p[0], p[0], 255].iter().cloned()),
+			2 => rgba.extend([p[0], p[0], p[0], p[1]].iter().cloned()),
+			3 => rgba.extend([p[0], p[1], p[2], 255].iter().cloned()),
+			_ => rgba.extend(p.iter().cloned()),
+		}
+	}
+	Ok(rgba)
+}
+
+/// Decodes a PNG image.
+pub fn decode_png<R: Read>(r: R) -> Result<Image, String> {
+	let mut decoder = png::Decoder::new(r);
+	// Turn palettes into RGB, and grayscale below 8 bits into 8 bits
+	decoder.set(png::Transformations::EXPAND);
+	let (info, mut reader) = match decoder.read_info() {
+		Ok(ret) => ret,
+		Err(e) => return Err(format!("invalid PNG: {}", e)),
+	};
+	let mut buf = vec![0; info.buffer_size()];
+	if let Err(e) = reader.next_frame(&mut buf) {
+		return Err(format!("invalid PNG: {}", e));
+	}
+	let data = try!(to_rgba8(&buf, info.color_type, info.bit_depth));
+	if data.len() != info.width as usize * info.height as usize * 4 {
+		return Err(String::from("the PNG image data is cut off"));
+	}
+	Ok(Image {
+		width: info.width,
+		height: info.height,
+		data: data,
+	})
+}
+
+/// Loads a PNG image from a file.
+pub fn load_png(path: &Path) -> Result<Image, String> {
+	let file = match File::open(path) {
+		Ok(f) => f,
+		Err(e) => return Err(format!("error opening image '{}': {}", path.display(), e)),
+	};
+	match decode_png(file) {
+		Ok(img) => Ok(img),
+		Err(e) => Err(format!("error loading image '{}': {}", path.display(), e)),
+	}
+}
+
+/// Encodes an image as an 8-bit RGBA PNG.
+pub fn encode_png<W: Write>(w: W, img: &Image) -> Result<(), String> {
+	let mut encoder = png::Encoder::new(w, img.width, img.height);
+	encoder.set(ColorType::RGBA).set(BitDepth::Eight);
+	let mut writer = match encoder.write_header() {
+		Ok(writer) => writer,
+		Err(e) => return Err(format!("error writing PNG: {}", e)),
+	};
+	match writer.write_image_data(&img.data) {
+		Ok(()) => Ok(()),
+		Err(e) => Err(format!("error writing PNG: {}", e)),
+	}
+}
+
+/// Saves an image to a PNG file.
+pub fn save_png(path: &Path, img: &Image) -> Result<(), String> {
+	let file = match File::create(path) {
+		Ok(f) => f,
+		Err(e) => return Err(format!("error creating image '{}': {}", path.display(), e)),
+	};
+	match encode_png(BufWriter::new(file), img) {
+		Ok(()) => Ok(()),
+		Err(e) => Err(format!("error saving image '{}': {}", path.display(), e)),
+	}
+}
+
+/// The OpenGL texture, which is deleted when it is dropped.
+#[derive(Debug)]
+struct TextureHandle {
+	tex: GLuint,
+}
+impl Drop for TextureHandle {
+	fn drop(&mut self) {
+		if self.tex == 0 {
+			return;
+		}
+		unsafe {
+			gl::DeleteTextures(1, &self.tex);
+		}
+		render::remove_live_gl_objects(1);
+	}
+}
+
+/// A texture on the GPU. Without an OpenGL context the image is kept on the CPU instead, for `SoftRender`.
+/// Cloning it only copies the handles.
+#[derive(Debug, Clone)]
+pub struct Texture {
+	handle: Rc<TextureHandle>,
+	image: Option<Rc<Image>>,
+	width: u32,
+	height: u32,
+}
+impl Texture {
+	/// Uploads an image, with mipmaps. Without an OpenGL context, the texture is only kept on the CPU.
+	pub fn new(img: &Image) -> Texture {
+		let mut tex = 0;
+		if has_gl() {
+			unsafe {
+				gl::GenTextures(1, &mut tex);
+				gl::BindTexture(gl::TEXTURE_2D, tex);
+				gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+				gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as GLint, img.width as GLsizei, img.height as GLsizei, 0, gl::RGBA, gl::UNSIGNED_BYTE, mem::transmute(img.data.as_ptr()));
+				gl::GenerateMipmap(gl::TEXTURE_2D);
+				gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as GLint);
+				gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+				gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
+				gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
+			}
+			render::add_live_gl_objects(1);
+		}
+		Texture {
+			handle: Rc::new(TextureHandle { tex: tex }),
+			image: if tex == 0 { Some(Rc::new(img.clone())) } else { None },
+			width: img.width,
+			height: img.height,
+		}
+	}
+	
+	/// Loads a texture from a PNG file.
+	pub fn load(path: &Path) -> Result<Texture, String> {
+		Ok(Texture::new(&try!(load_png(path))))
+	}
+	
+	pub fn get_size(&self) -> (u32, u32) {
+		(self.width, self.height)
+	}
+	
+	/// Gets the image kept on the CPU, which is only there if the texture isn't uploaded.
+	pub fn get_image(&self) -> Option<&Image> {
+		self.image.as_ref().map(|i| &**i)
+	}
+	
+	/// Returns false if the texture only exists on the CPU, because there was no OpenGL context when it was created.
+	pub fn is_uploaded(&self) -> bool {
+		self.handle.tex != 0
+	}
+	
+	/// Binds the texture to texture unit `unit`.
+	pub fn bind(&self, unit: u32) {
+		unsafe {
+			gl::ActiveTexture(gl::TEXTURE0 + unit);
+			gl::BindTexture(gl::TEXTURE_2D, self.handle.tex);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use png::{BitDepth, ColorType};
+	
+	fn test_image() -> Image {
+		Image {
+			width: 3,
+			height: 2,
+			data: vec![
+				255, 0, 0, 255,  0, 255, 0, 128,  0, 0, 255, 0,
+				1, 2, 3, 4,  250, 251, 252, 253,  128, 128, 128, 255,
+			],
+		}
+	}
+	
+	#[test]
+	fn png_round_trip() {
+		let img = test_image();
+		let mut png = Vec::new();
+		encode_png(&mut png, &img).unwrap();
+		assert_eq!(decode_png(&png[..]).unwrap(), img);
+	}
+	
+	#[test]
+	fn cut_off_png_is_an_error() {
+		let mut png = Vec::new();
+		encode_png(&mut png, &test_image()).unwrap();
+		let half = png.len() / 2;
+		assert!(decode_png(&png[..half]).is_err());
+	}
+	
+	#[test]
+	fn grayscale_to_rgba8() {
+		assert_eq!(to_rgba8(&[10, 200], ColorType::Grayscale, BitDepth::Eight).unwrap(),
+			vec![10, 10, 10, 255,  200, 200, 200, 255]);
+		assert_eq!(to_rgba8(&[10, 128,  20, 0], ColorType::GrayscaleAlpha, BitDepth::Eight).unwrap(),
+			vec![10, 10, 10, 128,  20, 20, 20, 0]);
+	}
+	
+	#[test]
+	fn sixteen_bit_to_rgba8() {
+		// Only the high byte of each sample is kept
+		assert_eq!(to_rgba8(&[0x12, 0x34,  0x56, 0x78,  0x9a, 0xbc], ColorType::RGB, BitDepth::Sixteen).unwrap(),
+			vec![0x12, 0x56, 0x9a, 255]);
+		assert_eq!(to_rgba8(&[0x80, 0xff,  0x40, 0x00], ColorType::GrayscaleAlpha, BitDepth::Sixteen).unwrap(),
+			vec![0x80, 0x80, 0x80, 0x40]);
+		assert_eq!(to_rgba8(&[1, 2, 3, 4, 5, 6, 7, 8], ColorType::RGBA, BitDepth::Sixteen).unwrap(),
+			vec![1, 3, 5, 7]);
+	}
+	
+	#[test]
+	fn bad_data_to_rgba8_is_an_error() {
+		assert!(to_rgba8(&[1, 2, 3, 4, 5], ColorType::RGB, BitDepth::Eight).is_err());
+		assert!(to_rgba8(&[1, 2, 3], ColorType::Grayscale, BitDepth::Sixteen).is_err());
+		assert!(to_rgba8(&[1, 2], ColorType::Indexed, BitDepth::Eight).is_err());
+		assert!(to_rgba8(&[1, 2], ColorType::Grayscale, BitDepth::Four).is_err());
+	}
+}
@@ -0,0 +1,160 @@
+//! Setting the uniforms of shader programs.
+//!
+//! The uniforms of a program are found when it is linked, so that setting one is a lookup rather than a call to
+//! `glGetUniformLocation`. Values are set with `Shader::set_uniform`, which checks that the type matches.
+use prelude::*;
+
+use gl;
+use gl::types::*;
+
+use std::collections::HashMap;
+use std::ffi::CString;
+
+/// An active uniform of a linked program.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UniformInfo {
+	pub loc: GLint,
+	/// The GL type, such as `gl::FLOAT_VEC3`.
+	pub ty: GLenum,
+}
+
+/// Removes the `[0]` that GL puts at the end of the names of arrays.
+pub fn array_base_name(name: &str) -> &str {
+	if name.ends_with("[0]") {
+		&name[..name.len() - 3]
+	} else {
+		name
+	}
+}
+
+/// Gets every active uniform of a linked program, by name. Every element of an array is listed as `name[i]`,
+/// and the array itself as `name`, which is the same as its first element.
+pub fn get_uniforms(prog: GLuint) -> HashMap<String, UniformInfo> {
+	let mut uniforms = HashMap::new();
+	unsafe {
+		let mut count: GLint = 0;
+		gl::GetProgramiv(prog, gl::ACTIVE_UNIFORMS, &mut count);
+		let mut max_len: GLint = 0;
+		gl::GetProgramiv(prog, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_len);
+		
+		for i in 0..count as GLuint {
+			let mut buf: Vec<u8> = vec![0; max_len as usize + 1];
+			let (mut len, mut size, mut ty): (GLsizei, GLint, GLenum) = (0, 0, 0);
+			gl::GetActiveUniform(prog, i, buf.len() as GLsizei, &mut len, &mut size, &mut ty, buf.as_mut_ptr() as *mut GLchar);
+			buf.truncate(len as usize);
+			let full_name = String::from_utf8_lossy(&buf).into_owned();
+			let base = String::from(array_base_name(&full_name));
+			
+			let mut names = vec![base.clone()];
+			if size > 1 || base != full_name {
+				names.extend((0..size).map(|j| format!("{}[{}]", base, j)));
+			}
+			for name in names {
+				let loc = gl::GetUniformLocation(prog, CString::new(name.clone()).unwrap().as_ptr());
+				if loc != -1 {
+					uniforms.insert(name, UniformInfo { loc: loc, ty: ty });
+				}
+			}
+		}
+	}
+	uniforms
+}
+
+/// Gets the GLSL name of a GL type, for messages.
+pub fn type_name(ty: GLenum) -> String {
+	String::from(match ty {
+		gl::FLOAT => "float",
+		gl::FLOAT_VEC2 => "vec2",
+		gl::FLOAT_VEC3 => "vec3",
+		gl::FLOAT_VEC4 => "vec4",
+		gl::FLOAT_MAT3 => "mat3",
+		gl::FLOAT_MAT4 => "mat4",
+		gl::INT => "int",
+		gl::BOOL => "bool",
+		gl::SAMPLER_1D => "sampler1D",
+		gl::SAMPLER_2D => "sampler2D",
+		gl::SAMPLER_3D => "sampler3D",
+		gl::SAMPLER_CUBE => "samplerCube",
+		_ => return format!("type 0x{:X}", ty),
+	})
+}
+
+/// A value that a uniform can be set to.
+pub trait UniformValue {
+	/// Returns true if a uniform of GL type `ty` can be set to this type of value.
+	fn matches(ty: GLenum) -> bool;
+	/// The name of the type, for messages.
+	fn type_name() -> &'static str;
+	/// Sets the uniform at `loc` of the program that is in use.
+	unsafe fn set(&self, loc: GLint);
+}
+
+/// The texture unit that a sampler uniform reads from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Sampler(pub u32);
+
+impl UniformValue for f32 {
+	fn matches(ty: GLenum) -> bool { ty == gl::FLOAT }
+	fn type_name() -> &'static str { "float" }
+	unsafe fn set(&self, loc: GLint) {
+		gl::Uniform1f(loc, *self);
+	}
+}
+impl UniformValue for i32 {
+	fn matches(ty: GLenum) -> bool { ty == gl::INT || ty == gl::BOOL }
+	fn type_name() -> &'static str { "int" }
+	unsafe fn set(&self, loc: GLint) {
+		gl::Uniform1i(loc, *self);
+	}
+}
+impl UniformValue for bool {
+	fn matches(ty: GLenum) -> bool { ty == gl::BOOL }
+	fn type_name() -> &'static str { "bool" }
+	unsafe fn set(&self, loc: GLint) {
+		gl::Uniform1i(loc, *self as GLint);
+	}
+}
+impl UniformValue for Vec2 {
+	fn matches(ty: GLenum) -> bool { ty == gl::FLOAT_VEC2 }
+	fn type_name() -> &'static str { "vec2" }
+	unsafe fn set(&self, loc: GLint) {
+		gl::Uniform2f(loc, self.x, self.y);
+	}
+}
+impl UniformValue for Vec3 {
+	fn matches(ty: GLenum) -> bool { ty == gl::FLOAT_VEC3 }
+	fn type_name() -> &'static str { "vec3" }
+	unsafe fn set(&self, loc: GLint) {
+		gl::Uniform3f(loc, self.x, self.y, self.z);
+	}
+}
+impl UniformValue for Vec4 {
+	fn matches(ty: GLenum) -> bool { ty == gl::FLOAT_VEC4 }
+	fn type_name() -> &'static str { "vec4" }
+	unsafe fn set(&self, loc: GLint) {
+		gl::Uniform4f(loc, self.x, self.y, self.z, self.w);
+	}
+}
+impl UniformValue for Mat3 {
+	fn matches(ty: GLenum) -> bool { ty == gl::FLOAT_MAT3 }
+	fn type_name() -> &'static str { "mat3" }
+	unsafe fn set(&self, loc: GLint) {
+		gl::UniformMatrix3fv(loc, 1, gl::FALSE, self.as_ref().as_ptr() as *const GLfloat);
+	}
+}
+impl UniformValue for Mat4 {
+	fn matches(ty: GLenum) -> bool { ty == gl::FLOAT_MAT4 }
+	fn type_name() -> &'static str { "mat4" }
+	unsafe fn set(&self, loc: GLint) {
+		gl::UniformMatrix4fv(loc, 1, gl::FALSE, self.as_ref().as_ptr() as *const GLfloat);
+	}
+}
+impl UniformValue for Sampler {
+	fn matches(ty: GLenum) -> bool {
+		ty == gl::SAMPLER_1D || ty == gl::SAMPLER_2D || ty == gl::SAMPLER_3D || ty == gl::SAMPLER_CUBE
+	}
+	fn type_name() -> &'static str { "sampler" }
+	unsafe fn set(&self, loc: GLint) {
+		gl::Uniform1i(loc, self.0 as GLint);
+	}
+}
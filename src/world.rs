@@ -1,10 +1,18 @@
 use prelude::*;
 
-use entity::{Entity, Camera, Portal};
-use sdl2::keyboard::{KeyboardState, Keycode, Mod};
-use render::Render;
+use entity::{Entity, EntityType, Camera, Portal};
+use player::Player;
+use portal_gun::{self, PortalColor};
+use render::PortalFallback;
+use draw_list::{Backend, DrawCommand, DrawList, DrawSettings, Pass, Shading};
+use light::Lighting;
+use actions::{Action, ActionInput};
+use level;
+use physics;
 
-use gl;
+use nc::ray::{Ray, RayCast, RayIntersection};
+
+use std::path::Path;
 
 #[derive(Clone)]
 pub struct World {
@@ -12,6 +20,10 @@ pub struct World {
 	pub camera: Camera,
 	/// All of the entities in the world.
 	pub entities: Vec<Entity>,
+	/// The player's body, which moves the camera.
+	pub player: Player,
+	/// The lights that the world is drawn with.
+	pub lighting: Lighting,
 	portals: Option<(Portal, Portal)>,
 }
 
@@ -20,181 +32,469 @@ impl World {
 		World {
 			camera: cam,
 			entities: Vec::new(),
+			player: Player::new(),
+			lighting: Lighting::default(),
 			portals: None,
 		}
 	}
 	
+	/// Loads a world from a level file. See the `level` module for the format.
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<World, String> {
+		level::load(path)
+	}
+	
+	/// Saves the world to a level file.
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+		level::save(self, path)
+	}
+	
 	pub fn set_portals(&mut self, p1: Portal, p2: Portal) {
 		self.portals = Some((p1, p2));
 	}
 	pub fn get_portals(&self) -> Option<(Portal, Portal)> {
-		self.portals
+		self.portals.clone()
 	}
 	
-	pub fn tick(&mut self, dt: DT, state: &KeyboardState) {
-		for ent in self.entities.iter_mut() {
-			ent.tick(dt);
-		}
+	pub fn tick<A: ActionInput>(&mut self, dt: DT, state: &A) {
+		physics::step(&mut self.entities, &self.portals, dt);
+		self.camera.tick(dt);
 		
-		let speed = if state.is_scancode_pressed(Scan::LShift) || state.is_scancode_pressed(Scan::RShift) {2.0}
-		            else if state.is_scancode_pressed(Scan::LCtrl) || state.is_scancode_pressed(Scan::RCtrl) {0.1}
+		let speed = if state.is_action_held(Action::Sprint) {2.0}
+		            else if state.is_action_held(Action::Crouch) {0.1}
 		            else {0.5};
-		let dp = speed * dt;
 		let rot = Rot3::new(Vec3::new(0.0, -self.camera.get_xrot(), 0.0));
-		let mut mov = Vec3::new(0.0, 0.0, 0.0);
-		if state.is_scancode_pressed(Scan::W) {
-			mov = mov + rot.rotate(&Vec3::new(0.0, 0.0,  dp));
-		}
-		if state.is_scancode_pressed(Scan::S) {
-			mov = mov + rot.rotate(&Vec3::new(0.0, 0.0, -dp));
+		let mut vel = Vec3::new(0.0, 0.0, 0.0);
+		if state.is_action_held(Action::MoveForward) {
+			vel = vel + rot.rotate(&Vec3::new(0.0, 0.0,  speed));
 		}
-		if state.is_scancode_pressed(Scan::A) {
-			mov = mov + rot.rotate(&Vec3::new( dp, 0.0, 0.0));
+		if state.is_action_held(Action::MoveBack) {
+			vel = vel + rot.rotate(&Vec3::new(0.0, 0.0, -speed));
 		}
-		if state.is_scancode_pressed(Scan::D) {
-			mov = mov + rot.rotate(&Vec3::new(-dp, 0.0, 0.0));
+		if state.is_action_held(Action::MoveLeft) {
+			vel = vel + rot.rotate(&Vec3::new( speed, 0.0, 0.0));
 		}
-		if state.is_scancode_pressed(Scan::Q) {
-			mov = mov + rot.rotate(&Vec3::new(0.0,  dp, 0.0));
+		if state.is_action_held(Action::MoveRight) {
+			vel = vel + rot.rotate(&Vec3::new(-speed, 0.0, 0.0));
 		}
-		if state.is_scancode_pressed(Scan::E) {
-			mov = mov + rot.rotate(&Vec3::new(0.0, -dp, 0.0));
-		}
-		if mov != Vec3::new(0.0, 0.0, 0.0) {
-			self.camera.translate(mov, &self.portals.clone());
+		let axis = state.get_move_axis();
+		vel = vel + rot.rotate(&Vec3::new(-axis.x * speed, 0.0, axis.y * speed));
+		let portals = self.portals.clone();
+		if self.player.is_noclip() {
+			// Fly freely through everything
+			if state.is_action_held(Action::MoveUp) {
+				vel = vel + rot.rotate(&Vec3::new(0.0,  speed, 0.0));
+			}
+			if state.is_action_held(Action::MoveDown) {
+				vel = vel + rot.rotate(&Vec3::new(0.0, -speed, 0.0));
+			}
+			let mov = vel * dt;
+			if mov != Vec3::new(0.0, 0.0, 0.0) {
+				self.camera.translate(mov, &portals);
+			}
+		} else {
+			let jump = state.is_action_held(Action::Jump);
+			self.player.walk(&mut self.camera, vel, jump, dt, &mut self.entities, &portals);
 		}
 		
 		let rot_speed = speed;
 		let drot = rot_speed * dt;
 		let (mut rot_x, mut rot_y) = (0.0, 0.0);
-		if state.is_scancode_pressed(Scan::I) {
+		if state.is_action_held(Action::RotatePortalUp) {
 			rot_y += drot;
 		}
-		if state.is_scancode_pressed(Scan::K) {
+		if state.is_action_held(Action::RotatePortalDown) {
 			rot_y -= drot;
 		}
-		if state.is_scancode_pressed(Scan::J) {
+		if state.is_action_held(Action::RotatePortalLeft) {
 			rot_x += drot;
 		}
-		if state.is_scancode_pressed(Scan::L) {
+		if state.is_action_held(Action::RotatePortalRight) {
 			rot_x -= drot;
 		}
-		let rot = Rot3::new_with_euler_angles(rot_y, 0.0, 0.0) * Rot3::new_with_euler_angles(0.0, rot_x, 0.0);
-		if let Some((ref mut p1, ref mut p2)) = self.portals {
+		self.rotate_exit_portal(rot_x, rot_y);
+	}
+	
+	/// Turns the second portal by `x` radians to the left and `y` radians up, as the portal rotation controls do.
+	pub fn rotate_exit_portal(&mut self, x: f32, y: f32) {
+		let rot = Rot3::new_with_euler_angles(y, 0.0, 0.0) * Rot3::new_with_euler_angles(0.0, x, 0.0);
+		if let Some((_, ref mut p2)) = self.portals {
 			p2.rot = p2.rot * rot;
+			if x != 0.0 || y != 0.0 {
+				physics::wake_near_portal(&mut self.entities, p2);
+			}
 		}
 	}
 	
-	pub fn render(&self, ren: &mut Render) {
-		ren.set_camera(&self.camera);
+	/// Returns the world `alpha` of the way between `self` and `next`, where `next` is this world one tick later.
+	pub fn interpolate(&self, next: &World, alpha: f32) -> World {
+		let mut ret = next.clone();
+		ret.camera = self.camera.interpolate(&next.camera, alpha);
+		if self.entities.len() == next.entities.len() {
+			for (ent, prev) in ret.entities.iter_mut().zip(self.entities.iter()) {
+				// Don't smear entities that went through a portal across the world
+				if ent.get_transits() == prev.get_transits() {
+					ent.pos = prev.pos + (ent.pos - prev.pos) * alpha;
+					let delta = prev.get_rot().rotation_to(&ent.get_rot());
+					let rot = Rot3::new(delta.rotation() * alpha) * prev.get_rot();
+					ent.set_rot(rot);
+				}
+			}
+		}
+		if let (&Some((ref p1, ref p2)), &Some((ref n1, ref n2))) = (&self.portals, &next.portals) {
+			ret.portals = Some((p1.interpolate(&n1, alpha), p2.interpolate(&n2, alpha)));
+		}
+		ret
+	}
+	
+	/// Draws the world with a backend, such as `Render`.
+	pub fn render<B: Backend>(&self, ren: &mut B) {
+		let list = self.build_draw_list(&ren.get_draw_settings());
+		ren.execute(&list);
+	}
+	
+	/// Makes the list of what to draw to show the world from its camera.
+	pub fn build_draw_list(&self, settings: &DrawSettings) -> DrawList {
+		let mut list = DrawList::new();
+		list.push(DrawCommand::SetLighting(self.lighting.clone()));
+		list.set_camera(&self.camera);
 		
 		match self.portals {
-			Some((p1, p2)) => {
-				if ren.should_render_portals() {
-					let mut p1_transformed_cam = self.camera.clone();
-					p1_transformed_cam.transform_through_portal(&p1, &p2);
-					let mut p2_transformed_cam = self.camera.clone();
-					p2_transformed_cam.transform_through_portal(&p2, &p1);
-					
-					unsafe {
-						gl::Enable(gl::STENCIL_TEST);
-						
-						// 2. Draw portal 1 in the depth buffer
-						gl::StencilMask(0x00);
-						gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
-						gl::DepthMask(gl::TRUE);
-						p1.render(ren);
-						// 3. Draw portal 2 in the stencil buffer with 2s
-						gl::StencilMask(0xFF);
-						gl::StencilFunc(gl::ALWAYS, 2, 0xFF);
-						gl::StencilOp(gl::KEEP, gl::KEEP, gl::REPLACE);
-						gl::DepthMask(gl::FALSE);
-						p2.render(ren);
-						
-						// 4. Clear the depth buffer
-						gl::StencilMask(0x00);
-						gl::DepthMask(gl::TRUE);
-						gl::Clear(gl::DEPTH_BUFFER_BIT);
-						
-						// 5. Draw portal 2 in the depth buffer
-						gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
-						gl::DepthMask(gl::TRUE);
-						p2.render(ren);
-						// 6. Draw portal 1 in the stencil buffer with 1s
-						gl::StencilMask(0xFF);
-						gl::StencilFunc(gl::ALWAYS, 1, 0xFF);
-						gl::DepthMask(gl::FALSE);
-						p1.render(ren);
-						
-						// 7. Clear the Depth Buffer
-						gl::StencilMask(0x00);
-						gl::DepthMask(gl::TRUE);
-						gl::Clear(gl::DEPTH_BUFFER_BIT);
-						
-						//  8. Draw scene through portal 1 in the 1s
-						gl::StencilFunc(gl::EQUAL, 0x1, 0xFF);
-						gl::StencilOp(gl::KEEP, gl::KEEP, gl::KEEP);
-						gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
-						gl::DepthMask(gl::TRUE);
-						self.render_from_camera(ren, &p1_transformed_cam);
-						
-						//  9 Draw scene through portal 2 in the 2s
-						gl::StencilFunc(gl::EQUAL, 0x2, 0xFF);
-						self.render_from_camera(ren, &p2_transformed_cam);
-						
-						// 10. Draw portal 1 in the depth buffer to protect portal 1
-						gl::StencilFunc(gl::ALWAYS, 0x00, 0xFF);
-						gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
-						p1.render(ren);
-						
-						// 11. Draw portal 2 in the depth buffer to protect portal 2
-						p2.render(ren);
-						
-						// 12. Draw main scene
-						gl::Disable(gl::STENCIL_TEST);
-						gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
-					}
+			Some((ref p1, ref p2)) => {
+				if settings.render_portals {
+					self.draw_through_portals(&mut list, settings, &self.camera, p1, p2, 0);
+					list.begin_pass(Pass::Normal);
+					list.set_camera(&self.camera);
+					return list;
 				}
-				if ren.is_wireframe() && !ren.should_render_portals() {
-					p1.render(ren);
-					p2.render(ren);
+				if settings.wireframe {
+					p1.draw(&mut list, Shading::Lit);
+					p2.draw(&mut list, Shading::Lit);
 				}
 			},
 			_ => {},
 		}
 		
-		self.render_from_camera(ren, &self.camera);
+		self.draw_from_camera(&mut list, &self.camera);
+		list
 	}
-	fn render_from_camera(&self, ren: &mut Render, cam: &Camera) {
-		ren.set_camera(cam);
+	
+	// Draws the world from `cam` into the area of the stencil buffer marked with `level`.
+	// The view through each portal is drawn first, recursing until the portal depth is reached.
+	fn draw_through_portals(&self, list: &mut DrawList, settings: &DrawSettings, cam: &Camera, p1: &Portal, p2: &Portal, level: u32) {
+		if level >= settings.portal_depth {
+			// Deepest level - draw the scene, and fill the portals with the fallback
+			list.begin_pass(Pass::Level { level: level, clear_depth: true });
+			self.draw_from_camera(list, cam);
+			let shading = match settings.portal_fallback {
+				PortalFallback::Color(c) => Shading::Color(c),
+				PortalFallback::LastFrame => Shading::LastFrame,
+				PortalFallback::Nothing => return,
+			};
+			p1.draw(list, shading);
+			p2.draw(list, shading);
+			return;
+		}
+		
+		for &(p_in, p_out) in [(p1, p2), (p2, p1)].iter() {
+			// 1. Mark the area seen through p_in with level + 1
+			list.set_camera(cam);
+			list.begin_pass(Pass::StencilPortal { level: level });
+			p_in.draw(list, Shading::Lit);
+			
+			// 2. Draw the scene seen through p_in in that area
+			let mut portal_cam = cam.clone();
+			portal_cam.transform_through_portal(p_in, p_out);
+			self.draw_through_portals(list, settings, &portal_cam, p1, p2, level + 1);
+			
+			// 3. Set the area back to level, so that the other portal can use level + 1
+			list.set_camera(cam);
+			list.begin_pass(Pass::UnstencilPortal { level: level });
+			p_in.draw(list, Shading::Lit);
+		}
+		
+		// 4. Draw the portals in the depth buffer to protect the views through them
+		list.set_camera(cam);
+		list.begin_pass(Pass::ProtectPortals { level: level });
+		p1.draw(list, Shading::Lit);
+		p2.draw(list, Shading::Lit);
+		
+		// 5. Draw the scene at this level
+		list.begin_pass(Pass::Level { level: level, clear_depth: false });
+		self.draw_from_camera(list, cam);
+	}
+	fn draw_from_camera(&self, list: &mut DrawList, cam: &Camera) {
+		list.set_camera(cam);
 		
 		for ent in self.entities.iter() {
-			ent.render(ren);
+			ent.draw(list);
 		}
 		match self.portals {
-			Some((p1, p2)) => {
-				p1.render_outline(ren);
-				p2.render_outline(ren);
+			Some((ref p1, ref p2)) => {
+				p1.draw_outline(list);
+				p2.draw_outline(list);
 			},
 			None => {}
 		}
-		
-		ren.set_camera(&self.camera);
 	}
 	
-	pub fn handle_keydown(&mut self, key: &Keycode, keymod: &Mod, repeat: bool) {
-		let (_, _, _) = (key, keymod, repeat);
+	/// Performs an action that happens once when its key or button is pressed.
+	pub fn handle_action(&mut self, action: Action) {
+		match action {
+			Action::ToggleNoclip => self.player.toggle_noclip(),
+			Action::FireBluePortal => self.fire_portal(PortalColor::Blue),
+			Action::FireOrangePortal => self.fire_portal(PortalColor::Orange),
+			_ => {},
+		}
 	}
 	
-	pub fn handle_keyup(&mut self, key: &Keycode, keymod: &Mod) {
-		let (_, _) = (key, keymod);
+	/// Shoots a portal from the camera, and moves it to where it hits if it fits there.
+	pub fn fire_portal(&mut self, color: PortalColor) {
+		let (p1, p2) = match self.portals {
+			Some(ref ps) => ps.clone(),
+			None => {
+				println!("can't fire portal: there are no portals in the world");
+				return;
+			},
+		};
+		let (portal, other) = match color {
+			PortalColor::Blue => (p1, p2),
+			PortalColor::Orange => (p2, p1),
+		};
+		match portal_gun::fire(self, &self.camera, &portal, &other) {
+			Ok((pos, rot)) => {
+				if let Some((ref mut p1, ref mut p2)) = self.portals {
+					let p = match color {
+						PortalColor::Blue => p1,
+						PortalColor::Orange => p2,
+					};
+					// Bodies that were through the portal, or now are, have to notice
+					physics::wake_near_portal(&mut self.entities, p);
+					p.pos = pos;
+					p.rot = rot;
+					physics::wake_near_portal(&mut self.entities, p);
+				}
+			},
+			Err(e) => println!("can't place {:?} portal: {}", color, e),
+		}
+	}
+	
+	/// Casts a ray against the static geometry of the world, returning the closest hit within `max_toi`.
+	/// The normal of the hit faces back along the ray.
+	pub fn cast_ray(&self, ray: &Ray<Pnt3>, max_toi: f32) -> Option<RayIntersection<Vec3>> {
+		let mut closest: Option<RayIntersection<Vec3>> = None;
+		for ent in self.entities.iter() {
+			if ent.get_type() != EntityType::Static {
+				continue;
+			}
+			let shape = match ent.get_collision_mesh() {
+				Some(shape) => shape,
+				None => continue,
+			};
+			if let Some(mut hit) = shape.toi_and_normal_with_ray(&ent.get_iso(), ray, true) {
+				if hit.toi > max_toi || closest.as_ref().map_or(false, |c| c.toi <= hit.toi) {
+					continue;
+				}
+				if hit.normal.dot(&ray.dir) > 0.0 {
+					hit.normal = -hit.normal;
+				}
+				closest = Some(hit);
+			}
+		}
+		closest
 	}
 	
 	pub fn handle_mouse_motion(&mut self, x: f32, y: f32) {
 		self.camera.rotate(x as f32 * 0.1, y as f32 * 0.1);
 	}
 	
+	/// Prints the full state of the world, one object per line.
+	pub fn dump(&self) {
+		println!("camera: pos: {:?}, xrot: {:.4}, yrot: {:.4}, tilt: {:.4}", self.camera.get_pos(), self.camera.get_xrot(), self.camera.get_yrot(), self.camera.get_tilt_angle());
+		println!("player: vel: {:?}, on_ground: {}, noclip: {}", self.player.vel, self.player.is_on_ground(), self.player.is_noclip());
+		for (i, ent) in self.entities.iter().enumerate() {
+			match ent.get_body() {
+				Some(body) => println!("entity {}: pos: {:?}, vel: {:?}, rot: {:?}, ang_vel: {:?}, sleeping: {}", i, ent.pos, ent.vel, ent.get_rot().rotation(), body.ang_vel, body.is_sleeping()),
+				None => println!("entity {}: pos: {:?}, vel: {:?}", i, ent.pos, ent.vel),
+			}
+		}
+		if let Some((ref p1, ref p2)) = self.portals {
+			println!("portal 1: pos: {:?}, normal: {:?}", p1.pos, p1.get_normal());
+			println!("portal 2: pos: {:?}, normal: {:?}", p2.pos, p2.get_normal());
+		}
+	}
+	
 	pub fn print(&self) {
 		print!("x:{:.4}, y:{:.4}, z:{:.4}, xrot:{:.4}, yrot:{:.4}", self.camera.get_pos().x, self.camera.get_pos().y, self.camera.get_pos().z, self.camera.get_xrot(), self.camera.get_yrot());
+		if self.player.is_noclip() {
+			print!(", NOCLIP");
+		} else if self.player.is_on_ground() {
+			print!(", ON_GROUND");
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use prelude::*;
+	use draw_list::{DrawCommand, DrawSettings, Pass, Shading};
+	use render::PortalFallback;
+	use actions::{Action, HeldActions};
+	use portal_gun::{PortalColor, SURFACE_OFFSET};
+	use level;
+	use std::path::Path;
+	
+	const SCENE: &'static str = "
+camera 0 1 0
+static 0 0 5  rectangle 4 3  1 1 1
+portal  0.0 1 4.0  0 0 0  0.9 1.4
+portal -1.5 1 2.5  0 3.14159 0  0.9 1.4
+";
+	/// The rectangle and the outlines of the two portals.
+	const SCENE_DRAWS: usize = 3;
+	
+	fn fallback() -> Vec4 {
+		Vec4::new(1.0, 0.0, 1.0, 1.0)
+	}
+	
+	fn build(depth: u32, fallback: PortalFallback, render_portals: bool) -> DrawList {
+		let world = level::parse(SCENE, Path::new(".")).unwrap();
+		world.build_draw_list(&DrawSettings {
+			render_portals: render_portals,
+			wireframe: false,
+			portal_depth: depth,
+			portal_fallback: fallback,
+		})
+	}
+	
+	/// Counts the meshes drawn in the colour of the fallback.
+	fn count_fallback_draws(list: &DrawList) -> usize {
+		list.commands.iter().filter(|c| match **c {
+			DrawCommand::Draw { shading: Shading::Color(c), .. } => c == fallback(),
+			DrawCommand::Draw { shading: Shading::LastFrame, .. } => true,
+			_ => false,
+		}).count()
+	}
+	
+	#[test]
+	fn passes_without_recursion() {
+		let list = build(0, PortalFallback::Color(fallback()), true);
+		assert_eq!(list.get_passes(), vec![
+			Pass::Level { level: 0, clear_depth: true },
+			Pass::Normal,
+		]);
+		assert_eq!(count_fallback_draws(&list), 2);
+		assert_eq!(list.get_draw_count(), SCENE_DRAWS + 2);
+	}
+	
+	#[test]
+	fn passes_through_one_level() {
+		let list = build(1, PortalFallback::Color(fallback()), true);
+		assert_eq!(list.get_passes(), vec![
+			Pass::StencilPortal { level: 0 },
+			Pass::Level { level: 1, clear_depth: true },
+			Pass::UnstencilPortal { level: 0 },
+			Pass::StencilPortal { level: 0 },
+			Pass::Level { level: 1, clear_depth: true },
+			Pass::UnstencilPortal { level: 0 },
+			Pass::ProtectPortals { level: 0 },
+			Pass::Level { level: 0, clear_depth: false },
+			Pass::Normal,
+		]);
+		// Both portals are filled in at level 1 through each of the two portals
+		assert_eq!(count_fallback_draws(&list), 4);
+	}
+	
+	#[test]
+	fn passes_through_two_levels() {
+		let list = build(2, PortalFallback::Color(fallback()), true);
+		let inner = vec![
+			Pass::StencilPortal { level: 1 },
+			Pass::Level { level: 2, clear_depth: true },
+			Pass::UnstencilPortal { level: 1 },
+			Pass::StencilPortal { level: 1 },
+			Pass::Level { level: 2, clear_depth: true },
+			Pass::UnstencilPortal { level: 1 },
+			Pass::ProtectPortals { level: 1 },
+			Pass::Level { level: 1, clear_depth: false },
+		];
+		let mut expected = vec![Pass::StencilPortal { level: 0 }];
+		expected.extend(inner.iter().cloned());
+		expected.push(Pass::UnstencilPortal { level: 0 });
+		expected.push(Pass::StencilPortal { level: 0 });
+		expected.extend(inner.iter().cloned());
+		expected.extend(vec![
+			Pass::UnstencilPortal { level: 0 },
+			Pass::ProtectPortals { level: 0 },
+			Pass::Level { level: 0, clear_depth: false },
+			Pass::Normal,
+		]);
+		assert_eq!(list.get_passes(), expected);
+		assert_eq!(count_fallback_draws(&list), 8);
+	}
+	
+	#[test]
+	fn no_fallback_draws_nothing_in_portals() {
+		for depth in 0..3 {
+			let with = build(depth, PortalFallback::Color(fallback()), true);
+			let without = build(depth, PortalFallback::Nothing, true);
+			assert_eq!(count_fallback_draws(&without), 0);
+			assert_eq!(with.get_passes(), without.get_passes());
+			assert_eq!(with.get_draw_count() - without.get_draw_count(), count_fallback_draws(&with));
+		}
+	}
+	
+	#[test]
+	fn no_stencil_passes_without_portal_rendering() {
+		let list = build(2, PortalFallback::Color(fallback()), false);
+		assert!(list.get_passes().is_empty());
+		assert_eq!(list.get_draw_count(), SCENE_DRAWS);
+		assert_eq!(count_fallback_draws(&list), 0);
+	}
+	
+	/// A floor between two walls, facing each other across it.
+	const CORRIDOR: &'static str = "
+camera 0 1 0
+static 0 0 0  planes 1 1 10 10  1 1 1  1 1 1
+static 0 1.5  5  rectangle 4 3  1 1 1
+static 0 1.5 -5  rectangle 4 3  1 1 1
+portal -3 1 -3  0 0 0  0.9 1.4
+portal  3 1 -3  0 0 0  0.9 1.4
+";
+	
+	#[test]
+	fn player_walks_through_fired_portals() {
+		let mut world = level::parse(CORRIDOR, Path::new(".")).unwrap();
+		// Low enough on the walls that the player's feet fit through
+		world.camera.look_at(Vec3::new(0.0, 0.75, 5.0));
+		world.fire_portal(PortalColor::Blue);
+		world.camera.look_at(Vec3::new(0.0, 0.75, -5.0));
+		world.fire_portal(PortalColor::Orange);
+		world.camera.look_at(Vec3::new(0.0, 1.0, 5.0));
+		let (blue, orange) = world.get_portals().unwrap();
+		assert!((blue.pos - Vec3::new(0.0, 0.75, 5.0 - SURFACE_OFFSET)).norm() < 0.001, "blue portal at {:?}", blue.pos);
+		assert!((orange.pos - Vec3::new(0.0, 0.75, -5.0 + SURFACE_OFFSET)).norm() < 0.001, "orange portal at {:?}", orange.pos);
+		
+		let mut input = HeldActions::new();
+		input.press(Action::MoveForward);
+		input.press(Action::Sprint);
+		let dt = 1.0 / 60.0;
+		let mut ticks = 0;
+		while world.camera.get_transits() == 0 {
+			assert!(ticks < 300, "stopped at {:?} instead of going into the blue portal", world.camera.get_pos());
+			world.tick(dt, &input);
+			ticks += 1;
+		}
+		let pos = world.camera.get_pos();
+		assert!(orange.get_distance(pos) > 0.0 && orange.get_distance(pos) < 0.1, "came out at {:?}", pos);
+		
+		// The wall behind the orange portal doesn't push the player back in, and they carry on walking away from it
+		for _ in 0..30 {
+			world.tick(dt, &input);
+		}
+		assert_eq!(world.camera.get_transits(), 1);
+		let after = world.camera.get_pos();
+		assert!(after.z > pos.z + 0.5, "walked from {:?} to {:?}", pos, after);
+		assert!(after.y > 0.9 && after.y < 1.1, "walked from {:?} to {:?}", pos, after);
 	}
 }